@@ -0,0 +1,12 @@
+CREATE TABLE reservations (
+    id           UUID        PRIMARY KEY DEFAULT uuid_generate_v4(),
+    warehouse_id UUID        NOT NULL REFERENCES warehouses (id) ON DELETE CASCADE,
+    product_id   INTEGER     NOT NULL,
+    order_id     VARCHAR     NOT NULL,
+    quantity     INTEGER     NOT NULL CHECK (quantity > 0),
+    status       VARCHAR     NOT NULL DEFAULT 'held',
+    expires_at   TIMESTAMPTZ NOT NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX reservations_stock_idx ON reservations (warehouse_id, product_id) WHERE status = 'held';
@@ -0,0 +1,16 @@
+CREATE TABLE stock_movements (
+    id              UUID        PRIMARY KEY DEFAULT uuid_generate_v4(),
+    stock_id        UUID        NOT NULL,
+    warehouse_id    UUID        NOT NULL,
+    product_id      INTEGER     NOT NULL,
+    quantity_before INTEGER     NOT NULL,
+    quantity_after  INTEGER     NOT NULL,
+    delta           INTEGER     NOT NULL,
+    user_id         INTEGER,
+    reason          VARCHAR     NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX stock_movements_warehouse_idx ON stock_movements (warehouse_id, created_at);
+CREATE INDEX stock_movements_product_idx ON stock_movements (product_id, created_at);
+CREATE INDEX stock_movements_stock_idx ON stock_movements (stock_id, created_at);
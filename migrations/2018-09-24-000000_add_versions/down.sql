@@ -0,0 +1,5 @@
+DROP TRIGGER IF EXISTS increment_version ON stocks;
+DROP TRIGGER IF EXISTS increment_version ON warehouses;
+DROP FUNCTION IF EXISTS increment_version();
+ALTER TABLE stocks DROP COLUMN IF EXISTS version;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS version;
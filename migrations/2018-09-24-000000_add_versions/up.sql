@@ -0,0 +1,17 @@
+ALTER TABLE warehouses ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE stocks ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+-- Bumps `version` whenever the row is modified, so that writers can detect concurrent changes
+CREATE OR REPLACE FUNCTION increment_version() RETURNS trigger AS $$
+BEGIN
+    IF NEW IS DISTINCT FROM OLD THEN
+        NEW.version := OLD.version + 1;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER increment_version BEFORE UPDATE ON warehouses
+    FOR EACH ROW EXECUTE PROCEDURE increment_version();
+CREATE TRIGGER increment_version BEFORE UPDATE ON stocks
+    FOR EACH ROW EXECUTE PROCEDURE increment_version();
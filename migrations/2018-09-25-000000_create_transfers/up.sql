@@ -0,0 +1,20 @@
+CREATE TABLE transfers (
+    id                       UUID        PRIMARY KEY DEFAULT uuid_generate_v4(),
+    source_warehouse_id      UUID        NOT NULL REFERENCES warehouses (id) ON DELETE CASCADE,
+    destination_warehouse_id UUID        NOT NULL REFERENCES warehouses (id) ON DELETE CASCADE,
+    status                   VARCHAR     NOT NULL DEFAULT 'draft',
+    created_by               INTEGER,
+    created_at               TIMESTAMPTZ NOT NULL DEFAULT now(),
+    shipped_at               TIMESTAMPTZ,
+    received_at              TIMESTAMPTZ,
+    CHECK (source_warehouse_id <> destination_warehouse_id)
+);
+
+CREATE INDEX transfers_destination_status_idx ON transfers (destination_warehouse_id, status);
+
+CREATE TABLE transfer_lines (
+    transfer_id UUID    NOT NULL REFERENCES transfers (id) ON DELETE CASCADE,
+    product_id  INTEGER NOT NULL,
+    quantity    INTEGER NOT NULL CHECK (quantity > 0),
+    PRIMARY KEY (transfer_id, product_id)
+);
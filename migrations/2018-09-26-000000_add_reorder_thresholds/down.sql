@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS low_stock_events;
+
+ALTER TABLE stocks DROP COLUMN IF EXISTS reorder_threshold;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS default_reorder_threshold;
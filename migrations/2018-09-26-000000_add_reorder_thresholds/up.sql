@@ -0,0 +1,15 @@
+ALTER TABLE warehouses ADD COLUMN default_reorder_threshold INTEGER CHECK (default_reorder_threshold >= 0);
+ALTER TABLE stocks ADD COLUMN reorder_threshold INTEGER CHECK (reorder_threshold >= 0);
+
+CREATE TABLE low_stock_events (
+    id           UUID        PRIMARY KEY DEFAULT uuid_generate_v4(),
+    stock_id     UUID        NOT NULL REFERENCES stocks (id) ON DELETE CASCADE,
+    warehouse_id UUID        NOT NULL REFERENCES warehouses (id) ON DELETE CASCADE,
+    product_id   INTEGER     NOT NULL,
+    movement_id  UUID        NOT NULL,
+    quantity     INTEGER     NOT NULL,
+    threshold    INTEGER     NOT NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX low_stock_events_warehouse_idx ON low_stock_events (warehouse_id, created_at);
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS warehouses_store_active_idx;
+
+ALTER TABLE warehouses DROP CONSTRAINT IF EXISTS warehouses_archived_at_check;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS archived_at;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS is_archived;
@@ -0,0 +1,5 @@
+ALTER TABLE warehouses ADD COLUMN is_archived BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE warehouses ADD COLUMN archived_at TIMESTAMPTZ;
+ALTER TABLE warehouses ADD CONSTRAINT warehouses_archived_at_check CHECK (is_archived = (archived_at IS NOT NULL));
+
+CREATE INDEX warehouses_store_active_idx ON warehouses (store_id) WHERE NOT is_archived;
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS warehouse_status_changes;
+
+ALTER TABLE warehouses DROP CONSTRAINT IF EXISTS warehouses_status_check;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS status;
@@ -0,0 +1,13 @@
+ALTER TABLE warehouses ADD COLUMN status VARCHAR NOT NULL DEFAULT 'active';
+ALTER TABLE warehouses ADD CONSTRAINT warehouses_status_check CHECK (status IN ('active', 'paused', 'closed'));
+
+CREATE TABLE warehouse_status_changes (
+    id            UUID        PRIMARY KEY DEFAULT uuid_generate_v4(),
+    warehouse_id  UUID        NOT NULL REFERENCES warehouses (id) ON DELETE CASCADE,
+    status_before VARCHAR     NOT NULL,
+    status        VARCHAR     NOT NULL,
+    changed_by    INTEGER,
+    changed_at    TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX warehouse_status_changes_warehouse_idx ON warehouse_status_changes (warehouse_id, changed_at);
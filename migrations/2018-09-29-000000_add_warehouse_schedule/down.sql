@@ -0,0 +1,3 @@
+ALTER TABLE warehouses DROP COLUMN IF EXISTS holidays;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS opening_hours;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS time_zone;
@@ -0,0 +1,3 @@
+ALTER TABLE warehouses ADD COLUMN time_zone VARCHAR;
+ALTER TABLE warehouses ADD COLUMN opening_hours JSONB NOT NULL DEFAULT '[]';
+ALTER TABLE warehouses ADD COLUMN holidays DATE[] NOT NULL DEFAULT '{}';
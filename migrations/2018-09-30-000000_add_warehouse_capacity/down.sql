@@ -0,0 +1,2 @@
+ALTER TABLE warehouses DROP COLUMN IF EXISTS product_capacities;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS capacity;
@@ -0,0 +1,2 @@
+ALTER TABLE warehouses ADD COLUMN capacity INTEGER CHECK (capacity >= 0);
+ALTER TABLE warehouses ADD COLUMN product_capacities JSONB NOT NULL DEFAULT '[]';
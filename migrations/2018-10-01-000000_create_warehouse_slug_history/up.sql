@@ -0,0 +1,8 @@
+-- Former slugs keep resolving to their warehouse and cannot be taken by another one
+CREATE TABLE warehouse_slug_history (
+    slug         VARCHAR     PRIMARY KEY,
+    warehouse_id UUID        NOT NULL REFERENCES warehouses (id) ON DELETE CASCADE,
+    replaced_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX warehouse_slug_history_warehouse_idx ON warehouse_slug_history (warehouse_id);
@@ -0,0 +1,9 @@
+DROP INDEX IF EXISTS stocks_updated_at_idx;
+DROP TRIGGER IF EXISTS set_updated_at ON stocks;
+ALTER TABLE stocks DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE stocks DROP COLUMN IF EXISTS created_at;
+
+DROP INDEX IF EXISTS warehouses_updated_at_idx;
+DROP TRIGGER IF EXISTS set_updated_at ON warehouses;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE warehouses DROP COLUMN IF EXISTS created_at;
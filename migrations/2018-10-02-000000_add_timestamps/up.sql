@@ -0,0 +1,9 @@
+ALTER TABLE warehouses ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE warehouses ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+SELECT diesel_manage_updated_at('warehouses');
+CREATE INDEX warehouses_updated_at_idx ON warehouses (updated_at);
+
+ALTER TABLE stocks ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE stocks ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+SELECT diesel_manage_updated_at('stocks');
+CREATE INDEX stocks_updated_at_idx ON stocks (updated_at);
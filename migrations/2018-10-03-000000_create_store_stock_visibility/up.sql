@@ -0,0 +1,7 @@
+-- What readers who do not manage the stock of a store learn about its quantities
+CREATE TABLE store_stock_visibility (
+    store_id INTEGER PRIMARY KEY,
+    mode     VARCHAR NOT NULL CHECK (mode IN ('availability', 'capped')),
+    cap      INTEGER CHECK (cap > 0),
+    CHECK (mode <> 'capped' OR cap IS NOT NULL)
+);
@@ -0,0 +1,14 @@
+-- Credentials of store integrations, only the SHA-256 digest of the secret is kept
+CREATE TABLE api_keys (
+    id            UUID        PRIMARY KEY,
+    key_hash      VARCHAR     NOT NULL UNIQUE,
+    name          VARCHAR     NOT NULL,
+    store_id      INTEGER     NOT NULL,
+    warehouse_ids UUID[]      NOT NULL DEFAULT '{}',
+    scope         VARCHAR     NOT NULL CHECK (scope IN ('read', 'write')),
+    created_by    INTEGER     NOT NULL,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at    TIMESTAMPTZ
+);
+
+CREATE INDEX api_keys_store_idx ON api_keys (store_id);
@@ -0,0 +1,16 @@
+-- Who changed warehouses and roles, keeping the changed fields before and after
+CREATE TABLE audit_log (
+    id          UUID        PRIMARY KEY,
+    actor_id    INTEGER,
+    api_key_id  UUID,
+    entity_type VARCHAR     NOT NULL CHECK (entity_type IN ('warehouse', 'role')),
+    entity_id   VARCHAR     NOT NULL,
+    action      VARCHAR     NOT NULL CHECK (action IN ('create', 'update', 'delete')),
+    old_value   JSONB,
+    new_value   JSONB,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_log_entity_idx ON audit_log (entity_type, entity_id, created_at);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor_id, created_at);
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at);
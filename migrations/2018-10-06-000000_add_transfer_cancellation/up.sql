@@ -0,0 +1,2 @@
+-- Drafts can be cancelled before anything is shipped
+ALTER TABLE transfers ADD COLUMN cancelled_at TIMESTAMPTZ;
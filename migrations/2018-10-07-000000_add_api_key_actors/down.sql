@@ -0,0 +1,3 @@
+ALTER TABLE transfers DROP COLUMN IF EXISTS api_key_id;
+ALTER TABLE warehouse_status_changes DROP COLUMN IF EXISTS api_key_id;
+ALTER TABLE stock_movements DROP COLUMN IF EXISTS api_key_id;
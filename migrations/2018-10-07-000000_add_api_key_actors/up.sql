@@ -0,0 +1,4 @@
+-- Changes made with an API key are credited to the key instead of the user who created it
+ALTER TABLE stock_movements ADD COLUMN api_key_id UUID;
+ALTER TABLE warehouse_status_changes ADD COLUMN api_key_id UUID;
+ALTER TABLE transfers ADD COLUMN api_key_id UUID;
@@ -0,0 +1,114 @@
+use config::Cache as CacheConfig;
+use models::UserLogin;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use stq_api::warehouses::Warehouse;
+use stq_types::*;
+
+/// In-process map whose entries expire after the TTL.
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl<K, V> TtlCache<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().expect("Cache lock poisoned");
+        let value = match entries.get(key) {
+            Some((inserted_at, value)) if inserted_at.elapsed() < self.ttl => Some(value.clone()),
+            _ => None,
+        };
+
+        if value.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            entries.remove(key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.entries
+            .lock()
+            .expect("Cache lock poisoned")
+            .insert(key, (Instant::now(), value));
+    }
+
+    pub fn invalidate(&self, key: &K) {
+        self.entries
+            .lock()
+            .expect("Cache lock poisoned")
+            .remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().expect("Cache lock poisoned").clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().expect("Cache lock poisoned").len(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub entries: usize,
+}
+
+/// Caches shared by all requests served by the process.
+#[derive(Clone)]
+pub struct Caches {
+    /// Logins with roles by user.
+    pub logins: Arc<TtlCache<UserId, UserLogin>>,
+    /// Warehouses looked up by the stock and reservation ACLs, for their owning store.
+    pub warehouses: Arc<TtlCache<WarehouseId, Warehouse>>,
+}
+
+impl Caches {
+    pub fn new(config: &CacheConfig) -> Self {
+        let ttl = Duration::from_secs(config.ttl_seconds);
+        Self {
+            logins: Arc::new(TtlCache::new(ttl)),
+            warehouses: Arc::new(TtlCache::new(ttl)),
+        }
+    }
+
+    pub fn stats(&self) -> CachesStats {
+        CachesStats {
+            logins: self.logins.stats(),
+            warehouses: self.warehouses.stats(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachesStats {
+    pub logins: CacheStats,
+    pub warehouses: CacheStats,
+}
@@ -3,6 +3,7 @@ use std::net::IpAddr;
 use stq_logging::GrayLogConfig;
 
 use config_crate::{Config as RawConfig, ConfigError, Environment, File};
+use jsonwebtoken::Algorithm;
 
 use sentry_integration::SentryConfig;
 
@@ -18,6 +19,61 @@ pub struct Database {
     pub dsn: String,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Auth {
+    /// Algorithm the gateway signs bearer tokens with, e.g. `HS256` or `RS256`
+    #[serde(default = "default_token_algorithm")]
+    pub algorithm: Algorithm,
+    /// Shared secret for HMAC algorithms
+    pub secret: Option<String>,
+    /// Path to the DER-encoded public key for RSA algorithms
+    pub public_key_path: Option<String>,
+    /// Expected `iss` claim, not checked if unset
+    pub issuer: Option<String>,
+    /// Clock skew tolerated when checking expiration
+    #[serde(default)]
+    pub leeway_seconds: i64,
+    /// Accept a bare user id in `Authorization` while clients migrate to tokens
+    #[serde(default)]
+    pub allow_legacy_user_id: bool,
+}
+
+fn default_token_algorithm() -> Algorithm {
+    Algorithm::HS256
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Self {
+            algorithm: default_token_algorithm(),
+            secret: None,
+            public_key_path: None,
+            issuer: None,
+            leeway_seconds: 0,
+            allow_legacy_user_id: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cache {
+    /// Seconds cached logins and warehouse owners stay valid
+    pub ttl_seconds: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self { ttl_seconds: 30 }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Maintenance {
+    /// Allows wiping all warehouses at once, for test environments only
+    #[serde(default)]
+    pub enabled: bool,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
     /// Server listen address
@@ -28,6 +84,15 @@ pub struct Config {
     pub graylog: Option<GrayLogConfig>,
     /// Sentry settings
     pub sentry: Option<SentryConfig>,
+    /// Bearer token settings
+    #[serde(default)]
+    pub auth: Auth,
+    /// Login and warehouse owner cache settings
+    #[serde(default)]
+    pub cache: Cache,
+    /// Maintenance mode settings
+    #[serde(default)]
+    pub maintenance: Maintenance,
 }
 
 static ENV_PREFIX: &'static str = "STQ_WAREHOUSES";
@@ -0,0 +1,126 @@
+use config::Auth;
+use errors::*;
+
+use failure::{self, ResultExt};
+use hyper::{self, Headers};
+use jsonwebtoken::{self, Algorithm, Validation};
+use std::fs::File;
+use std::io::Read;
+use stq_types::*;
+
+const BEARER_PREFIX: &str = "Bearer ";
+const API_KEY_PREFIX: &str = "ApiKey ";
+
+/// Identity the caller claims in the `Authorization` header.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Credentials {
+    /// Verified or, in the legacy mode, trusted user id, `None` for anonymous callers.
+    User(Option<UserId>),
+    /// Secret of an API key, still to be looked up.
+    ApiKey(String),
+}
+
+/// Claims of the bearer token issued to the caller by the gateway.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    pub user_id: UserId,
+    /// Expiration time as a Unix timestamp.
+    pub exp: i64,
+    pub iss: Option<String>,
+}
+
+/// Verifies bearer tokens signed with the key from the configuration.
+#[derive(Clone)]
+pub struct Authenticator {
+    /// HMAC secret or DER-encoded RSA public key, `None` if tokens are not accepted.
+    key: Option<Vec<u8>>,
+    validation: Validation,
+    allow_legacy_user_id: bool,
+}
+
+impl Authenticator {
+    pub fn new(config: &Auth) -> Result<Self, failure::Error> {
+        let key = match config.algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                config.secret.clone().map(String::into_bytes)
+            }
+            _ => match config.public_key_path {
+                Some(ref path) => {
+                    let mut key = vec![];
+                    File::open(path)
+                        .and_then(|mut file| file.read_to_end(&mut key))
+                        .context(format!("Failed to read public key from {}", path))?;
+                    Some(key)
+                }
+                None => None,
+            },
+        };
+
+        if key.is_none() && !config.allow_legacy_user_id {
+            return Err(format_err!(
+                "No secret or public key is configured for {:?} tokens, so no caller could authenticate",
+                config.algorithm
+            ));
+        }
+
+        let mut validation = Validation::new(config.algorithm);
+        validation.leeway = config.leeway_seconds;
+        validation.iss = config.issuer.clone();
+
+        Ok(Self {
+            key,
+            validation,
+            allow_legacy_user_id: config.allow_legacy_user_id,
+        })
+    }
+
+    /// Checks the signature and expiration of the token and returns its claims.
+    pub fn verify(&self, token: &str) -> Result<TokenClaims, failure::Error> {
+        let key = self.key.as_ref().ok_or_else(|| {
+            format_err!("No key is configured to verify tokens").context(Error::Unauthorized)
+        })?;
+
+        Ok(
+            jsonwebtoken::decode::<TokenClaims>(token, key, &self.validation)
+                .map_err(|e| format_err!("{}", e))
+                .context("Invalid bearer token")
+                .context(Error::Unauthorized)?
+                .claims,
+        )
+    }
+
+    /// Reads the caller from the `Authorization` header, e.g. `Authorization: Bearer <token>`
+    /// or `Authorization: ApiKey <key>`. A bare user id is accepted only in the legacy compatibility mode.
+    pub fn authenticate(&self, headers: &Headers) -> Result<Credentials, failure::Error> {
+        let auth = match headers.get::<hyper::header::Authorization<String>>() {
+            Some(auth) => auth.0.clone(),
+            None => return Ok(Credentials::User(None)),
+        };
+
+        if auth.starts_with(BEARER_PREFIX) {
+            let claims = self.verify(auth[BEARER_PREFIX.len()..].trim())?;
+            return Ok(Credentials::User(Some(claims.user_id)));
+        }
+
+        if auth.starts_with(API_KEY_PREFIX) {
+            return Ok(Credentials::ApiKey(
+                auth[API_KEY_PREFIX.len()..].trim().to_string(),
+            ));
+        }
+
+        if !self.allow_legacy_user_id {
+            return Err(
+                format_err!("Authorization header must carry a bearer token")
+                    .context(Error::Unauthorized)
+                    .into(),
+            );
+        }
+
+        Ok(auth
+            .parse()
+            .map(|v| Credentials::User(Some(UserId(v))))
+            .map_err(failure::Error::from)
+            .context(format!("Failed to parse user ID: {}", auth))
+            .context(Error::UserIdParse)?)
+    }
+}
@@ -1,3 +1,9 @@
+pub mod auth;
+pub mod routes;
+
+use self::auth::*;
+use self::routes::*;
+use cache::Caches;
 use config::*;
 use errors::*;
 use models::*;
@@ -5,9 +11,12 @@ use sentry_integration::log_and_capture_error;
 use services::*;
 use types::*;
 
+use chrono::prelude::*;
 use failure::{self, ResultExt};
 use futures::{future, prelude::*};
+use geo::Point as GeoPoint;
 use hyper::{self, Delete, Get, Headers, Post, Put, Request};
+use serde_json;
 use std::rc::Rc;
 use stq_api::warehouses::*;
 use stq_http::{
@@ -20,24 +29,39 @@ use stq_roles::{
     models::RepoLogin,
     service::{get_login_data, RoleService},
 };
+use stq_router::RouteParser;
 use stq_types::*;
+use uuid::Uuid;
 
 pub const SUPERADMIN_USER: UserId = UserId(1);
 
 #[derive(Clone)]
 pub struct ServiceFactory {
     role: Rc<Fn(UserLogin) -> Box<RoleService<UserRole>>>,
-    warehouse: Rc<Fn(UserLogin) -> Box<WarehouseService>>,
+    warehouse: Rc<Fn(UserLogin, Option<ApiKeyId>) -> Box<WarehouseService>>,
+    transfer: Rc<Fn(UserLogin, Option<ApiKeyId>) -> Box<TransferService>>,
+    allocation: Rc<Fn(UserLogin) -> Box<AllocationService>>,
+    api_key: Rc<Fn(UserLogin) -> Box<ApiKeyService>>,
+    audit: Rc<Fn(UserLogin, Option<ApiKeyId>) -> Box<AuditService>>,
 }
 
 pub struct ControllerImpl {
     db_pool: DbPool,
+    authenticator: Rc<Authenticator>,
+    caches: Caches,
+    route_parser: Rc<RouteParser<ServiceRoute>>,
     service_factory: ServiceFactory,
 }
 
 impl ControllerImpl {
-    pub fn new(db_pool: DbPool, _config: &Config) -> Self {
+    pub fn new(
+        db_pool: DbPool,
+        config: &Config,
+        authenticator: Authenticator,
+        caches: Caches,
+    ) -> Self {
         ControllerImpl {
+            authenticator: Rc::new(authenticator),
             service_factory: {
                 let roles_service_factory: Rc<
                     Fn(RepoLogin<UserRole>) -> Box<RoleService<UserRole>>,
@@ -53,32 +77,403 @@ impl ControllerImpl {
                 ServiceFactory {
                     role: roles_service_factory.clone(),
                     warehouse: Rc::new({
+                        let db_pool = db_pool.clone();
+                        let caches = caches.clone();
+                        let maintenance_mode = config.maintenance.enabled;
+                        move |login, api_key_id| {
+                            Box::new(
+                                WarehouseServiceImpl::new(&db_pool, &login, &caches)
+                                    .with_maintenance_mode(maintenance_mode)
+                                    .with_api_key(api_key_id),
+                            ) as Box<WarehouseService>
+                        }
+                    }),
+                    transfer: Rc::new({
+                        let db_pool = db_pool.clone();
+                        let caches = caches.clone();
+                        move |login, api_key_id| {
+                            Box::new(
+                                TransferServiceImpl::new(&db_pool, &login, &caches)
+                                    .with_api_key(api_key_id),
+                            ) as Box<TransferService>
+                        }
+                    }),
+                    allocation: Rc::new({
+                        let db_pool = db_pool.clone();
+                        let caches = caches.clone();
+                        move |login| {
+                            Box::new(AllocationServiceImpl::new(&db_pool, &login, &caches))
+                                as Box<AllocationService>
+                        }
+                    }),
+                    api_key: Rc::new({
                         let db_pool = db_pool.clone();
                         move |login| {
-                            Box::new(WarehouseServiceImpl::new(&db_pool, &login))
-                                as Box<WarehouseService>
+                            Box::new(ApiKeyServiceImpl::new(&db_pool, &login)) as Box<ApiKeyService>
+                        }
+                    }),
+                    audit: Rc::new({
+                        let db_pool = db_pool.clone();
+                        move |login, api_key_id| {
+                            Box::new(AuditServiceImpl::new(&db_pool, &login, api_key_id))
+                                as Box<AuditService>
                         }
                     }),
                 }
             },
+            route_parser: Rc::new(create_route_parser()),
             db_pool,
+            caches,
         }
     }
 }
 
-pub fn extract_user_id(headers: &Headers) -> Result<Option<UserId>, failure::Error> {
-    if let Some(auth) = headers.get::<hyper::header::Authorization<String>>() {
-        let string_id = auth.0.clone();
+/// Reads the version the caller expects to modify from the `If-Match` header, e.g. `If-Match: "3"`.
+pub fn extract_expected_version(headers: &Headers) -> Result<Option<Version>, failure::Error> {
+    match headers.get::<hyper::header::IfMatch>() {
+        None | Some(hyper::header::IfMatch::Any) => Ok(None),
+        Some(hyper::header::IfMatch::Items(tags)) => match tags.as_slice() {
+            [tag] => Ok(Some(Version(
+                tag.tag()
+                    .parse()
+                    .map_err(failure::Error::from)
+                    .context(format!("Failed to parse entity version: {}", tag.tag()))
+                    .context(Error::ParseError)?,
+            ))),
+            _ => Err(
+                format_err!("Expected a single entity version in If-Match header")
+                    .context(Error::ParseError)
+                    .into(),
+            ),
+        },
+    }
+}
 
-        Ok(string_id
+/// Reads `offset` and `count` query parameters, falling back to the default page for missing ones.
+pub fn extract_page(query: Option<&str>) -> Result<Page, failure::Error> {
+    let mut page = Page::default();
+    for (key, value) in query_pairs(query) {
+        let target = match key {
+            "offset" => &mut page.offset,
+            "count" => &mut page.count,
+            _ => continue,
+        };
+        *target = value
             .parse()
-            .map(|v| Some(UserId(v)))
             .map_err(failure::Error::from)
-            .context(format!("Failed to parse user ID: {}", string_id))
-            .context(Error::UserIdParse)?)
-    } else {
-        Ok(None)
+            .context(format!("Failed to parse query parameter {}={}", key, value))
+            .context(Error::ParseError)?;
+    }
+
+    Ok(page)
+}
+
+/// Reads `latitude`, `longitude` and optional `radius` (in meters) and `store_id` query parameters.
+pub fn extract_nearest_query(query: Option<&str>) -> Result<NearestWarehouseQuery, failure::Error> {
+    let (mut latitude, mut longitude, mut radius_meters, mut store_id) = (None, None, None, None);
+    for (key, value) in query_pairs(query) {
+        let parsed = match key {
+            "latitude" => value.parse().map(|v| latitude = Some(v)),
+            "longitude" => value.parse().map(|v| longitude = Some(v)),
+            "radius" => value.parse().map(|v| radius_meters = Some(v)),
+            "store_id" => {
+                store_id = Some(StoreId(
+                    value
+                        .parse()
+                        .map_err(failure::Error::from)
+                        .context(format!("Failed to parse query parameter {}={}", key, value))
+                        .context(Error::ParseError)?,
+                ));
+                Ok(())
+            }
+            _ => continue,
+        };
+        parsed
+            .map_err(failure::Error::from)
+            .context(format!("Failed to parse query parameter {}={}", key, value))
+            .context(Error::ParseError)?;
+    }
+
+    match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => Ok(NearestWarehouseQuery {
+            location: GeoPoint::new(longitude, latitude),
+            radius_meters,
+            store_id,
+        }),
+        _ => Err(format_err!("Both latitude and longitude are required")
+            .context(Error::ParseError)
+            .into()),
+    }
+}
+
+/// Reads stock page parameters, e.g. `store_id=1&product_ids=3,4&min_quantity=1&updated_since=...&sort=quantity&order=desc&after=...&count=100`.
+pub fn extract_stock_list_query(query: Option<&str>) -> Result<StockListQuery, failure::Error> {
+    fn parse<T>(key: &str, value: &str) -> Result<T, failure::Error>
+    where
+        T: ::std::str::FromStr,
+        T::Err: Into<failure::Error>,
+    {
+        Ok(value
+            .parse::<T>()
+            .map_err(Into::<failure::Error>::into)
+            .context(format!("Failed to parse query parameter {}={}", key, value))
+            .context(Error::ParseError)?)
+    }
+
+    fn parse_list<T>(key: &str, value: &str) -> Result<Vec<T>, failure::Error>
+    where
+        T: ::std::str::FromStr,
+        T::Err: Into<failure::Error>,
+    {
+        value
+            .split(',')
+            .filter(|v| !v.is_empty())
+            .map(|v| parse(key, v))
+            .collect()
+    }
+
+    let mut list_query = StockListQuery {
+        page: extract_page(query)?,
+        ..Default::default()
+    };
+    for (key, value) in query_pairs(query) {
+        match key {
+            "store_id" => list_query.store_id = Some(StoreId(parse(key, value)?)),
+            "warehouse_ids" => {
+                list_query.warehouse_ids = parse_list::<Uuid>(key, value)?
+                    .into_iter()
+                    .map(WarehouseId)
+                    .collect()
+            }
+            "product_ids" => {
+                list_query.product_ids = parse_list::<i32>(key, value)?
+                    .into_iter()
+                    .map(ProductId)
+                    .collect()
+            }
+            "min_quantity" => list_query.min_quantity = Some(Quantity(parse(key, value)?)),
+            "max_quantity" => list_query.max_quantity = Some(Quantity(parse(key, value)?)),
+            "updated_since" => {
+                list_query.updated_since = Some(parse_moment(key, &decode_query_value(value)?)?)
+            }
+            "sort" => list_query.sort = parse(key, value)?,
+            "order" => {
+                list_query.descending = match value {
+                    "asc" => false,
+                    "desc" => true,
+                    _ => {
+                        return Err(format_err!("Unknown sort order {}", value)
+                            .context(Error::ParseError)
+                            .into())
+                    }
+                }
+            }
+            "after" => list_query.after = Some(parse(key, value)?),
+            "offset" => {
+                return Err(format_err!(
+                    "Stock pages are addressed by the after cursor, not by offset"
+                )
+                .context(Error::ParseError)
+                .into())
+            }
+            _ => {}
+        }
     }
+
+    Ok(list_query)
+}
+
+/// Decodes a `application/x-www-form-urlencoded` query value, e.g. `New+York` or `M%C3%BCnchen`.
+pub fn decode_query_value(value: &str) -> Result<String, failure::Error> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| ::std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| {
+                        format_err!("Invalid percent encoding in {}", value)
+                            .context(Error::ParseError)
+                    })?;
+                decoded.push(hex);
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    Ok(String::from_utf8(decoded)
+        .context(format!("Query value {} is not valid UTF-8", value))
+        .context(Error::ParseError)?)
+}
+
+/// Reads warehouse search parameters: exact address attributes such as `locality` or `country_code`,
+/// partial `name` and `address`, RFC 3339 `updated_since`, and `offset`/`count`.
+pub fn extract_warehouse_search(query: Option<&str>) -> Result<WarehouseSearch, failure::Error> {
+    let mut search = WarehouseSearch {
+        archived: extract_flag(query, "archived")?,
+        page: extract_page(query)?,
+        ..Default::default()
+    };
+    for (key, value) in query_pairs(query) {
+        let value = decode_query_value(value)?;
+        match key {
+            "store_id" => {
+                search.filter.store_id = Some(
+                    StoreId(
+                        value
+                            .parse()
+                            .map_err(failure::Error::from)
+                            .context(format!("Failed to parse query parameter {}={}", key, value))
+                            .context(Error::ParseError)?,
+                    )
+                    .into(),
+                )
+            }
+            "slug" => search.filter.slug = Some(WarehouseSlug(value).into()),
+            "country" => search.filter.country = Some(Some(value).into()),
+            "country_code" => search.filter.country_code = Some(Some(Alpha3(value)).into()),
+            "administrative_area_level_1" => {
+                search.filter.administrative_area_level_1 = Some(Some(value).into())
+            }
+            "administrative_area_level_2" => {
+                search.filter.administrative_area_level_2 = Some(Some(value).into())
+            }
+            "locality" => search.filter.locality = Some(Some(value).into()),
+            "political" => search.filter.political = Some(Some(value).into()),
+            "postal_code" => search.filter.postal_code = Some(Some(value).into()),
+            "route" => search.filter.route = Some(Some(value).into()),
+            "street_number" => search.filter.street_number = Some(Some(value).into()),
+            "place_id" => search.filter.place_id = Some(Some(value).into()),
+            "name" => search.name = Some(value),
+            "address" => search.address = Some(value),
+            "updated_since" => search.updated_since = Some(parse_moment(key, &value)?),
+            _ => {}
+        }
+    }
+
+    Ok(search)
+}
+
+/// Parses a decoded RFC 3339 query value such as `2018-10-02T10:00:00Z`.
+/// A `+` in the offset decodes to a space, so it has to be sent as `%2B`, e.g. `2018-10-02T10:00:00%2B03:00`.
+pub fn parse_moment(key: &str, value: &str) -> Result<DateTime<Utc>, failure::Error> {
+    Ok(DateTime::parse_from_rfc3339(value)
+        .map_err(failure::Error::from)
+        .context(format!("Failed to parse query parameter {}={}", key, value))
+        .context(Error::ParseError)?
+        .with_timezone(&Utc))
+}
+
+/// Reads the RFC 3339 `at` query parameter, defaulting to the current time.
+pub fn extract_moment(query: Option<&str>) -> Result<DateTime<Utc>, failure::Error> {
+    match query_pairs(query).into_iter().find(|(key, _)| *key == "at") {
+        None => Ok(Utc::now()),
+        Some((key, value)) => parse_moment(key, &decode_query_value(value)?),
+    }
+}
+
+/// Reads a boolean query parameter such as `dry_run=true`, missing parameters being `false`.
+pub fn extract_flag(query: Option<&str>, name: &str) -> Result<bool, failure::Error> {
+    match query_pairs(query).into_iter().find(|(key, _)| *key == name) {
+        None => Ok(false),
+        Some((_, "")) => Ok(true),
+        Some((key, value)) => Ok(value
+            .parse()
+            .map_err(failure::Error::from)
+            .context(format!("Failed to parse query parameter {}={}", key, value))
+            .context(Error::ParseError)?),
+    }
+}
+
+/// Whether the caller asked for CSV either with `format=csv` or with the `Accept` header.
+pub fn wants_csv(headers: &Headers, query: Option<&str>) -> bool {
+    if query_pairs(query).contains(&("format", "csv")) {
+        return true;
+    }
+
+    headers
+        .get::<hyper::header::Accept>()
+        .map(|accept| {
+            accept
+                .iter()
+                .any(|v| v.item.type_() == hyper::mime::TEXT && v.item.subtype() == "csv")
+        })
+        .unwrap_or(false)
+}
+
+/// Reads the audit log query, e.g. `entity_type=warehouse&actor_id=42&from=2018-10-01T00:00:00Z`.
+pub fn extract_audit_log_query(query: Option<&str>) -> Result<AuditLogQuery, failure::Error> {
+    let mut audit_query = AuditLogQuery {
+        page: extract_page(query)?,
+        ..Default::default()
+    };
+    for (key, value) in query_pairs(query) {
+        let value = decode_query_value(value)?;
+        match key {
+            "entity_type" => audit_query.entity_type = Some(value.parse()?),
+            "entity_id" => audit_query.entity_id = Some(value),
+            "actor_id" => {
+                audit_query.actor_id = Some(UserId(
+                    value
+                        .parse()
+                        .map_err(failure::Error::from)
+                        .context(format!("Failed to parse query parameter {}={}", key, value))
+                        .context(Error::ParseError)?,
+                ))
+            }
+            "from" => audit_query.from = Some(parse_moment(key, &value)?),
+            "to" => audit_query.to = Some(parse_moment(key, &value)?),
+            _ => {}
+        }
+    }
+
+    Ok(audit_query)
+}
+
+/// Records what the change of roles did and passes its result through. Roles are changed by
+/// `stq_roles` in a transaction of its own, so unlike warehouse changes, which are recorded before
+/// they commit, this happens afterwards and failing to record it is reported but does not fail the request.
+fn record_audit<T: 'static>(
+    audit_service: &AuditService,
+    entity_type: AuditEntityType,
+    action: AuditAction,
+    before: Vec<serde_json::Value>,
+    after: Vec<serde_json::Value>,
+    result: T,
+) -> Box<Future<Item = T, Error = failure::Error>> {
+    Box::new(
+        audit_service
+            .record_changes(entity_type, action, before, after)
+            .then(move |res| {
+                if let Err(e) = res {
+                    log_and_capture_error(&e);
+                }
+                Ok(result)
+            }),
+    )
+}
+
+fn query_pairs(query: Option<&str>) -> Vec<(&str, &str)> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            (
+                parts.next().unwrap_or_default(),
+                parts.next().unwrap_or_default(),
+            )
+        })
+        .collect()
 }
 
 impl Controller for ControllerImpl {
@@ -86,24 +481,385 @@ impl Controller for ControllerImpl {
         let (method, uri, _, headers, payload) = request.deconstruct();
 
         let service_factory = self.service_factory.clone();
+        let caches = self.caches.clone();
 
         let route = Route::from_path(uri.path());
+        let service_route = self.route_parser.test(uri.path());
+        let query = uri.query().map(|v| v.to_string());
+        let expected_version = extract_expected_version(&headers);
+        let csv_requested = wants_csv(&headers, uri.query());
 
         Box::new(
-            future::result(extract_user_id(&headers))
+            future::result(self.authenticator.authenticate(&headers))
                 .map_err(|e| e.context("Failed to extract user ID").into())
                 .and_then({
                     let db_pool = self.db_pool.clone();
+                    let caches = caches.clone();
                     let path = uri.path().to_string();
                     let method = method.clone();
-                    move |caller_id| {
-                        debug!("Server received Request, method: {}, url: {}, user id: {:?}", method, path, caller_id);
-                        get_login_data(&db_pool, caller_id)
+                    move |credentials| match credentials {
+                        Credentials::User(caller_id) => {
+                            debug!("Server received Request, method: {}, url: {}, user id: {:?}", method, path, caller_id);
+                            let cached = caller_id.and_then(|caller_id| caches.logins.get(&caller_id));
+                            match cached {
+                                Some(login_data) => Box::new(future::ok((login_data, None)))
+                                    as Box<Future<Item = (UserLogin, Option<ApiKeyId>), Error = failure::Error>>,
+                                None => Box::new(get_login_data(&db_pool, caller_id).map(move |login_data| {
+                                    if let Some(caller_id) = caller_id {
+                                        caches.logins.insert(caller_id, login_data.clone());
+                                    }
+                                    (login_data, None)
+                                })),
+                            }
+                        }
+                        Credentials::ApiKey(key) => Box::new(find_api_key(&db_pool, key).and_then(move |api_key| {
+                            debug!("Server received Request, method: {}, url: {}, API key: {}", method, path, api_key.id);
+                            if api_key.scope == ApiKeyScope::Read && method != Get {
+                                Err(format_err!("API key {} is read-only", api_key.id)
+                                    .context(Error::Forbidden)
+                                    .into())
+                            } else {
+                                Ok((api_key.login(), Some(api_key.id)))
+                            }
+                        })),
                     }
                 })
-                .and_then(move |login_data| {
-                    let warehouse_service = (service_factory.warehouse)(login_data.clone());
+                .and_then(move |(login_data, api_key_id)| {
+                    let warehouse_service = (service_factory.warehouse)(login_data.clone(), api_key_id);
+                    let transfer_service = (service_factory.transfer)(login_data.clone(), api_key_id);
+                    let allocation_service = (service_factory.allocation)(login_data.clone());
+                    let api_key_service = (service_factory.api_key)(login_data.clone());
+                    let audit_service = (service_factory.audit)(login_data.clone(), api_key_id);
                     let roles_service = (service_factory.role)(login_data.clone());
+                    if let Some(service_route) = service_route {
+                        match (&method, service_route) {
+                            // Keys cannot issue or revoke other keys
+                            (_, ServiceRoute::ApiKeys) | (_, ServiceRoute::ApiKey { .. }) | (_, ServiceRoute::StoreApiKeys { .. })
+                                if api_key_id.is_some() =>
+                            {
+                                return Box::new(future::err(
+                                    format_err!("API keys can only be managed by users")
+                                        .context(Error::Forbidden)
+                                        .into(),
+                                ))
+                            }
+                            (Post, ServiceRoute::ApiKeys) => {
+                                return serialize_future({
+                                    parse_body::<NewApiKey>(payload).and_then(move |data| {
+                                        debug!("Received request to create API key {:?}", data);
+                                        api_key_service.create_api_key(data)
+                                    })
+                                })
+                            }
+                            (Get, ServiceRoute::StoreApiKeys { store_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to list API keys of store {}", store_id);
+                                    api_key_service.list_api_keys(store_id)
+                                })
+                            }
+                            (Get, ServiceRoute::AuditLog) => {
+                                return serialize_future({
+                                    debug!("Received request to query audit log: {:?}", query);
+                                    future::result(extract_audit_log_query(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |audit_query| audit_service.find_audit_entries(audit_query))
+                                })
+                            }
+                            (Get, ServiceRoute::CacheStats) => {
+                                return serialize_future({
+                                    debug!("Received request to get cache stats");
+                                    future::result(ensure_superadmin(&login_data).map(|()| caches.stats()))
+                                })
+                            }
+                            (Delete, ServiceRoute::ApiKey { api_key_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to revoke API key {}", api_key_id);
+                                    api_key_service.revoke_api_key(api_key_id)
+                                })
+                            }
+                            (Post, ServiceRoute::Reservations) => {
+                                return serialize_future({
+                                    parse_body::<ReservationInput>(payload).and_then(move |data| {
+                                        debug!("Received request to hold stock: {:?}", &data);
+                                        warehouse_service.hold_stock(data)
+                                    })
+                                })
+                            }
+                            (Get, ServiceRoute::Reservation { reservation_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to get reservation {}", reservation_id);
+                                    warehouse_service.get_reservation(reservation_id)
+                                })
+                            }
+                            (Post, ServiceRoute::ReservationCommit { reservation_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to commit reservation {}", reservation_id);
+                                    warehouse_service.commit_reservation(reservation_id)
+                                })
+                            }
+                            (Post, ServiceRoute::ReservationRelease { reservation_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to release reservation {}", reservation_id);
+                                    warehouse_service.release_reservation(reservation_id)
+                                })
+                            }
+                            (Get, ServiceRoute::WarehouseStockMovements { warehouse_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to list stock movements of warehouse {}", warehouse_id);
+                                    future::result(extract_page(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |page| {
+                                            warehouse_service.list_stock_movements(
+                                                StockMovementSubject::Warehouse(warehouse_id),
+                                                page,
+                                            )
+                                        })
+                                })
+                            }
+                            (Get, ServiceRoute::ProductStockMovements { product_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to list stock movements of product {}", product_id);
+                                    future::result(extract_page(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |page| {
+                                            warehouse_service.list_stock_movements(
+                                                StockMovementSubject::Product(product_id),
+                                                page,
+                                            )
+                                        })
+                                })
+                            }
+                            (Get, ServiceRoute::StockMovements { stock_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to list stock movements of stock {}", stock_id.0);
+                                    future::result(extract_page(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |page| {
+                                            warehouse_service.list_stock_movements(
+                                                StockMovementSubject::Stock(stock_id),
+                                                page,
+                                            )
+                                        })
+                                })
+                            }
+                            (
+                                Post,
+                                ServiceRoute::StockAdjustments {
+                                    warehouse_id,
+                                    product_id,
+                                },
+                            ) => {
+                                return serialize_future({
+                                    parse_body::<StockAdjustPayload>(payload).and_then(move |data| {
+                                        debug!(
+                                            "Received request to adjust stocks of product {} in warehouse {} with the following data {:?}",
+                                            product_id, warehouse_id, &data
+                                        );
+                                        warehouse_service.adjust_product_in_warehouse(
+                                            warehouse_id,
+                                            product_id,
+                                            data.delta,
+                                        )
+                                    })
+                                })
+                            }
+                            (
+                                Put,
+                                ServiceRoute::StockReorderThreshold {
+                                    warehouse_id,
+                                    product_id,
+                                },
+                            ) => {
+                                return serialize_future({
+                                    parse_body::<StockReorderThresholdPayload>(payload).and_then(move |data| {
+                                        debug!(
+                                            "Received request to set reorder threshold of product {} in warehouse {} to {:?}",
+                                            product_id, warehouse_id, data.reorder_threshold
+                                        );
+                                        warehouse_service.set_stock_reorder_threshold(
+                                            warehouse_id,
+                                            product_id,
+                                            data.reorder_threshold,
+                                        )
+                                    })
+                                })
+                            }
+                            (Put, ServiceRoute::WarehouseReorderThreshold { warehouse_id }) => {
+                                return serialize_future({
+                                    parse_body::<WarehouseReorderThresholdPayload>(payload).and_then(move |data| {
+                                        debug!(
+                                            "Received request to set default reorder threshold of warehouse {} to {:?}",
+                                            warehouse_id, data.default_reorder_threshold
+                                        );
+                                        warehouse_service.set_warehouse_reorder_threshold(warehouse_id, data.default_reorder_threshold)
+                                    })
+                                })
+                            }
+                            (Get, ServiceRoute::StoreLowStock { store_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to list low stocks of store {}", store_id);
+                                    warehouse_service.list_low_stock(store_id)
+                                })
+                            }
+                            (Get, ServiceRoute::StoreLowStockEvents { store_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to list low stock events of store {}", store_id);
+                                    future::result(extract_page(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |page| warehouse_service.list_low_stock_events(store_id, page))
+                                })
+                            }
+                            (Get, ServiceRoute::StoreStockVisibility { store_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to get stock visibility of store {}", store_id);
+                                    warehouse_service.get_stock_visibility(store_id)
+                                })
+                            }
+                            (Put, ServiceRoute::StoreStockVisibility { store_id }) => {
+                                return serialize_future({
+                                    parse_body::<StockVisibility>(payload).and_then(move |data| {
+                                        debug!("Received request to set stock visibility of store {} to {:?}", store_id, data);
+                                        warehouse_service.set_stock_visibility(store_id, data)
+                                    })
+                                })
+                            }
+                            (Get, ServiceRoute::WarehouseSearch) => {
+                                return serialize_future({
+                                    debug!("Received request to search warehouses: {:?}", query);
+                                    future::result(extract_warehouse_search(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |search| warehouse_service.search_warehouses(search))
+                                })
+                            }
+                            (Get, ServiceRoute::NearestWarehouses) => {
+                                return serialize_future({
+                                    debug!("Received request to find nearest warehouses: {:?}", query);
+                                    let query = query.as_ref().map(|v| v.as_str());
+                                    future::result(extract_nearest_query(query).and_then(|search| Ok((search, extract_page(query)?))))
+                                        .and_then(move |(search, page)| warehouse_service.find_nearest_warehouses(search, page))
+                                })
+                            }
+                            (Post, ServiceRoute::StoreAllocations { store_id }) => {
+                                return serialize_future({
+                                    parse_body::<AllocationInput>(payload).and_then(move |data| {
+                                        debug!("Received request to plan allocation for store {}: {:?}", store_id, &data);
+                                        allocation_service.plan_allocation(store_id, data)
+                                    })
+                                })
+                            }
+                            (Get, ServiceRoute::StocksPage) => {
+                                return serialize_future({
+                                    debug!("Received request to page through stocks in all warehouses: {:?}", query);
+                                    future::result(extract_stock_list_query(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |list_query| warehouse_service.page_products(list_query))
+                                })
+                            }
+                            (Post, ServiceRoute::StocksBulk) => {
+                                return serialize_future({
+                                    parse_body::<Vec<StockUpsertLine>>(payload).and_then(move |data| {
+                                        debug!("Received request to bulk upsert {} stock lines", data.len());
+                                        warehouse_service.bulk_set_products_in_warehouses(data)
+                                    })
+                                })
+                            }
+                            (Post, ServiceRoute::StocksImport { warehouse_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to import stocks of warehouse {}", warehouse_id);
+                                    future::result(extract_flag(query.as_ref().map(|v| v.as_str()), "dry_run"))
+                                        .join(payload.concat2().map_err(failure::Error::from))
+                                        .and_then(|(dry_run, body)| parse_stock_csv(&body).map(|rows| (dry_run, rows)))
+                                        .and_then(move |(dry_run, rows)| {
+                                            warehouse_service.import_products_in_warehouse(warehouse_id, rows, dry_run)
+                                        })
+                                })
+                            }
+                            (Post, ServiceRoute::Transfers) => {
+                                return serialize_future({
+                                    parse_body::<TransferInput>(payload).and_then(move |data| {
+                                        debug!("Received request to create transfer: {:?}", &data);
+                                        transfer_service.create_transfer(data)
+                                    })
+                                })
+                            }
+                            (Get, ServiceRoute::Transfer { transfer_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to get transfer {}", transfer_id);
+                                    transfer_service.get_transfer(transfer_id)
+                                })
+                            }
+                            (Post, ServiceRoute::TransferShip { transfer_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to ship transfer {}", transfer_id);
+                                    transfer_service.ship_transfer(transfer_id)
+                                })
+                            }
+                            (Post, ServiceRoute::TransferReceive { transfer_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to receive transfer {}", transfer_id);
+                                    transfer_service.receive_transfer(transfer_id)
+                                })
+                            }
+                            (Post, ServiceRoute::TransferCancel { transfer_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to cancel transfer {}", transfer_id);
+                                    transfer_service.cancel_transfer(transfer_id)
+                                })
+                            }
+                            (Get, ServiceRoute::WarehouseInTransit { warehouse_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to list goods in transit to warehouse {}", warehouse_id);
+                                    transfer_service.list_in_transit(warehouse_id)
+                                })
+                            }
+                            (Post, ServiceRoute::WarehouseRestore { warehouse_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to restore warehouse {}", warehouse_id);
+                                    warehouse_service.restore_warehouse(WarehouseIdentifier::Id(warehouse_id))
+                                })
+                            }
+                            (Delete, ServiceRoute::WarehousePurge { warehouse_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to purge warehouse {}", warehouse_id);
+                                    warehouse_service.purge_warehouse(WarehouseIdentifier::Id(warehouse_id))
+                                })
+                            }
+                            (Put, ServiceRoute::WarehouseStatus { warehouse_id }) => {
+                                return serialize_future({
+                                    parse_body::<WarehouseStatusPayload>(payload).and_then(move |data| {
+                                        debug!(
+                                            "Received request to set status of warehouse {} to {}",
+                                            warehouse_id, data.status
+                                        );
+                                        warehouse_service.set_warehouse_status(warehouse_id, data.status)
+                                    })
+                                })
+                            }
+                            (Put, ServiceRoute::WarehouseCapacity { warehouse_id }) => {
+                                return serialize_future({
+                                    parse_body::<WarehouseCapacity>(payload).and_then(move |data| {
+                                        debug!("Received request to set capacity of warehouse {} to {:?}", warehouse_id, data);
+                                        warehouse_service.set_warehouse_capacity(warehouse_id, data)
+                                    })
+                                })
+                            }
+                            (Get, ServiceRoute::WarehouseUtilization { warehouse_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to get utilization of warehouse {}", warehouse_id);
+                                    warehouse_service.get_warehouse_utilization(warehouse_id)
+                                })
+                            }
+                            (Get, ServiceRoute::WarehouseOpeningStatus { warehouse_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to get opening status of warehouse {}", warehouse_id);
+                                    future::result(extract_moment(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |at| warehouse_service.get_opening_status(warehouse_id, at))
+                                })
+                            }
+                            (Get, ServiceRoute::WarehouseStatusChanges { warehouse_id }) => {
+                                return serialize_future({
+                                    debug!("Received request to list status changes of warehouse {}", warehouse_id);
+                                    future::result(extract_page(query.as_ref().map(|v| v.as_str())))
+                                        .and_then(move |page| warehouse_service.list_warehouse_status_changes(warehouse_id, page))
+                                })
+                            }
+                            (_, _) => {}
+                        }
+                    }
+
                     match (&method, route) {
                         (Get, Some(Route::Warehouse { warehouse_id })) => {
                             return serialize_future({
@@ -117,25 +873,41 @@ impl Controller for ControllerImpl {
                                 warehouse_service.get_warehouses_for_store(store_id)
                             })
                         }
+                        (Delete, Some(Route::WarehousesByStore { store_id })) => {
+                            return serialize_future({
+                                future::result(extract_flag(query.as_ref().map(|v| v.as_str()), "purge"))
+                                    .and_then(move |purge| {
+                                        if purge {
+                                            debug!("Received request to purge warehouses of store {}", store_id);
+                                            warehouse_service.purge_store_warehouses(store_id)
+                                        } else {
+                                            debug!("Received request to archive warehouses of store {}", store_id);
+                                            warehouse_service.archive_store_warehouses(store_id)
+                                        }
+                                    })
+                            })
+                        }
                         (Post, Some(Route::Warehouses)) => {
                             return serialize_future({
                                 debug!("Received request to create warehouse");
-                                parse_body::<WarehouseInput>(payload)
+                                parse_body::<NewWarehouseRecord>(payload)
                                     .and_then(move |data| warehouse_service.create_warehouse(data))
                             })
                         }
                         (Put, Some(Route::Warehouse { warehouse_id })) => {
                             return serialize_future({
                                 debug!("Received request to update warehouse {:?}", warehouse_id);
-                                parse_body::<WarehouseUpdateData>(payload).and_then(move |data| {
-                                    warehouse_service.update_warehouse(warehouse_id, data)
-                                })
+                                future::result(expected_version)
+                                    .join(parse_body::<WarehouseRecordUpdate>(payload))
+                                    .and_then(move |(expected_version, data)| {
+                                        warehouse_service.update_warehouse(warehouse_id, data, expected_version)
+                                    })
                             })
                         }
                         (Delete, Some(Route::Warehouse { warehouse_id })) => {
                             return serialize_future({
-                                debug!("Received request to delete warehouse {:?}", warehouse_id);
-                                warehouse_service.delete_warehouse(warehouse_id)
+                                debug!("Received request to archive warehouse {:?}", warehouse_id);
+                                warehouse_service.archive_warehouse(warehouse_id)
                             })
                         }
                         (Delete, Some(Route::Warehouses)) => {
@@ -144,6 +916,17 @@ impl Controller for ControllerImpl {
                                 warehouse_service.delete_all_warehouses()
                             })
                         }
+                        (Get, Some(Route::StocksInWarehouse { warehouse_id })) if csv_requested => {
+                            debug!(
+                                "Received request to export stocks of warehouse {:?} as CSV",
+                                warehouse_id
+                            );
+                            return Box::new(
+                                warehouse_service
+                                    .export_products_in_warehouse(warehouse_id)
+                                    .and_then(stocks_to_csv),
+                            );
+                        }
                         (Get, Some(Route::StocksInWarehouse { warehouse_id })) => {
                             return serialize_future({
                                 debug!(
@@ -176,17 +959,20 @@ impl Controller for ControllerImpl {
                             }),
                         ) => {
                             return serialize_future({
-                                parse_body::<StockSetPayload>(payload).and_then(move |data| {
-                                    debug!(
-                                    "Received request to update stocks of product {} in warehouse {} with the following data {:?}",
-                                    product_id, warehouse_id, &data
-                                );
-                                    warehouse_service.set_product_in_warehouse(
-                                        warehouse_id,
-                                        product_id,
-                                        data.quantity,
-                                    )
-                                })
+                                future::result(expected_version)
+                                    .join(parse_body::<StockSetPayload>(payload))
+                                    .and_then(move |(expected_version, data)| {
+                                        debug!(
+                                        "Received request to update stocks of product {} in warehouse {} with the following data {:?}",
+                                        product_id, warehouse_id, &data
+                                    );
+                                        warehouse_service.set_product_in_warehouse(
+                                            warehouse_id,
+                                            product_id,
+                                            data.quantity,
+                                            expected_version,
+                                        )
+                                    })
                             })
                         }
                         (Get, Some(Route::StocksByProductId { product_id })) => {
@@ -198,12 +984,12 @@ impl Controller for ControllerImpl {
                                 warehouse_service.find_by_product_id(product_id)
                             });
                         }
+                        // Kept as a plain array for older clients, `/stocks/page` also returns the next cursor
                         (Get, Some(Route::Stocks)) => {
                             return serialize_future({
-                                debug!(
-                                    "Received request to get stocks in all warehouses",
-                                );
-                                warehouse_service.find_products()
+                                debug!("Received request to get stocks in all warehouses: {:?}", query);
+                                future::result(extract_stock_list_query(query.as_ref().map(|v| v.as_str())))
+                                    .and_then(move |list_query| warehouse_service.find_products(list_query))
                             });
                         }
                         (method, Some(Route::Roles(route))) => {
@@ -211,7 +997,32 @@ impl Controller for ControllerImpl {
                                 service: roles_service.into(),
                             };
                             if let Some(out) = c.call(method, &route, payload) {
-                                return out;
+                                if *method == Get {
+                                    return out;
+                                }
+                                // Created roles are echoed and removed ones returned in the response
+                                let action = if *method == Delete {
+                                    AuditAction::Delete
+                                } else {
+                                    AuditAction::Create
+                                };
+                                return Box::new(
+                                    out.and_then(move |body| {
+                                        let values = serde_json::from_str::<serde_json::Value>(&body)
+                                            .map(|v| audit_values(&v))
+                                            .unwrap_or_default();
+                                        let (before, after) = match action {
+                                            AuditAction::Delete => (values, vec![]),
+                                            _ => (vec![], values),
+                                        };
+                                        record_audit(&*audit_service, AuditEntityType::Role, action, before, after, body)
+                                    })
+                                    // Roles may have changed, cached logins are refreshed from the database
+                                    .then(move |res| {
+                                        caches.logins.clear();
+                                        res
+                                    }),
+                                );
                             }
                         }
                         (_, _) => {}
@@ -233,3 +1044,58 @@ impl Controller for ControllerImpl {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_values_are_form_decoded() {
+        assert_eq!(decode_query_value("main").unwrap(), "main");
+        assert_eq!(decode_query_value("New+York").unwrap(), "New York");
+        assert_eq!(decode_query_value("M%C3%BCnchen").unwrap(), "München");
+        assert_eq!(decode_query_value("a%2Bb%2fc").unwrap(), "a+b/c");
+        assert_eq!(decode_query_value("").unwrap(), "");
+    }
+
+    #[test]
+    fn malformed_query_values_are_rejected() {
+        for value in &["%zz", "%", "%4", "main%", "%C3"] {
+            assert!(decode_query_value(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn warehouse_search_decodes_its_values() {
+        let search =
+            extract_warehouse_search(Some("locality=New+York&name=M%C3%BCnchen&country_code=RUS"))
+                .unwrap();
+
+        assert_eq!(
+            search.filter.locality.map(|v| v.value),
+            Some(Some("New York".to_string()))
+        );
+        assert_eq!(search.name, Some("München".to_string()));
+        assert_eq!(
+            search.filter.country_code.map(|v| v.value),
+            Some(Some(Alpha3("RUS".to_string())))
+        );
+        assert!(extract_warehouse_search(Some("name=%zz")).is_err());
+    }
+
+    #[test]
+    fn moments_keep_their_offset_only_when_plus_is_encoded() {
+        let expected = Utc.ymd(2018, 10, 2).and_hms(7, 0, 0);
+
+        assert_eq!(
+            extract_moment(Some("at=2018-10-02T07:00:00Z")).unwrap(),
+            expected
+        );
+        assert_eq!(
+            extract_moment(Some("at=2018-10-02T10:00:00%2B03:00")).unwrap(),
+            expected
+        );
+        assert!(extract_moment(Some("at=2018-10-02T10:00:00+03:00")).is_err());
+        assert!(extract_moment(Some("at=yesterday")).is_err());
+    }
+}
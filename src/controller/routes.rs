@@ -0,0 +1,359 @@
+use models::*;
+
+use stq_router::RouteParser;
+use stq_types::*;
+use uuid::Uuid;
+
+/// Routes served by this service on top of those declared in `stq_api::warehouses::Route`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServiceRoute {
+    ApiKeys,
+    ApiKey {
+        api_key_id: ApiKeyId,
+    },
+    StoreApiKeys {
+        store_id: StoreId,
+    },
+    AuditLog,
+    CacheStats,
+    Reservations,
+    Reservation {
+        reservation_id: ReservationId,
+    },
+    ReservationCommit {
+        reservation_id: ReservationId,
+    },
+    ReservationRelease {
+        reservation_id: ReservationId,
+    },
+    WarehouseStockMovements {
+        warehouse_id: WarehouseId,
+    },
+    ProductStockMovements {
+        product_id: ProductId,
+    },
+    StockMovements {
+        stock_id: StockId,
+    },
+    StockAdjustments {
+        warehouse_id: WarehouseId,
+        product_id: ProductId,
+    },
+    StockReorderThreshold {
+        warehouse_id: WarehouseId,
+        product_id: ProductId,
+    },
+    WarehouseReorderThreshold {
+        warehouse_id: WarehouseId,
+    },
+    StoreLowStock {
+        store_id: StoreId,
+    },
+    StoreLowStockEvents {
+        store_id: StoreId,
+    },
+    StoreStockVisibility {
+        store_id: StoreId,
+    },
+    NearestWarehouses,
+    WarehouseSearch,
+    StoreAllocations {
+        store_id: StoreId,
+    },
+    StocksPage,
+    StocksBulk,
+    StocksImport {
+        warehouse_id: WarehouseId,
+    },
+    Transfers,
+    Transfer {
+        transfer_id: TransferId,
+    },
+    TransferShip {
+        transfer_id: TransferId,
+    },
+    TransferReceive {
+        transfer_id: TransferId,
+    },
+    TransferCancel {
+        transfer_id: TransferId,
+    },
+    WarehouseInTransit {
+        warehouse_id: WarehouseId,
+    },
+    WarehouseRestore {
+        warehouse_id: WarehouseId,
+    },
+    WarehousePurge {
+        warehouse_id: WarehouseId,
+    },
+    WarehouseStatus {
+        warehouse_id: WarehouseId,
+    },
+    WarehouseStatusChanges {
+        warehouse_id: WarehouseId,
+    },
+    WarehouseOpeningStatus {
+        warehouse_id: WarehouseId,
+    },
+    WarehouseCapacity {
+        warehouse_id: WarehouseId,
+    },
+    WarehouseUtilization {
+        warehouse_id: WarehouseId,
+    },
+}
+
+pub fn create_route_parser() -> RouteParser<ServiceRoute> {
+    let mut route_parser = RouteParser::default();
+
+    route_parser.add_route(r"^/api-keys$", || ServiceRoute::ApiKeys);
+    route_parser.add_route(r"^/audit-log$", || ServiceRoute::AuditLog);
+    route_parser.add_route(r"^/cache/stats$", || ServiceRoute::CacheStats);
+    route_parser.add_route_with_params(r"^/api-keys/([a-zA-Z0-9-]+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<Uuid>().ok())
+            .map(|id| ServiceRoute::ApiKey {
+                api_key_id: ApiKeyId(id),
+            })
+    });
+    route_parser.add_route_with_params(r"^/stores/(\d+)/api-keys$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<i32>().ok())
+            .map(|id| ServiceRoute::StoreApiKeys {
+                store_id: StoreId(id),
+            })
+    });
+
+    route_parser.add_route(r"^/reservations$", || ServiceRoute::Reservations);
+    route_parser.add_route_with_params(r"^/reservations/([a-zA-Z0-9-]+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|reservation_id| ServiceRoute::Reservation { reservation_id })
+    });
+    route_parser.add_route_with_params(r"^/reservations/([a-zA-Z0-9-]+)/commit$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|reservation_id| ServiceRoute::ReservationCommit { reservation_id })
+    });
+    route_parser.add_route_with_params(r"^/reservations/([a-zA-Z0-9-]+)/release$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|reservation_id| ServiceRoute::ReservationRelease { reservation_id })
+    });
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/movements$",
+        |params| {
+            params
+                .get(0)
+                .and_then(|string_id| string_id.parse::<Uuid>().ok())
+                .map(|id| ServiceRoute::WarehouseStockMovements {
+                    warehouse_id: WarehouseId(id),
+                })
+        },
+    );
+    route_parser.add_route_with_params(r"^/stocks/by-product-id/(\d+)/movements$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<i32>().ok())
+            .map(|id| ServiceRoute::ProductStockMovements {
+                product_id: ProductId(id),
+            })
+    });
+    route_parser.add_route_with_params(r"^/stocks/by-id/([a-zA-Z0-9-]+)/movements$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<Uuid>().ok())
+            .map(|id| ServiceRoute::StockMovements {
+                stock_id: StockId(id),
+            })
+    });
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/products/(\d+)/adjustments$",
+        |params| {
+            let warehouse_id = params.get(0)?.parse::<Uuid>().ok().map(WarehouseId)?;
+            let product_id = params.get(1)?.parse::<i32>().ok().map(ProductId)?;
+            Some(ServiceRoute::StockAdjustments {
+                warehouse_id,
+                product_id,
+            })
+        },
+    );
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/products/(\d+)/reorder-threshold$",
+        |params| {
+            let warehouse_id = params.get(0)?.parse::<Uuid>().ok().map(WarehouseId)?;
+            let product_id = params.get(1)?.parse::<i32>().ok().map(ProductId)?;
+            Some(ServiceRoute::StockReorderThreshold {
+                warehouse_id,
+                product_id,
+            })
+        },
+    );
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/reorder-threshold$",
+        |params| {
+            params
+                .get(0)
+                .and_then(|string_id| string_id.parse::<Uuid>().ok())
+                .map(|id| ServiceRoute::WarehouseReorderThreshold {
+                    warehouse_id: WarehouseId(id),
+                })
+        },
+    );
+    route_parser.add_route_with_params(r"^/stores/(\d+)/low-stock$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<i32>().ok())
+            .map(|id| ServiceRoute::StoreLowStock {
+                store_id: StoreId(id),
+            })
+    });
+    route_parser.add_route_with_params(r"^/stores/(\d+)/low-stock/events$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<i32>().ok())
+            .map(|id| ServiceRoute::StoreLowStockEvents {
+                store_id: StoreId(id),
+            })
+    });
+    route_parser.add_route_with_params(r"^/stores/(\d+)/stock-visibility$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<i32>().ok())
+            .map(|id| ServiceRoute::StoreStockVisibility {
+                store_id: StoreId(id),
+            })
+    });
+    route_parser.add_route(r"^/warehouses/nearest$", || ServiceRoute::NearestWarehouses);
+    route_parser.add_route(r"^/warehouses/search$", || ServiceRoute::WarehouseSearch);
+    route_parser.add_route_with_params(r"^/stores/(\d+)/allocations$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<i32>().ok())
+            .map(|id| ServiceRoute::StoreAllocations {
+                store_id: StoreId(id),
+            })
+    });
+    route_parser.add_route(r"^/stocks/page$", || ServiceRoute::StocksPage);
+    route_parser.add_route(r"^/stocks/bulk$", || ServiceRoute::StocksBulk);
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/products/import$",
+        |params| {
+            params
+                .get(0)
+                .and_then(|string_id| string_id.parse::<Uuid>().ok())
+                .map(|id| ServiceRoute::StocksImport {
+                    warehouse_id: WarehouseId(id),
+                })
+        },
+    );
+    route_parser.add_route(r"^/transfers$", || ServiceRoute::Transfers);
+    route_parser.add_route_with_params(r"^/transfers/([a-zA-Z0-9-]+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|transfer_id| ServiceRoute::Transfer { transfer_id })
+    });
+    route_parser.add_route_with_params(r"^/transfers/([a-zA-Z0-9-]+)/ship$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|transfer_id| ServiceRoute::TransferShip { transfer_id })
+    });
+    route_parser.add_route_with_params(r"^/transfers/([a-zA-Z0-9-]+)/receive$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|transfer_id| ServiceRoute::TransferReceive { transfer_id })
+    });
+    route_parser.add_route_with_params(r"^/transfers/([a-zA-Z0-9-]+)/cancel$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|transfer_id| ServiceRoute::TransferCancel { transfer_id })
+    });
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/in-transit$",
+        |params| {
+            params
+                .get(0)
+                .and_then(|string_id| string_id.parse::<Uuid>().ok())
+                .map(|id| ServiceRoute::WarehouseInTransit {
+                    warehouse_id: WarehouseId(id),
+                })
+        },
+    );
+    route_parser.add_route_with_params(r"^/warehouses/by-id/([a-zA-Z0-9-]+)/restore$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<Uuid>().ok())
+            .map(|id| ServiceRoute::WarehouseRestore {
+                warehouse_id: WarehouseId(id),
+            })
+    });
+    route_parser.add_route_with_params(r"^/warehouses/by-id/([a-zA-Z0-9-]+)/purge$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<Uuid>().ok())
+            .map(|id| ServiceRoute::WarehousePurge {
+                warehouse_id: WarehouseId(id),
+            })
+    });
+    route_parser.add_route_with_params(r"^/warehouses/by-id/([a-zA-Z0-9-]+)/status$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<Uuid>().ok())
+            .map(|id| ServiceRoute::WarehouseStatus {
+                warehouse_id: WarehouseId(id),
+            })
+    });
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/status/changes$",
+        |params| {
+            params
+                .get(0)
+                .and_then(|string_id| string_id.parse::<Uuid>().ok())
+                .map(|id| ServiceRoute::WarehouseStatusChanges {
+                    warehouse_id: WarehouseId(id),
+                })
+        },
+    );
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/opening-status$",
+        |params| {
+            params
+                .get(0)
+                .and_then(|string_id| string_id.parse::<Uuid>().ok())
+                .map(|id| ServiceRoute::WarehouseOpeningStatus {
+                    warehouse_id: WarehouseId(id),
+                })
+        },
+    );
+    route_parser.add_route_with_params(r"^/warehouses/by-id/([a-zA-Z0-9-]+)/capacity$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<Uuid>().ok())
+            .map(|id| ServiceRoute::WarehouseCapacity {
+                warehouse_id: WarehouseId(id),
+            })
+    });
+    route_parser.add_route_with_params(
+        r"^/warehouses/by-id/([a-zA-Z0-9-]+)/utilization$",
+        |params| {
+            params
+                .get(0)
+                .and_then(|string_id| string_id.parse::<Uuid>().ok())
+                .map(|id| ServiceRoute::WarehouseUtilization {
+                    warehouse_id: WarehouseId(id),
+                })
+        },
+    );
+
+    route_parser
+}
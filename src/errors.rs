@@ -8,12 +8,32 @@ pub enum Error {
     MissingUserId,
     #[fail(display = "Failed to parse user_id")]
     UserIdParse,
+    #[fail(display = "Unauthorized")]
+    Unauthorized,
     #[fail(display = "Parse failure")]
     ParseError,
     #[fail(display = "Invalid route")]
     InvalidRoute,
     #[fail(display = "Not found")]
     NotFound,
+    #[fail(display = "Forbidden")]
+    Forbidden,
+    #[fail(display = "Invalid input")]
+    InvalidInput,
+    #[fail(display = "Insufficient stock")]
+    InsufficientStock,
+    #[fail(display = "Reservation is not active")]
+    ReservationNotActive,
+    #[fail(display = "Version conflict")]
+    VersionConflict,
+    #[fail(display = "Invalid transfer status")]
+    InvalidTransferStatus,
+    #[fail(display = "Warehouse capacity exceeded")]
+    CapacityExceeded,
+    #[fail(display = "Warehouse slug is taken")]
+    SlugTaken,
+    #[fail(display = "Warehouse is not available")]
+    WarehouseUnavailable,
 }
 
 impl Codeable for Error {
@@ -22,9 +42,19 @@ impl Codeable for Error {
 
         match self {
             MissingUserId | UserIdParse { .. } => StatusCode::BadRequest,
+            Unauthorized => StatusCode::Unauthorized,
             ParseError => StatusCode::UnprocessableEntity,
             InvalidRoute => StatusCode::NotFound,
             NotFound => StatusCode::NotFound,
+            Forbidden => StatusCode::Forbidden,
+            InvalidInput => StatusCode::UnprocessableEntity,
+            InsufficientStock
+            | ReservationNotActive
+            | VersionConflict
+            | InvalidTransferStatus
+            | CapacityExceeded
+            | SlugTaken
+            | WarehouseUnavailable => StatusCode::Conflict,
         }
     }
 }
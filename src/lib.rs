@@ -1,21 +1,27 @@
 extern crate bb8;
 extern crate bb8_postgres;
 extern crate chrono;
+extern crate chrono_tz;
 extern crate config as config_crate;
+extern crate csv;
 extern crate env_logger;
 #[macro_use]
 extern crate failure;
 extern crate futures;
+extern crate futures_state_stream;
 extern crate geo;
 extern crate hyper;
 extern crate iso_country;
+extern crate jsonwebtoken;
 #[macro_use]
 extern crate log as log_crate;
 extern crate postgres;
+extern crate rand;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate sha2;
 extern crate stq_acl;
 extern crate stq_api;
 extern crate stq_db;
@@ -41,6 +47,7 @@ use stq_http::controller::Application;
 use tokio_core::reactor::Core;
 use tokio_postgres::TlsMode;
 
+pub mod cache;
 mod config;
 pub mod controller;
 pub mod errors;
@@ -75,9 +82,21 @@ pub fn start_server<F: FnOnce() + 'static>(config: config::Config, port: Option<
         SocketAddr::new(config.listen.host, port)
     };
 
+    let authenticator = controller::auth::Authenticator::new(&config.auth).unwrap_or_else(|why| {
+        error!("Failed to set up authentication: {}", why);
+        exit(1);
+    });
+
+    let caches = cache::Caches::new(&config.cache);
+
     let serve = Http::new()
         .serve_addr_handle(&listen_address, &core.handle(), move || {
-            let controller = controller::ControllerImpl::new(db_pool.clone(), &config);
+            let controller = controller::ControllerImpl::new(
+                db_pool.clone(),
+                &config,
+                authenticator.clone(),
+                caches.clone(),
+            );
 
             // Prepare application
             let app = Application::<errors::Error>::new(controller);
@@ -0,0 +1,322 @@
+use errors::*;
+use models::EARTH_RADIUS_METERS;
+
+use failure;
+use geo::Point as GeoPoint;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+
+const WAREHOUSE_ID_COLUMN: &str = "warehouse_id";
+const PRODUCT_ID_COLUMN: &str = "product_id";
+const QUANTITY_COLUMN: &str = "quantity";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllocationLine {
+    pub product_id: ProductId,
+    pub quantity: Quantity,
+}
+
+/// Basket to be shipped from the warehouses of a store to the given address.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AllocationInput {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub lines: Vec<AllocationLine>,
+}
+
+impl AllocationInput {
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if !(self.latitude >= -90.0 && self.latitude <= 90.0)
+            || !(self.longitude >= -180.0 && self.longitude <= 180.0)
+        {
+            return Err(format_err!("Shipping address coordinates are out of range")
+                .context(Error::InvalidInput)
+                .into());
+        }
+
+        if self.lines.is_empty() {
+            return Err(format_err!("Basket must have at least one line")
+                .context(Error::InvalidInput)
+                .into());
+        }
+
+        let mut product_ids = self
+            .lines
+            .iter()
+            .map(|v| v.product_id.0)
+            .collect::<Vec<_>>();
+        product_ids.sort();
+        product_ids.dedup();
+        if product_ids.len() != self.lines.len() {
+            return Err(format_err!("Each product may appear in a basket only once")
+                .context(Error::InvalidInput)
+                .into());
+        }
+
+        if let Some(line) = self.lines.iter().find(|v| v.quantity.0 <= 0) {
+            return Err(
+                format_err!("Quantity of product {} must be positive", line.product_id)
+                    .context(Error::InvalidInput)
+                    .into(),
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn destination(&self) -> GeoPoint<f64> {
+        GeoPoint::new(self.longitude, self.latitude)
+    }
+}
+
+/// Units of a product that can be promised from a warehouse, i.e. on-hand minus active reservations.
+pub struct AvailableQuantity(pub WarehouseId, pub ProductId, pub Quantity);
+
+impl From<Row> for AvailableQuantity {
+    fn from(row: Row) -> Self {
+        AvailableQuantity(
+            WarehouseId(row.get(WAREHOUSE_ID_COLUMN)),
+            ProductId(row.get(PRODUCT_ID_COLUMN)),
+            Quantity(row.get(QUANTITY_COLUMN)),
+        )
+    }
+}
+
+/// Warehouse that may take part in the allocation.
+#[derive(Clone, Debug)]
+pub struct AllocationCandidate {
+    pub warehouse_id: WarehouseId,
+    /// Distance to the shipping address, `None` if the warehouse has no location.
+    pub distance_meters: Option<f64>,
+    pub available: HashMap<ProductId, Quantity>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AllocationShipment {
+    pub warehouse_id: WarehouseId,
+    pub distance_meters: Option<f64>,
+    pub lines: Vec<AllocationLine>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AllocationPlan {
+    pub shipments: Vec<AllocationShipment>,
+    /// Units that no warehouse of the store can provide.
+    pub unfulfilled: Vec<AllocationLine>,
+}
+
+/// Haversine distance between two points given as longitude/latitude.
+pub fn great_circle_distance(a: GeoPoint<f64>, b: GeoPoint<f64>) -> f64 {
+    let (lat_a, lat_b) = (a.y().to_radians(), b.y().to_radians());
+    let d_lat = (b.y() - a.y()).to_radians();
+    let d_lng = (b.x() - a.x()).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * h.sqrt().min(1.0).asin()
+}
+
+/// Closer warehouses first, warehouses without a location last.
+fn compare_distance(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Proposes shipments for the basket.
+///
+/// The nearest warehouse able to ship the whole basket wins. Otherwise warehouses are picked greedily:
+/// each round takes the one that completes the most remaining lines, then the one providing the most units,
+/// then the closest, which keeps both the number of shipments and the distance low.
+pub fn plan_allocation(
+    lines: &[AllocationLine],
+    mut candidates: Vec<AllocationCandidate>,
+) -> AllocationPlan {
+    let mut remaining = lines
+        .iter()
+        .map(|line| (line.product_id, line.quantity.0))
+        .collect::<Vec<_>>();
+    let mut shipments = vec![];
+
+    candidates.sort_by(|a, b| compare_distance(a.distance_meters, b.distance_meters));
+
+    loop {
+        let best = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, candidate)| {
+                let (complete_lines, units) = remaining
+                    .iter()
+                    .filter(|(_, wanted)| *wanted > 0)
+                    .fold((0, 0), |(complete_lines, units), (product_id, wanted)| {
+                        let available = candidate
+                            .available
+                            .get(product_id)
+                            .map(|v| v.0)
+                            .unwrap_or(0);
+                        (
+                            complete_lines + if available >= *wanted { 1 } else { 0 },
+                            units + ::std::cmp::min(::std::cmp::max(available, 0), *wanted),
+                        )
+                    });
+                (i, complete_lines, units)
+            })
+            .filter(|(_, _, units)| *units > 0)
+            // Candidates are sorted by distance, so on a tie the first one is the closest
+            .fold(
+                None,
+                |best: Option<(usize, i32, i32)>, current| match best {
+                    Some(best) if (best.1, best.2) >= (current.1, current.2) => Some(best),
+                    _ => Some(current),
+                },
+            );
+
+        let i = match best {
+            Some((i, _, _)) => i,
+            None => break,
+        };
+
+        let candidate = candidates.remove(i);
+        let mut shipment_lines = vec![];
+        for (product_id, wanted) in remaining.iter_mut().filter(|(_, wanted)| *wanted > 0) {
+            let available = candidate
+                .available
+                .get(&*product_id)
+                .map(|v| v.0)
+                .unwrap_or(0);
+            let taken = ::std::cmp::min(::std::cmp::max(available, 0), *wanted);
+            if taken > 0 {
+                *wanted -= taken;
+                shipment_lines.push(AllocationLine {
+                    product_id: *product_id,
+                    quantity: Quantity(taken),
+                });
+            }
+        }
+        shipments.push(AllocationShipment {
+            warehouse_id: candidate.warehouse_id,
+            distance_meters: candidate.distance_meters,
+            lines: shipment_lines,
+        });
+    }
+
+    AllocationPlan {
+        shipments,
+        unfulfilled: remaining
+            .into_iter()
+            .filter(|(_, wanted)| *wanted > 0)
+            .map(|(product_id, wanted)| AllocationLine {
+                product_id,
+                quantity: Quantity(wanted),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn line(product_id: i32, quantity: i32) -> AllocationLine {
+        AllocationLine {
+            product_id: ProductId(product_id),
+            quantity: Quantity(quantity),
+        }
+    }
+
+    fn candidate(distance_meters: Option<f64>, available: Vec<(i32, i32)>) -> AllocationCandidate {
+        AllocationCandidate {
+            warehouse_id: WarehouseId(Uuid::new_v4()),
+            distance_meters,
+            available: available
+                .into_iter()
+                .map(|(product_id, quantity)| (ProductId(product_id), Quantity(quantity)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn nearest_complete_warehouse_ships_everything() {
+        let far = candidate(Some(1000.0), vec![(1, 5), (2, 5)]);
+        let near = candidate(Some(10.0), vec![(1, 5), (2, 5)]);
+        let near_id = near.warehouse_id;
+
+        let plan = plan_allocation(&[line(1, 2), line(2, 3)], vec![far, near]);
+
+        assert_eq!(
+            plan,
+            AllocationPlan {
+                shipments: vec![AllocationShipment {
+                    warehouse_id: near_id,
+                    distance_meters: Some(10.0),
+                    lines: vec![line(1, 2), line(2, 3)],
+                }],
+                unfulfilled: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn complete_warehouse_wins_over_closer_partial_one() {
+        let partial = candidate(Some(10.0), vec![(1, 5)]);
+        let complete = candidate(Some(500.0), vec![(1, 5), (2, 5)]);
+        let complete_id = complete.warehouse_id;
+
+        let plan = plan_allocation(&[line(1, 1), line(2, 1)], vec![partial, complete]);
+
+        assert_eq!(plan.shipments.len(), 1);
+        assert_eq!(plan.shipments[0].warehouse_id, complete_id);
+        assert!(plan.unfulfilled.is_empty());
+    }
+
+    #[test]
+    fn basket_is_split_and_shortage_reported() {
+        let first = candidate(Some(10.0), vec![(1, 3)]);
+        let second = candidate(None, vec![(1, 4), (2, -2)]);
+        let (first_id, second_id) = (first.warehouse_id, second.warehouse_id);
+
+        let plan = plan_allocation(&[line(1, 10), line(2, 1)], vec![second, first]);
+
+        assert_eq!(
+            plan,
+            AllocationPlan {
+                shipments: vec![
+                    AllocationShipment {
+                        warehouse_id: second_id,
+                        distance_meters: None,
+                        lines: vec![line(1, 4)],
+                    },
+                    AllocationShipment {
+                        warehouse_id: first_id,
+                        distance_meters: Some(10.0),
+                        lines: vec![line(1, 3)],
+                    },
+                ],
+                unfulfilled: vec![line(1, 3), line(2, 1)],
+            }
+        );
+    }
+
+    #[test]
+    fn nothing_available_leaves_basket_unfulfilled() {
+        let plan = plan_allocation(&[line(1, 1)], vec![candidate(Some(1.0), vec![(2, 5)])]);
+
+        assert!(plan.shipments.is_empty());
+        assert_eq!(plan.unfulfilled, vec![line(1, 1)]);
+    }
+
+    #[test]
+    fn great_circle_distance_is_symmetric_and_zero_for_same_point() {
+        let berlin = GeoPoint::new(13.405, 52.52);
+        let paris = GeoPoint::new(2.3522, 48.8566);
+
+        assert_eq!(great_circle_distance(berlin, berlin), 0.0);
+        let distance = great_circle_distance(berlin, paris);
+        assert!((distance - great_circle_distance(paris, berlin)).abs() < 1e-6);
+        assert!((distance - 878_000.0).abs() < 5_000.0);
+    }
+}
@@ -0,0 +1,182 @@
+use errors::*;
+use models::{RepoLogin, RoleEntry, UserLogin, UserRole};
+
+use chrono::prelude::*;
+use failure;
+use rand::{self, Rng};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+use uuid::Uuid;
+
+const ID_COLUMN: &str = "id";
+const NAME_COLUMN: &str = "name";
+const STORE_ID_COLUMN: &str = "store_id";
+const WAREHOUSE_IDS_COLUMN: &str = "warehouse_ids";
+const SCOPE_COLUMN: &str = "scope";
+const CREATED_BY_COLUMN: &str = "created_by";
+const CREATED_AT_COLUMN: &str = "created_at";
+const REVOKED_AT_COLUMN: &str = "revoked_at";
+
+/// Distinguishes API keys from other secrets, e.g. in leaked credential scans.
+const KEY_PREFIX: &str = "wk_";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ApiKeyId(pub Uuid);
+
+impl ApiKeyId {
+    pub fn new() -> Self {
+        ApiKeyId(Uuid::new_v4())
+    }
+}
+
+impl fmt::Display for ApiKeyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.hyphenated())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    /// Only `GET` requests.
+    Read,
+    /// Reads and changes, within the limits of the store or warehouses of the key.
+    Write,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        use self::ApiKeyScope::*;
+
+        match self {
+            Read => "read",
+            Write => "write",
+        }
+    }
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::ApiKeyScope::*;
+
+        match s {
+            "read" => Ok(Read),
+            "write" => Ok(Write),
+            other => Err(format_err!("Unknown API key scope {}", other)
+                .context(Error::ParseError)
+                .into()),
+        }
+    }
+}
+
+/// Credentials of an integration such as a store's ERP, acting on behalf of the user who created them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: ApiKeyId,
+    pub name: String,
+    pub store_id: StoreId,
+    /// Warehouses of the store the key is limited to, empty meaning the whole store.
+    pub warehouse_ids: Vec<WarehouseId>,
+    pub scope: ApiKeyScope,
+    pub created_by: UserId,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    /// Login with the roles the key grants, so that the usual ACLs apply to its requests.
+    /// Changes made with the key are credited to the key rather than to `created_by`, see `AuditActor`.
+    pub fn login(&self) -> UserLogin {
+        let roles = if self.warehouse_ids.is_empty() {
+            vec![UserRole::StoreManager(self.store_id)]
+        } else {
+            self.warehouse_ids
+                .iter()
+                .map(|v| UserRole::WarehouseManager(*v))
+                .collect()
+        };
+
+        RepoLogin::User {
+            caller_id: self.created_by,
+            caller_roles: roles
+                .into_iter()
+                .map(|role| RoleEntry {
+                    id: RoleEntryId::new(),
+                    user_id: self.created_by,
+                    role,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Row> for ApiKey {
+    fn from(row: Row) -> Self {
+        ApiKey {
+            id: ApiKeyId(row.get(ID_COLUMN)),
+            name: row.get(NAME_COLUMN),
+            store_id: StoreId(row.get(STORE_ID_COLUMN)),
+            warehouse_ids: row
+                .get::<Vec<Uuid>, _>(WAREHOUSE_IDS_COLUMN)
+                .into_iter()
+                .map(WarehouseId)
+                .collect(),
+            scope: row
+                .get::<String, _>(SCOPE_COLUMN)
+                .parse()
+                .expect("Unknown API key scope in database"),
+            created_by: UserId(row.get(CREATED_BY_COLUMN)),
+            created_at: row.get(CREATED_AT_COLUMN),
+            revoked_at: row.get(REVOKED_AT_COLUMN),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    pub store_id: StoreId,
+    #[serde(default)]
+    pub warehouse_ids: Vec<WarehouseId>,
+    pub scope: ApiKeyScope,
+}
+
+impl NewApiKey {
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if self.name.trim().is_empty() {
+            return Err(format_err!("API key name must not be empty")
+                .context(Error::InvalidInput)
+                .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Newly created key together with its secret, which is not stored and cannot be shown again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IssuedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// Generates a random secret for a new key.
+pub fn generate_api_key() -> String {
+    let bytes = rand::thread_rng().gen::<[u8; 32]>();
+    format!("{}{}", KEY_PREFIX, to_hex(&bytes))
+}
+
+/// Digest of the secret kept in the database instead of the secret itself.
+pub fn hash_api_key(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
@@ -0,0 +1,450 @@
+use errors::*;
+use models::{caller_id, ApiKeyId, Page, UserLogin};
+
+use chrono::prelude::*;
+use failure;
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+use std::fmt;
+use std::str::FromStr;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+use uuid::Uuid;
+
+const ID_COLUMN: &str = "id";
+const ACTOR_ID_COLUMN: &str = "actor_id";
+const API_KEY_ID_COLUMN: &str = "api_key_id";
+const ENTITY_TYPE_COLUMN: &str = "entity_type";
+const ENTITY_ID_COLUMN: &str = "entity_id";
+const ACTION_COLUMN: &str = "action";
+const OLD_VALUE_COLUMN: &str = "old_value";
+const NEW_VALUE_COLUMN: &str = "new_value";
+const CREATED_AT_COLUMN: &str = "created_at";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEntityType {
+    Warehouse,
+    Role,
+}
+
+impl AuditEntityType {
+    pub fn as_str(&self) -> &'static str {
+        use self::AuditEntityType::*;
+
+        match self {
+            Warehouse => "warehouse",
+            Role => "role",
+        }
+    }
+}
+
+impl fmt::Display for AuditEntityType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for AuditEntityType {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::AuditEntityType::*;
+
+        match s {
+            "warehouse" => Ok(Warehouse),
+            "role" => Ok(Role),
+            other => Err(format_err!("Unknown audit entity type {}", other)
+                .context(Error::ParseError)
+                .into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    /// Any change of an existing entity, including archiving and restoring warehouses.
+    Update,
+    Delete,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        use self::AuditAction::*;
+
+        match self {
+            Create => "create",
+            Update => "update",
+            Delete => "delete",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::AuditAction::*;
+
+        match s {
+            "create" => Ok(Create),
+            "update" => Ok(Update),
+            "delete" => Ok(Delete),
+            other => Err(format_err!("Unknown audit action {}", other)
+                .context(Error::ParseError)
+                .into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AuditEntryId(pub Uuid);
+
+impl AuditEntryId {
+    pub fn new() -> Self {
+        AuditEntryId(Uuid::new_v4())
+    }
+}
+
+/// Record of a single mutation. For updates only the changed fields are kept.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: AuditEntryId,
+    /// User who made the change, `None` for anonymous callers and API keys.
+    pub actor_id: Option<UserId>,
+    /// Key the change was made with.
+    pub api_key_id: Option<ApiKeyId>,
+    pub entity_type: AuditEntityType,
+    pub entity_id: String,
+    pub action: AuditAction,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Row> for AuditEntry {
+    fn from(row: Row) -> Self {
+        AuditEntry {
+            id: AuditEntryId(row.get(ID_COLUMN)),
+            actor_id: row.get::<Option<i32>, _>(ACTOR_ID_COLUMN).map(UserId),
+            api_key_id: row.get::<Option<Uuid>, _>(API_KEY_ID_COLUMN).map(ApiKeyId),
+            entity_type: row
+                .get::<String, _>(ENTITY_TYPE_COLUMN)
+                .parse()
+                .expect("Unknown audit entity type in database"),
+            entity_id: row.get(ENTITY_ID_COLUMN),
+            action: row
+                .get::<String, _>(ACTION_COLUMN)
+                .parse()
+                .expect("Unknown audit action in database"),
+            old_value: row.get(OLD_VALUE_COLUMN),
+            new_value: row.get(NEW_VALUE_COLUMN),
+            created_at: row.get(CREATED_AT_COLUMN),
+        }
+    }
+}
+
+/// Who makes the audited changes: the calling user, or the API key if the caller used one.
+/// Keys act on their own, so the user who created the key is not credited with their changes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AuditActor {
+    pub actor_id: Option<UserId>,
+    pub api_key_id: Option<ApiKeyId>,
+}
+
+impl AuditActor {
+    pub fn new(login: &UserLogin, api_key_id: Option<ApiKeyId>) -> Self {
+        match api_key_id {
+            Some(api_key_id) => AuditActor {
+                actor_id: None,
+                api_key_id: Some(api_key_id),
+            },
+            None => AuditActor {
+                actor_id: caller_id(login),
+                api_key_id: None,
+            },
+        }
+    }
+
+    /// Entries for the entities touched by a mutation, see `audit_changes`.
+    pub fn entries(
+        &self,
+        entity_type: AuditEntityType,
+        action: AuditAction,
+        before: Vec<Value>,
+        after: Vec<Value>,
+    ) -> Vec<AuditEntry> {
+        let created_at = Utc::now();
+        audit_changes(action, before, after)
+            .into_iter()
+            .map(|(entity_id, old_value, new_value)| AuditEntry {
+                id: AuditEntryId::new(),
+                actor_id: self.actor_id,
+                api_key_id: self.api_key_id,
+                entity_type,
+                entity_id,
+                action,
+                old_value,
+                new_value,
+                created_at,
+            })
+            .collect()
+    }
+}
+
+/// Audit log listing newest first, all filters optional.
+#[derive(Clone, Debug, Default)]
+pub struct AuditLogQuery {
+    pub entity_type: Option<AuditEntityType>,
+    pub entity_id: Option<String>,
+    pub actor_id: Option<UserId>,
+    /// Only entries made at or after this time.
+    pub from: Option<DateTime<Utc>>,
+    /// Only entries made before this time.
+    pub to: Option<DateTime<Utc>>,
+    pub page: Page,
+}
+
+/// Entities in the result of a mutation, which may be a single entity, an optional one or a list.
+pub fn audit_values<T: Serialize>(value: &T) -> Vec<Value> {
+    match serde_json::to_value(value) {
+        Ok(Value::Null) => vec![],
+        Ok(Value::Array(values)) => values,
+        Ok(value) => vec![value],
+        Err(e) => {
+            error!("Failed to serialize audited value: {}", e);
+            vec![]
+        }
+    }
+}
+
+fn entity_id(value: &Value) -> Option<String> {
+    match value.get("id") {
+        Some(Value::String(id)) => Some(id.clone()),
+        Some(Value::Null) | None => None,
+        Some(id) => Some(id.to_string()),
+    }
+}
+
+/// Keeps only the fields that differ between the two versions of an entity.
+pub fn diff_values(old: Value, new: Value) -> (Value, Value) {
+    match (old, new) {
+        (Value::Object(mut old), Value::Object(mut new)) => {
+            let keys = old.keys().chain(new.keys()).cloned().collect::<Vec<_>>();
+            let mut old_diff = Map::new();
+            let mut new_diff = Map::new();
+            for key in keys {
+                let old_field = old.remove(&key).unwrap_or(Value::Null);
+                let new_field = new.remove(&key).unwrap_or(Value::Null);
+                if old_field != new_field {
+                    old_diff.insert(key.clone(), old_field);
+                    new_diff.insert(key, new_field);
+                }
+            }
+            (Value::Object(old_diff), Value::Object(new_diff))
+        }
+        (old, new) => (old, new),
+    }
+}
+
+/// Entity id with its old and new values for each entity touched by the mutation.
+/// `before` is ignored for creations and `after` for deletions. Updates are paired by id
+/// and skipped if nothing changed.
+pub fn audit_changes(
+    action: AuditAction,
+    before: Vec<Value>,
+    after: Vec<Value>,
+) -> Vec<(String, Option<Value>, Option<Value>)> {
+    match action {
+        AuditAction::Create => after
+            .into_iter()
+            .filter_map(|new| entity_id(&new).map(|id| (id, None, Some(new))))
+            .collect(),
+        AuditAction::Delete => before
+            .into_iter()
+            .filter_map(|old| entity_id(&old).map(|id| (id, Some(old), None)))
+            .collect(),
+        AuditAction::Update => {
+            let mut before = before
+                .into_iter()
+                .filter_map(|old| entity_id(&old).map(|id| (id, old)))
+                .collect::<Vec<_>>();
+            after
+                .into_iter()
+                .filter_map(|new| {
+                    let id = entity_id(&new)?;
+                    match before.iter().position(|(old_id, _)| *old_id == id) {
+                        None => Some((id, None, Some(new))),
+                        Some(i) => {
+                            let (_, old) = before.remove(i);
+                            match diff_values(old, new) {
+                                (Value::Object(ref old), Value::Object(ref new))
+                                    if old.is_empty() && new.is_empty() =>
+                                {
+                                    None
+                                }
+                                (old, new) => Some((id, Some(old), Some(new))),
+                            }
+                        }
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn diff_keeps_only_changed_fields() {
+        let (old, new) = diff_values(
+            json(r#"{"id": 1, "name": "Main", "slug": "main", "kind": "store"}"#),
+            json(r#"{"id": 1, "name": "Central", "slug": "main", "capacity": 10}"#),
+        );
+
+        assert_eq!(
+            old,
+            json(r#"{"name": "Main", "kind": "store", "capacity": null}"#)
+        );
+        assert_eq!(
+            new,
+            json(r#"{"name": "Central", "kind": null, "capacity": 10}"#)
+        );
+    }
+
+    #[test]
+    fn diff_of_equal_objects_is_empty() {
+        let value = json(r#"{"id": 1, "location": {"x": 1.0, "y": 2.0}}"#);
+
+        assert_eq!(diff_values(value.clone(), value), (json("{}"), json("{}")));
+    }
+
+    #[test]
+    fn diff_compares_nested_values_as_a_whole() {
+        let (old, new) = diff_values(
+            json(r#"{"id": 1, "location": {"x": 1.0, "y": 2.0}}"#),
+            json(r#"{"id": 1, "location": {"x": 1.0, "y": 3.0}}"#),
+        );
+
+        assert_eq!(old, json(r#"{"location": {"x": 1.0, "y": 2.0}}"#));
+        assert_eq!(new, json(r#"{"location": {"x": 1.0, "y": 3.0}}"#));
+    }
+
+    #[test]
+    fn diff_of_non_objects_keeps_both_values() {
+        assert_eq!(
+            diff_values(json("1"), json(r#""one""#)),
+            (json("1"), json(r#""one""#))
+        );
+    }
+
+    #[test]
+    fn creations_record_new_values_only() {
+        let created = json(r#"{"id": "a", "name": "Main"}"#);
+
+        assert_eq!(
+            audit_changes(
+                AuditAction::Create,
+                vec![json(r#"{"id": "b"}"#)],
+                vec![created.clone()]
+            ),
+            vec![("a".to_string(), None, Some(created))]
+        );
+    }
+
+    #[test]
+    fn deletions_record_old_values_only() {
+        let deleted = json(r#"{"id": 7, "name": "Main"}"#);
+
+        assert_eq!(
+            audit_changes(
+                AuditAction::Delete,
+                vec![deleted.clone()],
+                vec![json(r#"{"id": 8}"#)]
+            ),
+            vec![("7".to_string(), Some(deleted), None)]
+        );
+    }
+
+    #[test]
+    fn updates_are_paired_by_id_and_diffed() {
+        assert_eq!(
+            audit_changes(
+                AuditAction::Update,
+                vec![
+                    json(r#"{"id": "a", "name": "A", "status": "open"}"#),
+                    json(r#"{"id": "b", "name": "B", "status": "open"}"#),
+                ],
+                vec![
+                    json(r#"{"id": "b", "name": "B", "status": "closed"}"#),
+                    json(r#"{"id": "a", "name": "A2", "status": "open"}"#),
+                ]
+            ),
+            vec![
+                (
+                    "b".to_string(),
+                    Some(json(r#"{"status": "open"}"#)),
+                    Some(json(r#"{"status": "closed"}"#))
+                ),
+                (
+                    "a".to_string(),
+                    Some(json(r#"{"name": "A"}"#)),
+                    Some(json(r#"{"name": "A2"}"#))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_updates_are_skipped() {
+        let value = json(r#"{"id": "a", "name": "A"}"#);
+
+        assert_eq!(
+            audit_changes(AuditAction::Update, vec![value.clone()], vec![value]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn updates_without_before_are_recorded_whole() {
+        let new = json(r#"{"id": "a", "name": "A"}"#);
+
+        assert_eq!(
+            audit_changes(AuditAction::Update, vec![], vec![new.clone()]),
+            vec![("a".to_string(), None, Some(new))]
+        );
+    }
+
+    #[test]
+    fn values_without_id_are_ignored() {
+        assert_eq!(
+            audit_changes(
+                AuditAction::Create,
+                vec![],
+                vec![json(r#"{"name": "A"}"#), json(r#"{"id": null}"#)]
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn audit_values_flattens_results() {
+        assert_eq!(audit_values(&None::<Value>), Vec::<Value>::new());
+        assert_eq!(
+            audit_values(&Some(json(r#"{"id": 1}"#))),
+            vec![json(r#"{"id": 1}"#)]
+        );
+        assert_eq!(
+            audit_values(&vec![json(r#"{"id": 1}"#), json(r#"{"id": 2}"#)]),
+            vec![json(r#"{"id": 1}"#), json(r#"{"id": 2}"#)]
+        );
+    }
+}
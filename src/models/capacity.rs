@@ -0,0 +1,179 @@
+use errors::*;
+use models::StockRecord;
+
+use failure;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+
+const USED_COLUMN: &str = "used";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProductCapacity {
+    pub product_id: ProductId,
+    pub max_quantity: Quantity,
+}
+
+/// Storage limits of a warehouse, `None` and missing products being unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WarehouseCapacity {
+    /// Total units of all products the warehouse can hold.
+    pub capacity: Option<Quantity>,
+    #[serde(default)]
+    pub product_capacities: Vec<ProductCapacity>,
+}
+
+impl WarehouseCapacity {
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if let Some(capacity) = self.capacity {
+            if capacity.0 < 0 {
+                return Err(format_err!("Capacity must not be negative")
+                    .context(Error::InvalidInput)
+                    .into());
+            }
+        }
+
+        if let Some(v) = self
+            .product_capacities
+            .iter()
+            .find(|v| v.max_quantity.0 < 0)
+        {
+            return Err(format_err!(
+                "Maximum quantity of product {} must not be negative",
+                v.product_id
+            )
+            .context(Error::InvalidInput)
+            .into());
+        }
+
+        let mut product_ids = self
+            .product_capacities
+            .iter()
+            .map(|v| v.product_id.0)
+            .collect::<Vec<_>>();
+        product_ids.sort();
+        product_ids.dedup();
+        if product_ids.len() != self.product_capacities.len() {
+            return Err(
+                format_err!("Each product may have only one maximum quantity")
+                    .context(Error::InvalidInput)
+                    .into(),
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn max_quantity(&self, product_id: ProductId) -> Option<Quantity> {
+        self.product_capacities
+            .iter()
+            .find(|v| v.product_id == product_id)
+            .map(|v| v.max_quantity)
+    }
+
+    /// Fails with `CapacityExceeded` if the product cannot be stored in the given quantity
+    /// while other products take `used_by_others` units.
+    pub fn check(
+        &self,
+        product_id: ProductId,
+        quantity: Quantity,
+        used_by_others: i64,
+    ) -> Result<(), failure::Error> {
+        if let Some(max_quantity) = self.max_quantity(product_id) {
+            if quantity.0 > max_quantity.0 {
+                return Err(format_err!(
+                    "At most {} units of product {} may be stored, got {}",
+                    max_quantity.0,
+                    product_id,
+                    quantity.0
+                )
+                .context(Error::CapacityExceeded)
+                .into());
+            }
+        }
+
+        if let Some(capacity) = self.capacity {
+            let free = i64::from(capacity.0) - used_by_others;
+            if i64::from(quantity.0) > free {
+                return Err(format_err!(
+                    "Only {} of {} units of capacity are free, got {}",
+                    ::std::cmp::max(free, 0),
+                    capacity.0,
+                    quantity.0
+                )
+                .context(Error::CapacityExceeded)
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sum of stock quantities, as returned by capacity queries.
+pub struct UsedCapacity(pub i64);
+
+impl From<Row> for UsedCapacity {
+    fn from(row: Row) -> Self {
+        UsedCapacity(row.get(USED_COLUMN))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProductUtilization {
+    pub product_id: ProductId,
+    pub quantity: Quantity,
+    pub max_quantity: Quantity,
+    pub free: Quantity,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WarehouseUtilization {
+    pub warehouse_id: WarehouseId,
+    pub capacity: Option<Quantity>,
+    /// Units of all products stored in the warehouse.
+    pub used: i64,
+    /// `None` if the warehouse has no total capacity.
+    pub free: Option<i64>,
+    /// Utilization of products with a maximum quantity.
+    pub products: Vec<ProductUtilization>,
+}
+
+impl WarehouseUtilization {
+    pub fn new(
+        warehouse_id: WarehouseId,
+        capacity: &WarehouseCapacity,
+        stocks: &[StockRecord],
+    ) -> Self {
+        let used = stocks
+            .iter()
+            .map(|v| i64::from(v.stock.quantity.0))
+            .sum::<i64>();
+        let products = capacity
+            .product_capacities
+            .iter()
+            .map(|v| {
+                let quantity = stocks
+                    .iter()
+                    .find(|stock| stock.stock.product_id == v.product_id)
+                    .map(|stock| stock.stock.quantity)
+                    .unwrap_or(Quantity(0));
+                ProductUtilization {
+                    product_id: v.product_id,
+                    quantity,
+                    max_quantity: v.max_quantity,
+                    free: Quantity(::std::cmp::max(v.max_quantity.0 - quantity.0, 0)),
+                }
+            })
+            .collect();
+
+        Self {
+            warehouse_id,
+            capacity: capacity.capacity,
+            used,
+            free: capacity
+                .capacity
+                .map(|capacity| ::std::cmp::max(i64::from(capacity.0) - used, 0)),
+            products,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use models::{DbStock, StockMovementId, StockRecord};
+
+use chrono::prelude::*;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+use uuid::Uuid;
+
+const ID_COLUMN: &str = "id";
+const STOCK_ID_COLUMN: &str = "stock_id";
+const WAREHOUSE_ID_COLUMN: &str = "warehouse_id";
+const PRODUCT_ID_COLUMN: &str = "product_id";
+const MOVEMENT_ID_COLUMN: &str = "movement_id";
+const QUANTITY_COLUMN: &str = "quantity";
+const THRESHOLD_COLUMN: &str = "threshold";
+const CREATED_AT_COLUMN: &str = "created_at";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LowStockEventId(pub Uuid);
+
+impl LowStockEventId {
+    pub fn new() -> Self {
+        LowStockEventId(Uuid::new_v4())
+    }
+}
+
+/// Stock whose quantity is at or below its effective reorder threshold.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LowStock {
+    #[serde(flatten)]
+    pub stock: StockRecord,
+    /// Stock threshold, or the warehouse default if the stock does not set one.
+    pub threshold: Quantity,
+}
+
+impl From<Row> for LowStock {
+    fn from(row: Row) -> Self {
+        let threshold = Quantity(row.get(THRESHOLD_COLUMN));
+        LowStock {
+            stock: DbStock::from(row).0,
+            threshold,
+        }
+    }
+}
+
+/// Emitted when a stock movement takes the quantity from above the threshold to or below it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LowStockEvent {
+    pub id: LowStockEventId,
+    pub stock_id: StockId,
+    pub warehouse_id: WarehouseId,
+    pub product_id: ProductId,
+    /// Movement that crossed the threshold.
+    pub movement_id: StockMovementId,
+    pub quantity: Quantity,
+    pub threshold: Quantity,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Row> for LowStockEvent {
+    fn from(row: Row) -> Self {
+        LowStockEvent {
+            id: LowStockEventId(row.get(ID_COLUMN)),
+            stock_id: StockId(row.get(STOCK_ID_COLUMN)),
+            warehouse_id: WarehouseId(row.get(WAREHOUSE_ID_COLUMN)),
+            product_id: ProductId(row.get(PRODUCT_ID_COLUMN)),
+            movement_id: StockMovementId(row.get(MOVEMENT_ID_COLUMN)),
+            quantity: Quantity(row.get(QUANTITY_COLUMN)),
+            threshold: Quantity(row.get(THRESHOLD_COLUMN)),
+            created_at: row.get(CREATED_AT_COLUMN),
+        }
+    }
+}
+
+/// Reorder threshold of a single stock, `None` falls back to the warehouse default.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockReorderThresholdPayload {
+    pub reorder_threshold: Option<Quantity>,
+}
+
+/// Reorder threshold used by stocks of the warehouse that do not set their own.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WarehouseReorderThresholdPayload {
+    pub default_reorder_threshold: Option<Quantity>,
+}
@@ -1,8 +1,53 @@
 pub mod warehouse;
 pub use self::warehouse::*;
 
+pub mod warehouse_status;
+pub use self::warehouse_status::*;
+
+pub mod opening_hours;
+pub use self::opening_hours::*;
+
+pub mod capacity;
+pub use self::capacity::*;
+
 pub mod stock;
 pub use self::stock::*;
 
+pub mod stock_csv;
+pub use self::stock_csv::*;
+
+pub mod stock_listing;
+pub use self::stock_listing::*;
+
+pub mod stock_visibility;
+pub use self::stock_visibility::*;
+
 pub mod role;
 pub use self::role::*;
+
+pub mod api_key;
+pub use self::api_key::*;
+
+pub mod audit;
+pub use self::audit::*;
+
+pub mod reservation;
+pub use self::reservation::*;
+
+pub mod stock_movement;
+pub use self::stock_movement::*;
+
+pub mod page;
+pub use self::page::*;
+
+pub mod version;
+pub use self::version::*;
+
+pub mod transfer;
+pub use self::transfer::*;
+
+pub mod low_stock;
+pub use self::low_stock::*;
+
+pub mod allocation;
+pub use self::allocation::*;
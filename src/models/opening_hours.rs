@@ -0,0 +1,374 @@
+use errors::*;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono_tz::Tz;
+use failure;
+
+/// Opening times are looked up this many days ahead before giving up.
+const MAX_LOOKAHEAD_DAYS: i64 = 400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DayOfWeek {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl From<Weekday> for DayOfWeek {
+    fn from(v: Weekday) -> Self {
+        use self::DayOfWeek::*;
+
+        match v {
+            Weekday::Mon => Monday,
+            Weekday::Tue => Tuesday,
+            Weekday::Wed => Wednesday,
+            Weekday::Thu => Thursday,
+            Weekday::Fri => Friday,
+            Weekday::Sat => Saturday,
+            Weekday::Sun => Sunday,
+        }
+    }
+}
+
+/// Local time span the warehouse is open on the given day, `closes` of midnight meaning the end of the day.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpeningInterval {
+    pub day: DayOfWeek,
+    pub opens: NaiveTime,
+    pub closes: NaiveTime,
+}
+
+impl OpeningInterval {
+    fn contains(&self, time: NaiveTime) -> bool {
+        self.opens <= time && (self.closes == NaiveTime::from_hms(0, 0, 0) || time < self.closes)
+    }
+
+    /// End of the interval on the given local date.
+    fn closes_on(&self, date: NaiveDate) -> NaiveDateTime {
+        if self.closes == NaiveTime::from_hms(0, 0, 0) {
+            date.succ().and_time(self.closes)
+        } else {
+            date.and_time(self.closes)
+        }
+    }
+}
+
+/// Weekly opening hours and holiday closures, all in the local time of the warehouse.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WarehouseSchedule {
+    /// IANA time zone name, e.g. `Europe/Moscow`.
+    pub time_zone: String,
+    pub opening_hours: Vec<OpeningInterval>,
+    /// Dates the warehouse stays closed regardless of its opening hours.
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+}
+
+impl WarehouseSchedule {
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        self.tz()?;
+
+        let mut intervals = self.opening_hours.clone();
+        intervals.sort_by_key(|v| (v.day as u8, v.opens));
+        for interval in &intervals {
+            if interval.closes <= interval.opens && interval.closes != NaiveTime::from_hms(0, 0, 0)
+            {
+                return Err(format_err!(
+                    "Opening interval on {:?} must close after it opens",
+                    interval.day
+                )
+                .context(Error::InvalidInput)
+                .into());
+            }
+        }
+        for pair in intervals.windows(2) {
+            if pair[0].day == pair[1].day && pair[0].contains(pair[1].opens) {
+                return Err(
+                    format_err!("Opening intervals on {:?} must not overlap", pair[0].day)
+                        .context(Error::InvalidInput)
+                        .into(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn tz(&self) -> Result<Tz, failure::Error> {
+        self.time_zone.parse::<Tz>().map_err(|e| {
+            format_err!("Unknown time zone {}: {}", self.time_zone, e)
+                .context(Error::InvalidInput)
+                .into()
+        })
+    }
+
+    /// Intervals of the given local date, none on holidays.
+    fn intervals_on(&self, date: NaiveDate) -> Vec<OpeningInterval> {
+        if self.holidays.contains(&date) {
+            return vec![];
+        }
+
+        let day = DayOfWeek::from(date.weekday());
+        let mut intervals = self
+            .opening_hours
+            .iter()
+            .filter(|v| v.day == day)
+            .cloned()
+            .collect::<Vec<_>>();
+        intervals.sort_by_key(|v| v.opens);
+        intervals
+    }
+
+    /// Whether the warehouse is open at the moment and when that changes.
+    pub fn opening_status(&self, at: DateTime<Utc>) -> Result<OpeningStatus, failure::Error> {
+        let tz = self.tz()?;
+        let local = at.with_timezone(&tz).naive_local();
+
+        let closes_at = self
+            .intervals_on(local.date())
+            .into_iter()
+            .find(|v| v.contains(local.time()))
+            .and_then(|v| to_utc(&tz, v.closes_on(local.date())));
+
+        let next_opening_at = if closes_at.is_some() {
+            None
+        } else {
+            let mut found = None;
+            for offset in 0..MAX_LOOKAHEAD_DAYS {
+                let date = local.date() + Duration::days(offset);
+                found = self
+                    .intervals_on(date)
+                    .into_iter()
+                    .filter_map(|v| to_utc(&tz, date.and_time(v.opens)))
+                    .find(|v| *v > at);
+                if found.is_some() {
+                    break;
+                }
+            }
+            found
+        };
+
+        Ok(OpeningStatus {
+            at,
+            open: closes_at.is_some(),
+            closes_at,
+            next_opening_at,
+        })
+    }
+}
+
+/// Converts local time to UTC, shifting times skipped by a DST change an hour later.
+fn to_utc(tz: &Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|v| v.with_timezone(&Utc))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OpeningStatus {
+    pub at: DateTime<Utc>,
+    pub open: bool,
+    /// End of the current opening interval if the warehouse is open.
+    pub closes_at: Option<DateTime<Utc>>,
+    /// Start of the next opening interval if the warehouse is closed,
+    /// `None` if it does not open within a year.
+    pub next_opening_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(day: DayOfWeek, opens: (u32, u32), closes: (u32, u32)) -> OpeningInterval {
+        OpeningInterval {
+            day,
+            opens: NaiveTime::from_hms(opens.0, opens.1, 0),
+            closes: NaiveTime::from_hms(closes.0, closes.1, 0),
+        }
+    }
+
+    fn schedule(time_zone: &str, opening_hours: Vec<OpeningInterval>) -> WarehouseSchedule {
+        WarehouseSchedule {
+            time_zone: time_zone.to_string(),
+            opening_hours,
+            holidays: vec![],
+        }
+    }
+
+    #[test]
+    fn times_skipped_by_dst_are_shifted_an_hour_later() {
+        let tz: Tz = "Europe/Berlin".parse().unwrap();
+
+        // Clocks jump from 02:00 CET to 03:00 CEST
+        let skipped = NaiveDate::from_ymd(2018, 3, 25).and_hms(2, 30, 0);
+        assert_eq!(
+            to_utc(&tz, skipped),
+            Some(Utc.ymd(2018, 3, 25).and_hms(1, 30, 0))
+        );
+
+        let regular = NaiveDate::from_ymd(2018, 3, 25).and_hms(12, 0, 0);
+        assert_eq!(
+            to_utc(&tz, regular),
+            Some(Utc.ymd(2018, 3, 25).and_hms(10, 0, 0))
+        );
+    }
+
+    #[test]
+    fn opening_at_a_skipped_time_happens_after_the_dst_change() {
+        let schedule = schedule(
+            "Europe/Berlin",
+            vec![interval(DayOfWeek::Sunday, (2, 30), (6, 0))],
+        );
+
+        let status = schedule
+            .opening_status(Utc.ymd(2018, 3, 25).and_hms(0, 0, 0))
+            .unwrap();
+        assert!(!status.open);
+        assert_eq!(
+            status.next_opening_at,
+            Some(Utc.ymd(2018, 3, 25).and_hms(1, 30, 0))
+        );
+    }
+
+    #[test]
+    fn open_interval_reports_its_closing_time() {
+        let schedule = schedule(
+            "Europe/Moscow",
+            vec![interval(DayOfWeek::Monday, (9, 0), (18, 0))],
+        );
+
+        // 12:00 in Moscow
+        let status = schedule
+            .opening_status(Utc.ymd(2018, 10, 15).and_hms(9, 0, 0))
+            .unwrap();
+        assert!(status.open);
+        assert_eq!(
+            status.closes_at,
+            Some(Utc.ymd(2018, 10, 15).and_hms(15, 0, 0))
+        );
+        assert_eq!(status.next_opening_at, None);
+    }
+
+    #[test]
+    fn interval_closing_at_midnight_lasts_until_the_end_of_the_day() {
+        let schedule = schedule("UTC", vec![interval(DayOfWeek::Friday, (18, 0), (0, 0))]);
+
+        let status = schedule
+            .opening_status(Utc.ymd(2018, 10, 19).and_hms(23, 30, 0))
+            .unwrap();
+        assert!(status.open);
+        assert_eq!(
+            status.closes_at,
+            Some(Utc.ymd(2018, 10, 20).and_hms(0, 0, 0))
+        );
+
+        let status = schedule
+            .opening_status(Utc.ymd(2018, 10, 20).and_hms(0, 0, 0))
+            .unwrap();
+        assert!(!status.open);
+        assert_eq!(
+            status.next_opening_at,
+            Some(Utc.ymd(2018, 10, 26).and_hms(18, 0, 0))
+        );
+    }
+
+    #[test]
+    fn closed_warehouse_reports_the_next_opening() {
+        let schedule = schedule(
+            "UTC",
+            vec![
+                interval(DayOfWeek::Monday, (9, 0), (13, 0)),
+                interval(DayOfWeek::Monday, (14, 0), (18, 0)),
+            ],
+        );
+
+        // Before opening on the same day
+        let status = schedule
+            .opening_status(Utc.ymd(2018, 10, 15).and_hms(7, 0, 0))
+            .unwrap();
+        assert!(!status.open);
+        assert_eq!(status.closes_at, None);
+        assert_eq!(
+            status.next_opening_at,
+            Some(Utc.ymd(2018, 10, 15).and_hms(9, 0, 0))
+        );
+
+        // During the lunch break
+        let status = schedule
+            .opening_status(Utc.ymd(2018, 10, 15).and_hms(13, 30, 0))
+            .unwrap();
+        assert!(!status.open);
+        assert_eq!(
+            status.next_opening_at,
+            Some(Utc.ymd(2018, 10, 15).and_hms(14, 0, 0))
+        );
+
+        // Over the weekend
+        let status = schedule
+            .opening_status(Utc.ymd(2018, 10, 20).and_hms(10, 0, 0))
+            .unwrap();
+        assert_eq!(
+            status.next_opening_at,
+            Some(Utc.ymd(2018, 10, 22).and_hms(9, 0, 0))
+        );
+    }
+
+    #[test]
+    fn warehouse_stays_closed_on_holidays() {
+        let mut schedule = schedule("UTC", vec![interval(DayOfWeek::Monday, (9, 0), (18, 0))]);
+        schedule.holidays = vec![NaiveDate::from_ymd(2018, 10, 15)];
+
+        let status = schedule
+            .opening_status(Utc.ymd(2018, 10, 15).and_hms(10, 0, 0))
+            .unwrap();
+        assert!(!status.open);
+        assert_eq!(status.closes_at, None);
+        assert_eq!(
+            status.next_opening_at,
+            Some(Utc.ymd(2018, 10, 22).and_hms(9, 0, 0))
+        );
+    }
+
+    #[test]
+    fn schedule_without_opening_hours_never_opens() {
+        let status = schedule("UTC", vec![])
+            .opening_status(Utc.ymd(2018, 10, 15).and_hms(10, 0, 0))
+            .unwrap();
+        assert!(!status.open);
+        assert_eq!(status.next_opening_at, None);
+    }
+
+    #[test]
+    fn overlapping_intervals_and_unknown_time_zones_are_rejected() {
+        assert!(schedule("Mars/Olympus", vec![]).validate().is_err());
+        assert!(schedule(
+            "UTC",
+            vec![
+                interval(DayOfWeek::Monday, (9, 0), (13, 0)),
+                interval(DayOfWeek::Monday, (12, 0), (18, 0)),
+            ],
+        )
+        .validate()
+        .is_err());
+        assert!(
+            schedule("UTC", vec![interval(DayOfWeek::Monday, (18, 0), (9, 0))])
+                .validate()
+                .is_err()
+        );
+        assert!(
+            schedule("UTC", vec![interval(DayOfWeek::Monday, (18, 0), (0, 0))])
+                .validate()
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 500;
+
+/// Offset based window into a listing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page {
+    pub offset: i64,
+    pub count: i64,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            count: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+impl Page {
+    /// Clamps values supplied by callers to sane bounds.
+    pub fn normalized(self) -> Self {
+        Self {
+            offset: ::std::cmp::max(self.offset, 0),
+            count: ::std::cmp::min(::std::cmp::max(self.count, 1), MAX_PAGE_SIZE),
+        }
+    }
+}
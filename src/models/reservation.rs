@@ -0,0 +1,219 @@
+use errors::*;
+
+use chrono::prelude::*;
+use failure;
+use std::fmt;
+use std::str::FromStr;
+use stq_api::types::ValueContainer;
+use stq_db::statement::*;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+use uuid::{self, Uuid};
+
+const ID_COLUMN: &str = "id";
+const WAREHOUSE_ID_COLUMN: &str = "warehouse_id";
+const PRODUCT_ID_COLUMN: &str = "product_id";
+const ORDER_ID_COLUMN: &str = "order_id";
+const QUANTITY_COLUMN: &str = "quantity";
+const STATUS_COLUMN: &str = "status";
+const EXPIRES_AT_COLUMN: &str = "expires_at";
+const CREATED_AT_COLUMN: &str = "created_at";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReservationId(pub Uuid);
+
+impl ReservationId {
+    pub fn new() -> Self {
+        ReservationId(Uuid::new_v4())
+    }
+}
+
+impl fmt::Display for ReservationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ReservationId {
+    type Err = uuid::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ReservationId(s.parse()?))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservationStatus {
+    /// Units are set aside and count against available quantity until `expires_at`.
+    Held,
+    /// Units were taken from stock.
+    Committed,
+    /// Units were returned to available quantity.
+    Released,
+}
+
+impl ReservationStatus {
+    pub fn as_str(&self) -> &'static str {
+        use self::ReservationStatus::*;
+
+        match self {
+            Held => "held",
+            Committed => "committed",
+            Released => "released",
+        }
+    }
+}
+
+impl FromStr for ReservationStatus {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::ReservationStatus::*;
+
+        match s {
+            "held" => Ok(Held),
+            "committed" => Ok(Committed),
+            "released" => Ok(Released),
+            other => Err(format_err!("Unknown reservation status {}", other)
+                .context(Error::ParseError)
+                .into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub id: ReservationId,
+    pub warehouse_id: WarehouseId,
+    pub product_id: ProductId,
+    /// Order or cart the units are held for.
+    pub order_id: String,
+    pub quantity: Quantity,
+    pub status: ReservationStatus,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Reservation {
+    /// Whether the reservation still counts against available quantity at the given moment.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.status == ReservationStatus::Held && self.expires_at > now
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReservationInput {
+    pub warehouse_id: WarehouseId,
+    pub product_id: ProductId,
+    pub order_id: String,
+    pub quantity: Quantity,
+    /// How long the hold lasts before it is released automatically.
+    pub ttl_seconds: u32,
+}
+
+pub struct DbReservation(pub Reservation);
+
+impl From<Row> for DbReservation {
+    fn from(row: Row) -> Self {
+        DbReservation(Reservation {
+            id: ReservationId(row.get(ID_COLUMN)),
+            warehouse_id: WarehouseId(row.get(WAREHOUSE_ID_COLUMN)),
+            product_id: ProductId(row.get(PRODUCT_ID_COLUMN)),
+            order_id: row.get(ORDER_ID_COLUMN),
+            quantity: Quantity(row.get(QUANTITY_COLUMN)),
+            status: row
+                .get::<String, _>(STATUS_COLUMN)
+                .parse()
+                .expect("Unknown reservation status in database"),
+            expires_at: row.get(EXPIRES_AT_COLUMN),
+            created_at: row.get(CREATED_AT_COLUMN),
+        })
+    }
+}
+
+impl Inserter for DbReservation {
+    fn into_insert_builder(self, table: &'static str) -> InsertBuilder {
+        InsertBuilder::new(table)
+            .with_arg(ID_COLUMN, self.0.id.0)
+            .with_arg(WAREHOUSE_ID_COLUMN, self.0.warehouse_id.0)
+            .with_arg(PRODUCT_ID_COLUMN, self.0.product_id.0)
+            .with_arg(ORDER_ID_COLUMN, self.0.order_id)
+            .with_arg(QUANTITY_COLUMN, self.0.quantity.0)
+            .with_arg(STATUS_COLUMN, self.0.status.as_str().to_string())
+            .with_arg(EXPIRES_AT_COLUMN, self.0.expires_at)
+            .with_arg(CREATED_AT_COLUMN, self.0.created_at)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReservationFilter {
+    pub id: Option<ValueContainer<ReservationId>>,
+    pub warehouse_id: Option<ValueContainer<WarehouseId>>,
+    pub product_id: Option<ValueContainer<ProductId>>,
+    pub order_id: Option<ValueContainer<String>>,
+    pub status: Option<ValueContainer<ReservationStatus>>,
+}
+
+impl Filter for ReservationFilter {
+    fn into_filtered_operation_builder(self, table: &'static str) -> FilteredOperationBuilder {
+        let mut b = FilteredOperationBuilder::new(table);
+
+        if let Some(id) = self.id {
+            b = b.with_filter(ID_COLUMN, id.value.0);
+        }
+
+        if let Some(warehouse_id) = self.warehouse_id {
+            b = b.with_filter(WAREHOUSE_ID_COLUMN, warehouse_id.value.0);
+        }
+
+        if let Some(product_id) = self.product_id {
+            b = b.with_filter(PRODUCT_ID_COLUMN, product_id.value.0);
+        }
+
+        if let Some(order_id) = self.order_id {
+            b = b.with_filter(ORDER_ID_COLUMN, order_id.value);
+        }
+
+        if let Some(status) = self.status {
+            b = b.with_filter(STATUS_COLUMN, status.value.as_str().to_string());
+        }
+
+        b
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReservationUpdateData {
+    pub status: Option<ValueContainer<ReservationStatus>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReservationUpdater {
+    pub mask: ReservationFilter,
+    pub data: ReservationUpdateData,
+}
+
+impl Updater for ReservationUpdater {
+    fn into_update_builder(self, table: &'static str) -> UpdateBuilder {
+        let mut b = UpdateBuilder::from(self.mask.into_filtered_operation_builder(table));
+
+        if let Some(status) = self.data.status {
+            b = b.with_value(STATUS_COLUMN, status.value.as_str().to_string());
+        }
+
+        b
+    }
+}
+
+/// Quantity of a product held by active reservations.
+pub struct HeldQuantity(pub ProductId, pub Quantity);
+
+impl From<Row> for HeldQuantity {
+    fn from(row: Row) -> Self {
+        HeldQuantity(
+            ProductId(row.get(PRODUCT_ID_COLUMN)),
+            Quantity(row.get(QUANTITY_COLUMN)),
+        )
+    }
+}
@@ -10,6 +10,7 @@ use stq_types::*;
 pub enum UserRoleName {
     Superadmin,
     StoreManager,
+    WarehouseManager,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -17,6 +18,8 @@ pub enum UserRoleName {
 pub enum UserRole {
     Superadmin,
     StoreManager(StoreId),
+    /// Manages stock of a single warehouse, e.g. a picker.
+    WarehouseManager(WarehouseId),
 }
 
 impl stq_roles::models::RoleModel for UserRole {
@@ -35,6 +38,7 @@ impl stq_roles::models::RoleModel for UserRole {
         match variant {
             "superadmin" => Ok(Superadmin),
             "store_manager" => Ok(StoreManager(from_value(data)?)),
+            "warehouse_manager" => Ok(WarehouseManager(from_value(data)?)),
             other => Err(format_err!("Unknown variant {}", other)
                 .context(Error::ParseError)
                 .into()),
@@ -47,6 +51,7 @@ impl stq_roles::models::RoleModel for UserRole {
         match self {
             Superadmin => ("superadmin".into(), Value::Null),
             StoreManager(data) => ("store_manager".into(), to_value(data).unwrap()),
+            WarehouseManager(data) => ("warehouse_manager".into(), to_value(data).unwrap()),
         }
     }
 }
@@ -55,3 +60,11 @@ pub type RoleEntry = stq_roles::models::RoleEntry<UserRole>;
 pub type RoleFilter = stq_roles::models::RoleFilter<UserRole>;
 
 pub type UserLogin = stq_roles::models::RepoLogin<UserRole>;
+
+/// Id of the calling user, `None` for anonymous callers.
+pub fn caller_id(login: &UserLogin) -> Option<UserId> {
+    match login {
+        RepoLogin::User { caller_id, .. } => Some(*caller_id),
+        RepoLogin::Anonymous => None,
+    }
+}
@@ -1,3 +1,7 @@
+use models::{QuantityPrecision, Version};
+
+use chrono::prelude::*;
+use std::collections::HashMap;
 use stq_api::{types::ValueContainer, warehouses::*};
 use stq_db::statement::*;
 use stq_types::*;
@@ -7,31 +11,157 @@ const ID_COLUMN: &str = "id";
 const WAREHOUSE_ID_COLUMN: &str = "warehouse_id";
 const PRODUCT_ID_COLUMN: &str = "product_id";
 const QUANTITY_COLUMN: &str = "quantity";
+const VERSION_COLUMN: &str = "version";
+const REORDER_THRESHOLD_COLUMN: &str = "reorder_threshold";
+const CREATED_AT_COLUMN: &str = "created_at";
+const UPDATED_AT_COLUMN: &str = "updated_at";
+
+/// Maximum number of lines accepted by a single bulk upsert.
+pub const MAX_BULK_UPSERT_LINES: usize = 5000;
+
+/// Stock together with the attributes maintained by this service on top of the shared API model.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockRecord {
+    #[serde(flatten)]
+    pub stock: Stock,
+    pub version: Version,
+    /// Quantity at or below which the product should be reordered, overrides the warehouse default.
+    pub reorder_threshold: Option<Quantity>,
+    /// Readers who do not manage the stock get a masked `quantity`.
+    #[serde(default)]
+    pub precision: QuantityPrecision,
+    pub created_at: DateTime<Utc>,
+    /// Time of the last change, for incremental sync.
+    pub updated_at: DateTime<Utc>,
+}
 
-pub struct DbStock(pub Stock);
+impl StockRecord {
+    pub fn new(stock: Stock) -> Self {
+        let now = Utc::now();
+        Self {
+            stock,
+            version: Version::initial(),
+            reorder_threshold: None,
+            precision: QuantityPrecision::Exact,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+pub struct DbStock(pub StockRecord);
 
 impl From<Row> for DbStock {
     fn from(row: Row) -> Self {
-        DbStock(Stock {
-            id: StockId(row.get(ID_COLUMN)),
-            warehouse_id: WarehouseId(row.get(WAREHOUSE_ID_COLUMN)),
-            product_id: ProductId(row.get(PRODUCT_ID_COLUMN)),
-            quantity: Quantity(row.get(QUANTITY_COLUMN)),
+        DbStock(StockRecord {
+            stock: Stock {
+                id: StockId(row.get(ID_COLUMN)),
+                warehouse_id: WarehouseId(row.get(WAREHOUSE_ID_COLUMN)),
+                product_id: ProductId(row.get(PRODUCT_ID_COLUMN)),
+                quantity: Quantity(row.get(QUANTITY_COLUMN)),
+            },
+            version: Version(row.get(VERSION_COLUMN)),
+            reorder_threshold: row
+                .get::<Option<i32>, _>(REORDER_THRESHOLD_COLUMN)
+                .map(Quantity),
+            precision: QuantityPrecision::Exact,
+            created_at: row.get(CREATED_AT_COLUMN),
+            updated_at: row.get(UPDATED_AT_COLUMN),
         })
     }
 }
 
 impl Inserter for DbStock {
     fn into_insert_builder(self, table: &'static str) -> InsertBuilder {
-        InsertBuilder::new(table)
-            .with_arg(ID_COLUMN, self.0.id.0)
-            .with_arg(PRODUCT_ID_COLUMN, self.0.product_id.0)
-            .with_arg(QUANTITY_COLUMN, self.0.quantity.0)
-            .with_arg(WAREHOUSE_ID_COLUMN, self.0.warehouse_id.0)
-            .with_extra("ON CONFLICT (warehouse_id, product_id) DO UPDATE SET quantity = $3")
+        let StockRecord {
+            stock,
+            reorder_threshold,
+            ..
+        } = self.0;
+        let mut b = InsertBuilder::new(table)
+            .with_arg(ID_COLUMN, stock.id.0)
+            .with_arg(PRODUCT_ID_COLUMN, stock.product_id.0)
+            .with_arg(QUANTITY_COLUMN, stock.quantity.0)
+            .with_arg(WAREHOUSE_ID_COLUMN, stock.warehouse_id.0);
+
+        if let Some(reorder_threshold) = reorder_threshold {
+            b = b.with_arg(REORDER_THRESHOLD_COLUMN, reorder_threshold.0);
+        }
+
+        b.with_extra("ON CONFLICT (warehouse_id, product_id) DO UPDATE SET quantity = $3")
+    }
+}
+
+/// Relative change of stock quantity, e.g. `12` for received or `-3` for picked units.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockAdjustPayload {
+    pub delta: i32,
+}
+
+/// Absolute quantity of a product in a warehouse, one line of a bulk upsert.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockUpsertLine {
+    pub warehouse_id: WarehouseId,
+    pub product_id: ProductId,
+    pub quantity: Quantity,
+}
+
+/// Outcome of a bulk upsert line: either the stored stock or the reason the line was rejected.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockUpsertResult {
+    #[serde(flatten)]
+    pub line: StockUpsertLine,
+    pub stock: Option<StockRecord>,
+    pub error: Option<String>,
+}
+
+impl StockUpsertResult {
+    pub fn applied(line: StockUpsertLine, stock: StockRecord) -> Self {
+        Self {
+            line,
+            stock: Some(stock),
+            error: None,
+        }
+    }
+
+    pub fn rejected(line: StockUpsertLine, error: String) -> Self {
+        Self {
+            line,
+            stock: None,
+            error: Some(error),
+        }
     }
 }
 
+/// Validates bulk upsert lines on their own, returning the rejection reason for each invalid line.
+pub fn check_upsert_lines(lines: &[StockUpsertLine]) -> Vec<Option<String>> {
+    let mut occurrences = HashMap::new();
+    for line in lines {
+        *occurrences
+            .entry((line.warehouse_id, line.product_id))
+            .or_insert(0) += 1;
+    }
+
+    lines
+        .iter()
+        .map(|line| {
+            if line.quantity.0 < 0 {
+                Some(format!(
+                    "Quantity of product {} must not be negative",
+                    line.product_id
+                ))
+            } else if occurrences[&(line.warehouse_id, line.product_id)] > 1 {
+                Some(format!(
+                    "Product {} appears in warehouse {} more than once",
+                    line.product_id, line.warehouse_id
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct StockFilter {
     pub id: Option<ValueContainer<StockId>>,
@@ -67,6 +197,7 @@ impl Filter for StockFilter {
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct StockUpdateData {
     pub quantity: Option<ValueContainer<Quantity>>,
+    pub reorder_threshold: Option<ValueContainer<Option<Quantity>>>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -83,6 +214,13 @@ impl Updater for StockUpdater {
             b = b.with_value(QUANTITY_COLUMN, quantity.value.0);
         }
 
+        if let Some(reorder_threshold) = self.data.reorder_threshold {
+            b = b.with_value(
+                REORDER_THRESHOLD_COLUMN,
+                reorder_threshold.value.map(|v| v.0),
+            );
+        }
+
         b
     }
 }
@@ -0,0 +1,112 @@
+use errors::*;
+use models::{StockRecord, Version, MAX_BULK_UPSERT_LINES};
+
+use csv;
+use failure::{self, ResultExt};
+use stq_types::*;
+
+/// Row of a stock export, `quantity` being the one to edit and upload back.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StockCsvRecord {
+    pub product_id: ProductId,
+    pub quantity: Quantity,
+    /// On-hand quantity minus active reservations.
+    pub available: Quantity,
+    pub stock_id: StockId,
+    pub warehouse_id: WarehouseId,
+    pub version: Version,
+}
+
+impl StockCsvRecord {
+    pub fn new(stock: StockRecord, available: Quantity) -> Self {
+        Self {
+            product_id: stock.stock.product_id,
+            quantity: stock.stock.quantity,
+            available,
+            stock_id: stock.stock.id,
+            warehouse_id: stock.stock.warehouse_id,
+            version: stock.version,
+        }
+    }
+}
+
+/// Columns read from an uploaded file, other columns are ignored.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct StockCsvImportRow {
+    pub product_id: ProductId,
+    pub quantity: Quantity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StockImportOutcome {
+    Created,
+    Changed,
+    Unchanged,
+    Rejected,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockImportRow {
+    /// Number of the data row in the file, starting with 1.
+    pub row: usize,
+    pub product_id: Option<ProductId>,
+    pub quantity: Option<Quantity>,
+    pub quantity_before: Option<Quantity>,
+    pub outcome: StockImportOutcome,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockImportReport {
+    /// Whether the report only describes the changes without applying them.
+    pub dry_run: bool,
+    pub rows: Vec<StockImportRow>,
+}
+
+pub fn stocks_to_csv(records: Vec<StockCsvRecord>) -> Result<String, failure::Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for record in records {
+        writer.serialize(record)?;
+    }
+    let data = writer
+        .into_inner()
+        .map_err(|e| format_err!("Failed to flush CSV writer: {}", e))?;
+
+    Ok(String::from_utf8(data)?)
+}
+
+/// Parses an uploaded file, keeping the reason for every row that could not be read.
+pub fn parse_stock_csv(
+    data: &[u8],
+) -> Result<Vec<Result<StockCsvImportRow, String>>, failure::Error> {
+    let mut reader = csv::Reader::from_reader(data);
+    let headers = reader
+        .headers()
+        .context("Failed to read CSV header")
+        .context(Error::ParseError)?
+        .clone();
+    for column in &["product_id", "quantity"] {
+        if !headers.iter().any(|header| header == *column) {
+            return Err(format_err!("CSV header lacks column {}", column)
+                .context(Error::ParseError)
+                .into());
+        }
+    }
+
+    let rows = reader
+        .deserialize::<StockCsvImportRow>()
+        .map(|row| row.map_err(|e| e.to_string()))
+        .collect::<Vec<_>>();
+    if rows.len() > MAX_BULK_UPSERT_LINES {
+        return Err(format_err!(
+            "CSV import accepts at most {} rows, got {}",
+            MAX_BULK_UPSERT_LINES,
+            rows.len()
+        )
+        .context(Error::InvalidInput)
+        .into());
+    }
+
+    Ok(rows)
+}
@@ -0,0 +1,127 @@
+use errors::*;
+use models::{Page, StockRecord};
+
+use chrono::prelude::*;
+use failure::{self, ResultExt};
+use std::fmt;
+use std::str::FromStr;
+use stq_types::*;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StockSortField {
+    ProductId,
+    Quantity,
+}
+
+impl StockSortField {
+    pub fn column(&self) -> &'static str {
+        use self::StockSortField::*;
+
+        match self {
+            ProductId => "product_id",
+            Quantity => "quantity",
+        }
+    }
+
+    pub fn key(&self, stock: &StockRecord) -> i32 {
+        use self::StockSortField::*;
+
+        match self {
+            ProductId => stock.stock.product_id.0,
+            Quantity => stock.stock.quantity.0,
+        }
+    }
+}
+
+impl FromStr for StockSortField {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::StockSortField::*;
+
+        match s {
+            "product_id" => Ok(ProductId),
+            "quantity" => Ok(Quantity),
+            other => Err(format_err!("Unknown stock sort field {}", other)
+                .context(Error::ParseError)
+                .into()),
+        }
+    }
+}
+
+/// Position after the last returned stock, opaque to clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StockCursor {
+    pub key: i32,
+    pub id: StockId,
+}
+
+impl fmt::Display for StockCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.key, self.id.0)
+    }
+}
+
+impl FromStr for StockCursor {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '.');
+        let key = parts.next().unwrap_or_default();
+        let id = parts.next().unwrap_or_default();
+        Ok(StockCursor {
+            key: key
+                .parse()
+                .context(format!("Invalid stock cursor {}", s))
+                .context(Error::ParseError)?,
+            id: StockId(
+                id.parse::<Uuid>()
+                    .context(format!("Invalid stock cursor {}", s))
+                    .context(Error::ParseError)?,
+            ),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StockListQuery {
+    pub store_id: Option<StoreId>,
+    /// Empty means all warehouses.
+    pub warehouse_ids: Vec<WarehouseId>,
+    /// Empty means all products.
+    pub product_ids: Vec<ProductId>,
+    pub min_quantity: Option<Quantity>,
+    pub max_quantity: Option<Quantity>,
+    /// Only stocks changed at or after this time.
+    pub updated_since: Option<DateTime<Utc>>,
+    pub sort: StockSortField,
+    pub descending: bool,
+    pub after: Option<StockCursor>,
+    /// Page size, only `count` is used as pages are addressed by the `after` cursor.
+    pub page: Page,
+}
+
+impl Default for StockListQuery {
+    fn default() -> Self {
+        Self {
+            store_id: None,
+            warehouse_ids: vec![],
+            product_ids: vec![],
+            min_quantity: None,
+            max_quantity: None,
+            updated_since: None,
+            sort: StockSortField::ProductId,
+            descending: false,
+            after: None,
+            page: Page::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockPage {
+    pub items: Vec<StockRecord>,
+    /// Pass as `after` to get the next page, `None` on the last page.
+    pub next_cursor: Option<String>,
+}
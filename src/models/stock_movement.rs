@@ -0,0 +1,243 @@
+use errors::*;
+use models::{ApiKeyId, AuditActor};
+
+use chrono::prelude::*;
+use failure;
+use std::str::FromStr;
+use stq_api::types::ValueContainer;
+use stq_api::warehouses::Stock;
+use stq_db::statement::*;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+use uuid::Uuid;
+
+const ID_COLUMN: &str = "id";
+const STOCK_ID_COLUMN: &str = "stock_id";
+const WAREHOUSE_ID_COLUMN: &str = "warehouse_id";
+const PRODUCT_ID_COLUMN: &str = "product_id";
+const QUANTITY_BEFORE_COLUMN: &str = "quantity_before";
+const QUANTITY_AFTER_COLUMN: &str = "quantity_after";
+const DELTA_COLUMN: &str = "delta";
+const USER_ID_COLUMN: &str = "user_id";
+const API_KEY_ID_COLUMN: &str = "api_key_id";
+const REASON_COLUMN: &str = "reason";
+const CREATED_AT_COLUMN: &str = "created_at";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StockMovementId(pub Uuid);
+
+impl StockMovementId {
+    pub fn new() -> Self {
+        StockMovementId(Uuid::new_v4())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StockMovementReason {
+    /// Quantity was overwritten with an absolute value.
+    Set,
+    /// Held units were taken by a committed reservation.
+    ReservationCommit,
+    /// Quantity was changed by a relative amount.
+    Adjustment,
+    /// Units left the warehouse with a shipped transfer.
+    TransferShipment,
+    /// Units arrived with a received transfer.
+    TransferReceipt,
+}
+
+impl StockMovementReason {
+    pub fn as_str(&self) -> &'static str {
+        use self::StockMovementReason::*;
+
+        match self {
+            Set => "set",
+            ReservationCommit => "reservation_commit",
+            Adjustment => "adjustment",
+            TransferShipment => "transfer_shipment",
+            TransferReceipt => "transfer_receipt",
+        }
+    }
+}
+
+impl FromStr for StockMovementReason {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::StockMovementReason::*;
+
+        match s {
+            "set" => Ok(Set),
+            "reservation_commit" => Ok(ReservationCommit),
+            "adjustment" => Ok(Adjustment),
+            "transfer_shipment" => Ok(TransferShipment),
+            "transfer_receipt" => Ok(TransferReceipt),
+            other => Err(format_err!("Unknown stock movement reason {}", other)
+                .context(Error::ParseError)
+                .into()),
+        }
+    }
+}
+
+/// Single change of stock quantity. Movements are never updated or deleted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StockMovement {
+    pub id: StockMovementId,
+    pub stock_id: StockId,
+    pub warehouse_id: WarehouseId,
+    pub product_id: ProductId,
+    pub quantity_before: Quantity,
+    pub quantity_after: Quantity,
+    pub delta: i32,
+    /// Acting user, `None` for anonymous callers and API keys.
+    pub user_id: Option<UserId>,
+    /// Acting API key.
+    pub api_key_id: Option<ApiKeyId>,
+    pub reason: StockMovementReason,
+    pub created_at: DateTime<Utc>,
+}
+
+impl StockMovement {
+    pub fn new(
+        after: &Stock,
+        quantity_before: Quantity,
+        actor: AuditActor,
+        reason: StockMovementReason,
+    ) -> Self {
+        Self {
+            id: StockMovementId::new(),
+            stock_id: after.id,
+            warehouse_id: after.warehouse_id,
+            product_id: after.product_id,
+            quantity_before,
+            quantity_after: after.quantity,
+            delta: after.quantity.0 - quantity_before.0,
+            user_id: actor.actor_id,
+            api_key_id: actor.api_key_id,
+            reason,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+pub struct DbStockMovement(pub StockMovement);
+
+impl From<Row> for DbStockMovement {
+    fn from(row: Row) -> Self {
+        DbStockMovement(StockMovement {
+            id: StockMovementId(row.get(ID_COLUMN)),
+            stock_id: StockId(row.get(STOCK_ID_COLUMN)),
+            warehouse_id: WarehouseId(row.get(WAREHOUSE_ID_COLUMN)),
+            product_id: ProductId(row.get(PRODUCT_ID_COLUMN)),
+            quantity_before: Quantity(row.get(QUANTITY_BEFORE_COLUMN)),
+            quantity_after: Quantity(row.get(QUANTITY_AFTER_COLUMN)),
+            delta: row.get(DELTA_COLUMN),
+            user_id: row.get::<Option<i32>, _>(USER_ID_COLUMN).map(UserId),
+            api_key_id: row.get::<Option<Uuid>, _>(API_KEY_ID_COLUMN).map(ApiKeyId),
+            reason: row
+                .get::<String, _>(REASON_COLUMN)
+                .parse()
+                .expect("Unknown stock movement reason in database"),
+            created_at: row.get(CREATED_AT_COLUMN),
+        })
+    }
+}
+
+impl Inserter for DbStockMovement {
+    fn into_insert_builder(self, table: &'static str) -> InsertBuilder {
+        let mut b = InsertBuilder::new(table)
+            .with_arg(ID_COLUMN, self.0.id.0)
+            .with_arg(STOCK_ID_COLUMN, self.0.stock_id.0)
+            .with_arg(WAREHOUSE_ID_COLUMN, self.0.warehouse_id.0)
+            .with_arg(PRODUCT_ID_COLUMN, self.0.product_id.0)
+            .with_arg(QUANTITY_BEFORE_COLUMN, self.0.quantity_before.0)
+            .with_arg(QUANTITY_AFTER_COLUMN, self.0.quantity_after.0)
+            .with_arg(DELTA_COLUMN, self.0.delta)
+            .with_arg(REASON_COLUMN, self.0.reason.as_str().to_string())
+            .with_arg(CREATED_AT_COLUMN, self.0.created_at);
+
+        if let Some(user_id) = self.0.user_id {
+            b = b.with_arg(USER_ID_COLUMN, user_id.0);
+        }
+
+        if let Some(api_key_id) = self.0.api_key_id {
+            b = b.with_arg(API_KEY_ID_COLUMN, api_key_id.0);
+        }
+
+        b
+    }
+}
+
+/// Which movements to page through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StockMovementSubject {
+    Warehouse(WarehouseId),
+    Product(ProductId),
+    Stock(StockId),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StockMovementFilter {
+    pub id: Option<ValueContainer<StockMovementId>>,
+    pub stock_id: Option<ValueContainer<StockId>>,
+    pub warehouse_id: Option<ValueContainer<WarehouseId>>,
+    pub product_id: Option<ValueContainer<ProductId>>,
+}
+
+impl From<StockMovementSubject> for StockMovementFilter {
+    fn from(v: StockMovementSubject) -> Self {
+        use self::StockMovementSubject::*;
+
+        match v {
+            Warehouse(warehouse_id) => Self {
+                warehouse_id: Some(warehouse_id.into()),
+                ..Default::default()
+            },
+            Product(product_id) => Self {
+                product_id: Some(product_id.into()),
+                ..Default::default()
+            },
+            Stock(stock_id) => Self {
+                stock_id: Some(stock_id.into()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Filter for StockMovementFilter {
+    fn into_filtered_operation_builder(self, table: &'static str) -> FilteredOperationBuilder {
+        let mut b = FilteredOperationBuilder::new(table);
+
+        if let Some(id) = self.id {
+            b = b.with_filter(ID_COLUMN, id.value.0);
+        }
+
+        if let Some(stock_id) = self.stock_id {
+            b = b.with_filter(STOCK_ID_COLUMN, stock_id.value.0);
+        }
+
+        if let Some(warehouse_id) = self.warehouse_id {
+            b = b.with_filter(WAREHOUSE_ID_COLUMN, warehouse_id.value.0);
+        }
+
+        if let Some(product_id) = self.product_id {
+            b = b.with_filter(PRODUCT_ID_COLUMN, product_id.value.0);
+        }
+
+        b
+    }
+}
+
+/// Movements are append-only, so the updater never changes anything.
+#[derive(Clone, Debug, Default)]
+pub struct StockMovementUpdater {
+    pub mask: StockMovementFilter,
+}
+
+impl Updater for StockMovementUpdater {
+    fn into_update_builder(self, table: &'static str) -> UpdateBuilder {
+        UpdateBuilder::from(self.mask.into_filtered_operation_builder(table))
+    }
+}
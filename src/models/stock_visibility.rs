@@ -0,0 +1,144 @@
+use errors::*;
+use models::StockRecord;
+
+use failure;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+
+const WAREHOUSE_ID_COLUMN: &str = "warehouse_id";
+const STORE_ID_COLUMN: &str = "store_id";
+const MODE_COLUMN: &str = "mode";
+const CAP_COLUMN: &str = "cap";
+
+const AVAILABILITY_MODE: &str = "availability";
+const CAPPED_MODE: &str = "capped";
+
+/// Bucket shown to readers of stores that did not configure their visibility.
+pub const DEFAULT_VISIBILITY_CAP: Quantity = Quantity(10);
+
+/// What readers who do not manage the stock learn about its quantity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum StockVisibility {
+    /// Only whether the product is in stock, shown as quantity `1` or `0`.
+    Availability,
+    /// Quantities above `cap` are shown as `cap`, e.g. "10+".
+    Capped { cap: Quantity },
+}
+
+impl Default for StockVisibility {
+    fn default() -> Self {
+        StockVisibility::Capped {
+            cap: DEFAULT_VISIBILITY_CAP,
+        }
+    }
+}
+
+impl StockVisibility {
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        match self {
+            StockVisibility::Capped { cap } if cap.0 < 1 => {
+                Err(format_err!("Visibility cap must be positive")
+                    .context(Error::InvalidInput)
+                    .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn mode(&self) -> &'static str {
+        match self {
+            StockVisibility::Availability => AVAILABILITY_MODE,
+            StockVisibility::Capped { .. } => CAPPED_MODE,
+        }
+    }
+
+    pub fn cap(&self) -> Option<Quantity> {
+        match self {
+            StockVisibility::Availability => None,
+            StockVisibility::Capped { cap } => Some(*cap),
+        }
+    }
+
+    /// Quantity shown instead of the exact one and how precise it is.
+    pub fn mask(&self, quantity: Quantity) -> (Quantity, QuantityPrecision) {
+        match self {
+            StockVisibility::Availability => (
+                Quantity(if quantity.0 > 0 { 1 } else { 0 }),
+                QuantityPrecision::Availability,
+            ),
+            StockVisibility::Capped { cap } if quantity.0 >= cap.0 => {
+                (*cap, QuantityPrecision::AtLeast)
+            }
+            StockVisibility::Capped { .. } => (quantity, QuantityPrecision::Exact),
+        }
+    }
+
+    /// Hides the exact quantity and the attributes only owners need.
+    pub fn apply(&self, mut stock: StockRecord) -> StockRecord {
+        let (quantity, precision) = self.mask(stock.stock.quantity);
+        stock.stock.quantity = quantity;
+        stock.precision = precision;
+        stock.reorder_threshold = None;
+        stock
+    }
+
+    fn from_columns(mode: Option<String>, cap: Option<i32>) -> Self {
+        match (mode.as_ref().map(|v| v.as_str()), cap) {
+            (Some(AVAILABILITY_MODE), _) => StockVisibility::Availability,
+            (Some(CAPPED_MODE), Some(cap)) => StockVisibility::Capped { cap: Quantity(cap) },
+            _ => StockVisibility::default(),
+        }
+    }
+}
+
+/// How `quantity` of a stock relates to the exact one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuantityPrecision {
+    Exact,
+    /// The exact quantity is this or more.
+    AtLeast,
+    /// `1` if the product is in stock, `0` otherwise.
+    Availability,
+}
+
+impl Default for QuantityPrecision {
+    fn default() -> Self {
+        QuantityPrecision::Exact
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoreStockVisibility {
+    pub store_id: StoreId,
+    #[serde(flatten)]
+    pub visibility: StockVisibility,
+}
+
+impl From<Row> for StoreStockVisibility {
+    fn from(row: Row) -> Self {
+        StoreStockVisibility {
+            store_id: StoreId(row.get(STORE_ID_COLUMN)),
+            visibility: StockVisibility::from_columns(row.get(MODE_COLUMN), row.get(CAP_COLUMN)),
+        }
+    }
+}
+
+/// Visibility configured by the store that owns the warehouse.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WarehouseStockVisibility {
+    pub warehouse_id: WarehouseId,
+    pub store_id: StoreId,
+    pub visibility: StockVisibility,
+}
+
+impl From<Row> for WarehouseStockVisibility {
+    fn from(row: Row) -> Self {
+        WarehouseStockVisibility {
+            warehouse_id: WarehouseId(row.get(WAREHOUSE_ID_COLUMN)),
+            store_id: StoreId(row.get(STORE_ID_COLUMN)),
+            visibility: StockVisibility::from_columns(row.get(MODE_COLUMN), row.get(CAP_COLUMN)),
+        }
+    }
+}
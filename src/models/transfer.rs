@@ -0,0 +1,348 @@
+use errors::*;
+use models::ApiKeyId;
+
+use chrono::prelude::*;
+use failure;
+use std::fmt;
+use std::str::FromStr;
+use stq_api::types::ValueContainer;
+use stq_db::statement::*;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+use uuid::{self, Uuid};
+
+const ID_COLUMN: &str = "id";
+const SOURCE_WAREHOUSE_ID_COLUMN: &str = "source_warehouse_id";
+const DESTINATION_WAREHOUSE_ID_COLUMN: &str = "destination_warehouse_id";
+const STATUS_COLUMN: &str = "status";
+const CREATED_BY_COLUMN: &str = "created_by";
+const API_KEY_ID_COLUMN: &str = "api_key_id";
+const CREATED_AT_COLUMN: &str = "created_at";
+const SHIPPED_AT_COLUMN: &str = "shipped_at";
+const RECEIVED_AT_COLUMN: &str = "received_at";
+const CANCELLED_AT_COLUMN: &str = "cancelled_at";
+
+const TRANSFER_ID_COLUMN: &str = "transfer_id";
+const PRODUCT_ID_COLUMN: &str = "product_id";
+const QUANTITY_COLUMN: &str = "quantity";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TransferId(pub Uuid);
+
+impl TransferId {
+    pub fn new() -> Self {
+        TransferId(Uuid::new_v4())
+    }
+}
+
+impl fmt::Display for TransferId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for TransferId {
+    type Err = uuid::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(TransferId(s.parse()?))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+    /// Document is prepared, stocks are not affected yet.
+    Draft,
+    /// Goods left the source warehouse and are in transit.
+    Shipped,
+    /// Goods arrived at the destination warehouse.
+    Received,
+    /// Draft was abandoned before shipping.
+    Cancelled,
+}
+
+impl TransferStatus {
+    pub fn as_str(&self) -> &'static str {
+        use self::TransferStatus::*;
+
+        match self {
+            Draft => "draft",
+            Shipped => "shipped",
+            Received => "received",
+            Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for TransferStatus {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::TransferStatus::*;
+
+        match s {
+            "draft" => Ok(Draft),
+            "shipped" => Ok(Shipped),
+            "received" => Ok(Received),
+            "cancelled" => Ok(Cancelled),
+            other => Err(format_err!("Unknown transfer status {}", other)
+                .context(Error::ParseError)
+                .into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transfer {
+    pub id: TransferId,
+    pub source_warehouse_id: WarehouseId,
+    pub destination_warehouse_id: WarehouseId,
+    pub status: TransferStatus,
+    /// User who created the transfer, `None` if an API key did.
+    pub created_by: Option<UserId>,
+    pub api_key_id: Option<ApiKeyId>,
+    pub created_at: DateTime<Utc>,
+    pub shipped_at: Option<DateTime<Utc>>,
+    pub received_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferLine {
+    pub transfer_id: TransferId,
+    pub product_id: ProductId,
+    pub quantity: Quantity,
+}
+
+/// Transfer together with the goods it moves.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferDocument {
+    #[serde(flatten)]
+    pub transfer: Transfer,
+    pub lines: Vec<TransferLine>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferLineInput {
+    pub product_id: ProductId,
+    pub quantity: Quantity,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferInput {
+    pub source_warehouse_id: WarehouseId,
+    pub destination_warehouse_id: WarehouseId,
+    pub lines: Vec<TransferLineInput>,
+}
+
+impl TransferInput {
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if self.source_warehouse_id == self.destination_warehouse_id {
+            return Err(format_err!("Source and destination warehouses must differ")
+                .context(Error::InvalidInput)
+                .into());
+        }
+
+        if self.lines.is_empty() {
+            return Err(format_err!("Transfer must have at least one line")
+                .context(Error::InvalidInput)
+                .into());
+        }
+
+        let mut product_ids = self
+            .lines
+            .iter()
+            .map(|v| v.product_id.0)
+            .collect::<Vec<_>>();
+        product_ids.sort();
+        product_ids.dedup();
+        if product_ids.len() != self.lines.len() {
+            return Err(
+                format_err!("Each product may appear in a transfer only once")
+                    .context(Error::InvalidInput)
+                    .into(),
+            );
+        }
+
+        if let Some(line) = self.lines.iter().find(|v| v.quantity.0 <= 0) {
+            return Err(
+                format_err!("Quantity of product {} must be positive", line.product_id)
+                    .context(Error::InvalidInput)
+                    .into(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+pub struct DbTransfer(pub Transfer);
+
+impl From<Row> for DbTransfer {
+    fn from(row: Row) -> Self {
+        DbTransfer(Transfer {
+            id: TransferId(row.get(ID_COLUMN)),
+            source_warehouse_id: WarehouseId(row.get(SOURCE_WAREHOUSE_ID_COLUMN)),
+            destination_warehouse_id: WarehouseId(row.get(DESTINATION_WAREHOUSE_ID_COLUMN)),
+            status: row
+                .get::<String, _>(STATUS_COLUMN)
+                .parse()
+                .expect("Unknown transfer status in database"),
+            created_by: row.get::<Option<i32>, _>(CREATED_BY_COLUMN).map(UserId),
+            api_key_id: row.get::<Option<Uuid>, _>(API_KEY_ID_COLUMN).map(ApiKeyId),
+            created_at: row.get(CREATED_AT_COLUMN),
+            shipped_at: row.get(SHIPPED_AT_COLUMN),
+            received_at: row.get(RECEIVED_AT_COLUMN),
+            cancelled_at: row.get(CANCELLED_AT_COLUMN),
+        })
+    }
+}
+
+impl Inserter for DbTransfer {
+    fn into_insert_builder(self, table: &'static str) -> InsertBuilder {
+        let mut b = InsertBuilder::new(table)
+            .with_arg(ID_COLUMN, self.0.id.0)
+            .with_arg(SOURCE_WAREHOUSE_ID_COLUMN, self.0.source_warehouse_id.0)
+            .with_arg(
+                DESTINATION_WAREHOUSE_ID_COLUMN,
+                self.0.destination_warehouse_id.0,
+            )
+            .with_arg(STATUS_COLUMN, self.0.status.as_str().to_string())
+            .with_arg(CREATED_AT_COLUMN, self.0.created_at);
+
+        if let Some(created_by) = self.0.created_by {
+            b = b.with_arg(CREATED_BY_COLUMN, created_by.0);
+        }
+
+        if let Some(api_key_id) = self.0.api_key_id {
+            b = b.with_arg(API_KEY_ID_COLUMN, api_key_id.0);
+        }
+
+        b
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransferFilter {
+    pub id: Option<ValueContainer<TransferId>>,
+    pub status: Option<ValueContainer<TransferStatus>>,
+}
+
+impl Filter for TransferFilter {
+    fn into_filtered_operation_builder(self, table: &'static str) -> FilteredOperationBuilder {
+        let mut b = FilteredOperationBuilder::new(table);
+
+        if let Some(id) = self.id {
+            b = b.with_filter(ID_COLUMN, id.value.0);
+        }
+
+        if let Some(status) = self.status {
+            b = b.with_filter(STATUS_COLUMN, status.value.as_str().to_string());
+        }
+
+        b
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransferUpdateData {
+    pub status: Option<ValueContainer<TransferStatus>>,
+    pub shipped_at: Option<ValueContainer<Option<DateTime<Utc>>>>,
+    pub received_at: Option<ValueContainer<Option<DateTime<Utc>>>>,
+    pub cancelled_at: Option<ValueContainer<Option<DateTime<Utc>>>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransferUpdater {
+    pub mask: TransferFilter,
+    pub data: TransferUpdateData,
+}
+
+impl Updater for TransferUpdater {
+    fn into_update_builder(self, table: &'static str) -> UpdateBuilder {
+        let Self { mask, data } = self;
+
+        let mut b = UpdateBuilder::from(mask.into_filtered_operation_builder(table));
+
+        if let Some(status) = data.status {
+            b = b.with_value(STATUS_COLUMN, status.value.as_str().to_string());
+        }
+
+        if let Some(shipped_at) = data.shipped_at {
+            b = b.with_value(SHIPPED_AT_COLUMN, shipped_at.value);
+        }
+
+        if let Some(received_at) = data.received_at {
+            b = b.with_value(RECEIVED_AT_COLUMN, received_at.value);
+        }
+
+        if let Some(cancelled_at) = data.cancelled_at {
+            b = b.with_value(CANCELLED_AT_COLUMN, cancelled_at.value);
+        }
+
+        b
+    }
+}
+
+pub struct DbTransferLine(pub TransferLine);
+
+impl From<Row> for DbTransferLine {
+    fn from(row: Row) -> Self {
+        DbTransferLine(TransferLine {
+            transfer_id: TransferId(row.get(TRANSFER_ID_COLUMN)),
+            product_id: ProductId(row.get(PRODUCT_ID_COLUMN)),
+            quantity: Quantity(row.get(QUANTITY_COLUMN)),
+        })
+    }
+}
+
+impl Inserter for DbTransferLine {
+    fn into_insert_builder(self, table: &'static str) -> InsertBuilder {
+        InsertBuilder::new(table)
+            .with_arg(TRANSFER_ID_COLUMN, self.0.transfer_id.0)
+            .with_arg(PRODUCT_ID_COLUMN, self.0.product_id.0)
+            .with_arg(QUANTITY_COLUMN, self.0.quantity.0)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransferLineFilter {
+    pub transfer_id: Option<ValueContainer<TransferId>>,
+}
+
+impl Filter for TransferLineFilter {
+    fn into_filtered_operation_builder(self, table: &'static str) -> FilteredOperationBuilder {
+        let mut b = FilteredOperationBuilder::new(table);
+
+        if let Some(transfer_id) = self.transfer_id {
+            b = b.with_filter(TRANSFER_ID_COLUMN, transfer_id.value.0);
+        }
+
+        b
+    }
+}
+
+/// Lines are fixed once the transfer is created, so the updater never changes anything.
+#[derive(Clone, Debug, Default)]
+pub struct TransferLineUpdater {
+    pub mask: TransferLineFilter,
+}
+
+impl Updater for TransferLineUpdater {
+    fn into_update_builder(self, table: &'static str) -> UpdateBuilder {
+        UpdateBuilder::from(self.mask.into_filtered_operation_builder(table))
+    }
+}
+
+/// Quantity of a product on its way to a warehouse.
+pub struct InTransitQuantity(pub ProductId, pub Quantity);
+
+impl From<Row> for InTransitQuantity {
+    fn from(row: Row) -> Self {
+        InTransitQuantity(
+            ProductId(row.get(PRODUCT_ID_COLUMN)),
+            Quantity(row.get(QUANTITY_COLUMN)),
+        )
+    }
+}
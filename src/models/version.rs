@@ -0,0 +1,19 @@
+use std::fmt;
+
+/// Row version used for optimistic concurrency control.
+/// It is incremented by a database trigger on every update of the row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Version(pub i32);
+
+impl Version {
+    /// Version of a freshly inserted row.
+    pub fn initial() -> Self {
+        Version(1)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
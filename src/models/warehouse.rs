@@ -1,5 +1,17 @@
+use errors::*;
+use models::{
+    OpeningInterval, Page, ProductCapacity, Version, WarehouseCapacity, WarehouseSchedule,
+    WarehouseStatus,
+};
+
+use chrono::prelude::*;
+use failure;
 use geo::Point as GeoPoint;
-use stq_api::{self, types::ValueContainer};
+use serde_json;
+use stq_api::{
+    types::ValueContainer,
+    warehouses::{Warehouse, WarehouseInput, WarehouseUpdateData},
+};
 use stq_db::statement::*;
 use stq_types::*;
 use tokio_postgres;
@@ -20,12 +32,143 @@ const ROUTE_COLUMN: &str = "route";
 const STREET_NUMBER_COLUMN: &str = "street_number";
 const ADDRESS_COLUMN: &str = "address";
 const PLACE_ID_COLUMN: &str = "place_id";
+const VERSION_COLUMN: &str = "version";
+const DEFAULT_REORDER_THRESHOLD_COLUMN: &str = "default_reorder_threshold";
+const DISTANCE_COLUMN: &str = "distance";
+const IS_ARCHIVED_COLUMN: &str = "is_archived";
+const ARCHIVED_AT_COLUMN: &str = "archived_at";
+const STATUS_COLUMN: &str = "status";
+const TIME_ZONE_COLUMN: &str = "time_zone";
+const OPENING_HOURS_COLUMN: &str = "opening_hours";
+const HOLIDAYS_COLUMN: &str = "holidays";
+const CAPACITY_COLUMN: &str = "capacity";
+const PRODUCT_CAPACITIES_COLUMN: &str = "product_capacities";
+const CREATED_AT_COLUMN: &str = "created_at";
+const UPDATED_AT_COLUMN: &str = "updated_at";
+
+/// Warehouse together with the attributes maintained by this service on top of the shared API model.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WarehouseRecord {
+    #[serde(flatten)]
+    pub warehouse: Warehouse,
+    pub version: Version,
+    /// Reorder threshold for stocks of this warehouse that do not set their own.
+    pub default_reorder_threshold: Option<Quantity>,
+    /// Archived warehouses keep their stocks but are hidden from listings and availability.
+    pub archived_at: Option<DateTime<Utc>>,
+    /// Only active warehouses count toward availability.
+    pub status: WarehouseStatus,
+    /// Opening hours for pickups and shipping cut-offs, `None` if not set up.
+    pub schedule: Option<WarehouseSchedule>,
+    #[serde(flatten)]
+    pub capacity: WarehouseCapacity,
+    pub created_at: DateTime<Utc>,
+    /// Time of the last change, for incremental sync.
+    pub updated_at: DateTime<Utc>,
+}
+
+impl WarehouseRecord {
+    pub fn new(warehouse: Warehouse) -> Self {
+        let now = Utc::now();
+        Self {
+            warehouse,
+            version: Version::initial(),
+            default_reorder_threshold: None,
+            archived_at: None,
+            status: WarehouseStatus::Active,
+            schedule: None,
+            capacity: WarehouseCapacity::default(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Warehouse creation payload, the shared API input plus the attributes maintained by this service.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewWarehouseRecord {
+    #[serde(flatten)]
+    pub input: WarehouseInput,
+    /// Generated from `warehouse_slug_seq` if not set.
+    pub slug: Option<WarehouseSlug>,
+    pub schedule: Option<WarehouseSchedule>,
+}
 
-pub struct DbWarehouse(pub stq_api::warehouses::Warehouse);
+impl NewWarehouseRecord {
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if let Some(ref slug) = self.slug {
+            validate_slug(slug)?;
+        }
+
+        if let Some(ref schedule) = self.schedule {
+            schedule.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Warehouse update payload, the shared API changes plus the attributes maintained by this service.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WarehouseRecordUpdate {
+    #[serde(flatten)]
+    pub data: WarehouseUpdateData,
+    /// Replaces the whole schedule if set, a `null` value removes it.
+    pub schedule: Option<ValueContainer<Option<WarehouseSchedule>>>,
+}
+
+impl WarehouseRecordUpdate {
+    /// The slug is validated only when it changes, see `validate_slug`.
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if let Some(ValueContainer {
+            value: Some(ref schedule),
+        }) = self.schedule
+        {
+            schedule.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+const MAX_SLUG_LENGTH: usize = 64;
+
+/// Custom slugs are lowercase latin letters and digits, optionally joined by single hyphens.
+/// Purely numeric slugs are reserved for the ones generated from `warehouse_slug_seq`.
+pub fn validate_slug(slug: &WarehouseSlug) -> Result<(), failure::Error> {
+    let value = &slug.0;
+    let well_formed = !value.is_empty()
+        && value.len() <= MAX_SLUG_LENGTH
+        && value.split('-').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        });
+    if !well_formed {
+        return Err(format_err!(
+            "Slug {:?} must be at most {} lowercase letters, digits and single hyphens",
+            value,
+            MAX_SLUG_LENGTH
+        )
+        .context(Error::InvalidInput)
+        .into());
+    }
+
+    if value.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format_err!("Slug {:?} must not be a number", value)
+            .context(Error::InvalidInput)
+            .into());
+    }
+
+    Ok(())
+}
+
+pub struct DbWarehouse(pub WarehouseRecord);
 
 impl From<tokio_postgres::rows::Row> for DbWarehouse {
     fn from(v: tokio_postgres::rows::Row) -> Self {
-        DbWarehouse(stq_api::warehouses::Warehouse {
+        let warehouse = Warehouse {
             id: WarehouseId(v.get(ID_COLUMN)),
             store_id: StoreId(v.get(STORE_ID_COLUMN)),
             slug: WarehouseSlug(v.get(SLUG_COLUMN)),
@@ -45,80 +188,207 @@ impl From<tokio_postgres::rows::Row> for DbWarehouse {
             street_number: v.get(STREET_NUMBER_COLUMN),
             address: v.get(ADDRESS_COLUMN),
             place_id: v.get(PLACE_ID_COLUMN),
+        };
+
+        DbWarehouse(WarehouseRecord {
+            warehouse,
+            version: Version(v.get(VERSION_COLUMN)),
+            default_reorder_threshold: v
+                .get::<Option<i32>, _>(DEFAULT_REORDER_THRESHOLD_COLUMN)
+                .map(Quantity),
+            archived_at: v.get(ARCHIVED_AT_COLUMN),
+            status: v
+                .get::<String, _>(STATUS_COLUMN)
+                .parse()
+                .expect("Unknown warehouse status in database"),
+            schedule: v
+                .get::<Option<String>, _>(TIME_ZONE_COLUMN)
+                .map(|time_zone| WarehouseSchedule {
+                    time_zone,
+                    opening_hours: serde_json::from_value(v.get(OPENING_HOURS_COLUMN))
+                        .expect("Invalid opening hours in database"),
+                    holidays: v.get(HOLIDAYS_COLUMN),
+                }),
+            capacity: WarehouseCapacity {
+                capacity: v.get::<Option<i32>, _>(CAPACITY_COLUMN).map(Quantity),
+                product_capacities: serde_json::from_value(v.get(PRODUCT_CAPACITIES_COLUMN))
+                    .expect("Invalid product capacities in database"),
+            },
+            created_at: v.get(CREATED_AT_COLUMN),
+            updated_at: v.get(UPDATED_AT_COLUMN),
         })
     }
 }
 
+/// Mean Earth radius used for great-circle distances.
+pub const EARTH_RADIUS_METERS: f64 = 6_371_008.8;
+
+/// Search for warehouses around a point, `location` having longitude as `x` and latitude as `y`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NearestWarehouseQuery {
+    pub location: GeoPoint<f64>,
+    /// Only return warehouses within this great-circle distance.
+    pub radius_meters: Option<f64>,
+    pub store_id: Option<StoreId>,
+}
+
+impl NearestWarehouseQuery {
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        let (longitude, latitude) = (self.location.x(), self.location.y());
+        if !(latitude >= -90.0 && latitude <= 90.0) {
+            return Err(format_err!("Latitude must be within [-90, 90]")
+                .context(Error::InvalidInput)
+                .into());
+        }
+
+        if !(longitude >= -180.0 && longitude <= 180.0) {
+            return Err(format_err!("Longitude must be within [-180, 180]")
+                .context(Error::InvalidInput)
+                .into());
+        }
+
+        if let Some(radius_meters) = self.radius_meters {
+            if !(radius_meters > 0.0) {
+                return Err(format_err!("Radius must be positive")
+                    .context(Error::InvalidInput)
+                    .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WarehouseWithDistance {
+    #[serde(flatten)]
+    pub warehouse: WarehouseRecord,
+    /// Great-circle distance from the searched point.
+    pub distance_meters: f64,
+}
+
+impl From<tokio_postgres::rows::Row> for WarehouseWithDistance {
+    fn from(v: tokio_postgres::rows::Row) -> Self {
+        let distance_meters = v.get(DISTANCE_COLUMN);
+        WarehouseWithDistance {
+            warehouse: DbWarehouse::from(v).0,
+            distance_meters,
+        }
+    }
+}
+
 impl Inserter for DbWarehouse {
     fn into_insert_builder(self, table: &'static str) -> InsertBuilder {
+        let WarehouseRecord {
+            warehouse,
+            default_reorder_threshold,
+            status,
+            schedule,
+            capacity,
+            ..
+        } = self.0;
         let mut b = InsertBuilder::new(table);
 
-        b = b.with_arg(ID_COLUMN, self.0.id.0);
-        b = b.with_arg(STORE_ID_COLUMN, self.0.store_id.0);
-        b = b.with_arg(SLUG_COLUMN, self.0.slug.0);
+        b = b.with_arg(ID_COLUMN, warehouse.id.0);
+        b = b.with_arg(STORE_ID_COLUMN, warehouse.store_id.0);
+        b = b.with_arg(SLUG_COLUMN, warehouse.slug.0);
+        b = b.with_arg(STATUS_COLUMN, status.as_str().to_string());
 
-        if let Some(name) = self.0.name {
+        if let Some(name) = warehouse.name {
             b = b.with_arg(NAME_COLUMN, name);
         }
 
-        if let Some(location) = self.0.location {
+        if let Some(location) = warehouse.location {
             b = b.with_arg(LOCATION_COLUMN, location);
         }
 
-        if let Some(administrative_area_level_1) = self.0.administrative_area_level_1 {
+        if let Some(administrative_area_level_1) = warehouse.administrative_area_level_1 {
             b = b.with_arg(
                 ADMINISTRATIVE_AREA_LEVEL_1_COLUMN,
                 administrative_area_level_1,
             );
         }
 
-        if let Some(administrative_area_level_2) = self.0.administrative_area_level_2 {
+        if let Some(administrative_area_level_2) = warehouse.administrative_area_level_2 {
             b = b.with_arg(
                 ADMINISTRATIVE_AREA_LEVEL_2_COLUMN,
                 administrative_area_level_2,
             );
         }
 
-        if let Some(country) = self.0.country {
+        if let Some(country) = warehouse.country {
             b = b.with_arg(COUNTRY_COLUMN, country.to_string());
         }
 
-        if let Some(country_code) = self.0.country_code {
+        if let Some(country_code) = warehouse.country_code {
             b = b.with_arg(COUNTRY_CODE_COLUMN, country_code.to_string());
         }
 
-        if let Some(locality) = self.0.locality {
+        if let Some(locality) = warehouse.locality {
             b = b.with_arg(LOCALITY_COLUMN, locality);
         }
 
-        if let Some(political) = self.0.political {
+        if let Some(political) = warehouse.political {
             b = b.with_arg(POLITICAL_COLUMN, political);
         }
 
-        if let Some(postal_code) = self.0.postal_code {
+        if let Some(postal_code) = warehouse.postal_code {
             b = b.with_arg(POSTAL_CODE_COLUMN, postal_code);
         }
 
-        if let Some(route) = self.0.route {
+        if let Some(route) = warehouse.route {
             b = b.with_arg(ROUTE_COLUMN, route);
         }
 
-        if let Some(street_number) = self.0.street_number {
+        if let Some(street_number) = warehouse.street_number {
             b = b.with_arg(STREET_NUMBER_COLUMN, street_number);
         }
 
-        if let Some(address) = self.0.address {
+        if let Some(address) = warehouse.address {
             b = b.with_arg(ADDRESS_COLUMN, address);
         }
 
-        if let Some(place_id) = self.0.place_id {
+        if let Some(place_id) = warehouse.place_id {
             b = b.with_arg(PLACE_ID_COLUMN, place_id);
         }
 
+        if let Some(default_reorder_threshold) = default_reorder_threshold {
+            b = b.with_arg(
+                DEFAULT_REORDER_THRESHOLD_COLUMN,
+                default_reorder_threshold.0,
+            );
+        }
+
+        if let Some(schedule) = schedule {
+            b = b
+                .with_arg(TIME_ZONE_COLUMN, schedule.time_zone)
+                .with_arg(
+                    OPENING_HOURS_COLUMN,
+                    opening_hours_value(&schedule.opening_hours),
+                )
+                .with_arg(HOLIDAYS_COLUMN, schedule.holidays);
+        }
+
+        if let Some(total) = capacity.capacity {
+            b = b.with_arg(CAPACITY_COLUMN, total.0);
+        }
+        b = b.with_arg(
+            PRODUCT_CAPACITIES_COLUMN,
+            product_capacities_value(&capacity.product_capacities),
+        );
+
         b
     }
 }
 
+fn opening_hours_value(opening_hours: &[OpeningInterval]) -> serde_json::Value {
+    serde_json::to_value(opening_hours).expect("Failed to serialize opening hours")
+}
+
+fn product_capacities_value(product_capacities: &[ProductCapacity]) -> serde_json::Value {
+    serde_json::to_value(product_capacities).expect("Failed to serialize product capacities")
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct WarehouseFilter {
     pub id: Option<ValueContainer<WarehouseId>>,
@@ -137,6 +407,9 @@ pub struct WarehouseFilter {
     pub street_number: Option<ValueContainer<Option<String>>>,
     pub address: Option<ValueContainer<Option<String>>>,
     pub place_id: Option<ValueContainer<Option<String>>>,
+    pub version: Option<ValueContainer<Version>>,
+    pub archived: Option<ValueContainer<bool>>,
+    pub status: Option<ValueContainer<WarehouseStatus>>,
 }
 
 impl Filter for WarehouseFilter {
@@ -216,6 +489,18 @@ impl Filter for WarehouseFilter {
             b = b.with_filter(PLACE_ID_COLUMN, place_id.value);
         }
 
+        if let Some(version) = self.version {
+            b = b.with_filter(VERSION_COLUMN, version.value.0);
+        }
+
+        if let Some(archived) = self.archived {
+            b = b.with_filter(IS_ARCHIVED_COLUMN, archived.value);
+        }
+
+        if let Some(status) = self.status {
+            b = b.with_filter(STATUS_COLUMN, status.value.as_str().to_string());
+        }
+
         b
     }
 }
@@ -237,15 +522,27 @@ impl From<WarehouseIdentifier> for WarehouseFilter {
     }
 }
 
+/// Changes to the attributes maintained by this service on top of the shared API model.
+#[derive(Clone, Debug, Default)]
+pub struct WarehouseRecordUpdateData {
+    pub default_reorder_threshold: Option<ValueContainer<Option<Quantity>>>,
+    /// `Some` archives the warehouse, `None` restores it.
+    pub archived_at: Option<ValueContainer<Option<DateTime<Utc>>>>,
+    pub status: Option<ValueContainer<WarehouseStatus>>,
+    pub schedule: Option<ValueContainer<Option<WarehouseSchedule>>>,
+    pub capacity: Option<ValueContainer<WarehouseCapacity>>,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct WarehouseUpdater {
     pub mask: WarehouseFilter,
-    pub data: stq_api::warehouses::WarehouseUpdateData,
+    pub data: WarehouseUpdateData,
+    pub extra: WarehouseRecordUpdateData,
 }
 
 impl Updater for WarehouseUpdater {
     fn into_update_builder(self, table: &'static str) -> UpdateBuilder {
-        let Self { mask, data } = self;
+        let Self { mask, data, extra } = self;
 
         let mut b = UpdateBuilder::from(mask.into_filtered_operation_builder(table));
 
@@ -311,6 +608,106 @@ impl Updater for WarehouseUpdater {
             b = b.with_value(PLACE_ID_COLUMN, place_id.value);
         }
 
+        if let Some(default_reorder_threshold) = extra.default_reorder_threshold {
+            b = b.with_value(
+                DEFAULT_REORDER_THRESHOLD_COLUMN,
+                default_reorder_threshold.value.map(|v| v.0),
+            );
+        }
+
+        if let Some(archived_at) = extra.archived_at {
+            b = b
+                .with_value(IS_ARCHIVED_COLUMN, archived_at.value.is_some())
+                .with_value(ARCHIVED_AT_COLUMN, archived_at.value);
+        }
+
+        if let Some(status) = extra.status {
+            b = b.with_value(STATUS_COLUMN, status.value.as_str().to_string());
+        }
+
+        if let Some(schedule) = extra.schedule {
+            let (time_zone, opening_hours, holidays) = match schedule.value {
+                Some(schedule) => (
+                    Some(schedule.time_zone),
+                    schedule.opening_hours,
+                    schedule.holidays,
+                ),
+                None => (None, vec![], vec![]),
+            };
+            b = b
+                .with_value(TIME_ZONE_COLUMN, time_zone)
+                .with_value(OPENING_HOURS_COLUMN, opening_hours_value(&opening_hours))
+                .with_value(HOLIDAYS_COLUMN, holidays);
+        }
+
+        if let Some(capacity) = extra.capacity {
+            b = b
+                .with_value(CAPACITY_COLUMN, capacity.value.capacity.map(|v| v.0))
+                .with_value(
+                    PRODUCT_CAPACITIES_COLUMN,
+                    product_capacities_value(&capacity.value.product_capacities),
+                );
+        }
+
         b
     }
 }
+
+/// Warehouse listing by address attributes, exact on `filter` and partial on `name` and `address`.
+#[derive(Clone, Debug, Default)]
+pub struct WarehouseSearch {
+    pub filter: WarehouseFilter,
+    pub name: Option<String>,
+    pub address: Option<String>,
+    /// List archived warehouses instead of active ones.
+    pub archived: bool,
+    /// Only warehouses changed at or after this time.
+    pub updated_since: Option<DateTime<Utc>>,
+    pub page: Page,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slug(value: &str) -> WarehouseSlug {
+        WarehouseSlug(value.to_string())
+    }
+
+    #[test]
+    fn lowercase_words_joined_by_hyphens_are_valid_slugs() {
+        for value in &["main", "main-2", "north-east-hub", "a", "4th-street"] {
+            assert!(validate_slug(&slug(value)).is_ok(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn numeric_slugs_are_reserved() {
+        for value in &["1", "42", "007"] {
+            assert!(validate_slug(&slug(value)).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn malformed_slugs_are_rejected() {
+        for value in &[
+            "",
+            "Main",
+            "main hub",
+            "main_hub",
+            "-main",
+            "main-",
+            "main--hub",
+            "münchen",
+            "main/hub",
+        ] {
+            assert!(validate_slug(&slug(value)).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn slugs_are_limited_in_length() {
+        assert!(validate_slug(&slug(&"a".repeat(MAX_SLUG_LENGTH))).is_ok());
+        assert!(validate_slug(&slug(&"a".repeat(MAX_SLUG_LENGTH + 1))).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use errors::*;
+use models::ApiKeyId;
+
+use chrono::prelude::*;
+use failure;
+use std::fmt;
+use std::str::FromStr;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+use uuid::Uuid;
+
+const ID_COLUMN: &str = "id";
+const WAREHOUSE_ID_COLUMN: &str = "warehouse_id";
+const STATUS_BEFORE_COLUMN: &str = "status_before";
+const STATUS_COLUMN: &str = "status";
+const CHANGED_BY_COLUMN: &str = "changed_by";
+const API_KEY_ID_COLUMN: &str = "api_key_id";
+const CHANGED_AT_COLUMN: &str = "changed_at";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarehouseStatus {
+    /// Warehouse ships goods and its stocks count toward availability.
+    Active,
+    /// Temporarily not shipping, e.g. during stocktaking.
+    Paused,
+    /// Not operating until further notice, e.g. closed for renovation.
+    Closed,
+}
+
+impl Default for WarehouseStatus {
+    fn default() -> Self {
+        WarehouseStatus::Active
+    }
+}
+
+impl WarehouseStatus {
+    pub fn as_str(&self) -> &'static str {
+        use self::WarehouseStatus::*;
+
+        match self {
+            Active => "active",
+            Paused => "paused",
+            Closed => "closed",
+        }
+    }
+}
+
+impl fmt::Display for WarehouseStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for WarehouseStatus {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::WarehouseStatus::*;
+
+        match s {
+            "active" => Ok(Active),
+            "paused" => Ok(Paused),
+            "closed" => Ok(Closed),
+            other => Err(format_err!("Unknown warehouse status {}", other)
+                .context(Error::ParseError)
+                .into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct WarehouseStatusChangeId(pub Uuid);
+
+impl WarehouseStatusChangeId {
+    pub fn new() -> Self {
+        WarehouseStatusChangeId(Uuid::new_v4())
+    }
+}
+
+/// Entry of the status history of a warehouse.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WarehouseStatusChange {
+    pub id: WarehouseStatusChangeId,
+    pub warehouse_id: WarehouseId,
+    pub status_before: WarehouseStatus,
+    pub status: WarehouseStatus,
+    /// User who changed the status, `None` if an API key did.
+    pub changed_by: Option<UserId>,
+    pub api_key_id: Option<ApiKeyId>,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl From<Row> for WarehouseStatusChange {
+    fn from(row: Row) -> Self {
+        WarehouseStatusChange {
+            id: WarehouseStatusChangeId(row.get(ID_COLUMN)),
+            warehouse_id: WarehouseId(row.get(WAREHOUSE_ID_COLUMN)),
+            status_before: row
+                .get::<String, _>(STATUS_BEFORE_COLUMN)
+                .parse()
+                .expect("Unknown warehouse status in database"),
+            status: row
+                .get::<String, _>(STATUS_COLUMN)
+                .parse()
+                .expect("Unknown warehouse status in database"),
+            changed_by: row.get::<Option<i32>, _>(CHANGED_BY_COLUMN).map(UserId),
+            api_key_id: row.get::<Option<Uuid>, _>(API_KEY_ID_COLUMN).map(ApiKeyId),
+            changed_at: row.get(CHANGED_AT_COLUMN),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WarehouseStatusPayload {
+    pub status: WarehouseStatus,
+}
@@ -0,0 +1,129 @@
+use models::*;
+use repos::query::*;
+
+use chrono::prelude::*;
+use futures::prelude::*;
+use stq_db::repo::*;
+use stq_types::*;
+use tokio_postgres::rows::Row;
+
+const TABLE: &str = "api_keys";
+
+pub fn insert_api_key(
+    conn: RepoConnection,
+    api_key: ApiKey,
+    key_hash: String,
+) -> RepoConnectionFuture<ApiKey> {
+    Box::new(
+        query::<ApiKey>(
+            conn,
+            format!(
+                "INSERT INTO {} (id, key_hash, name, store_id, warehouse_ids, scope, created_by, created_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *",
+                TABLE
+            ),
+            vec![
+                Box::new(api_key.id.0),
+                Box::new(key_hash),
+                Box::new(api_key.name.clone()),
+                Box::new(api_key.store_id.0),
+                Box::new(
+                    api_key
+                        .warehouse_ids
+                        .iter()
+                        .map(|v| v.0)
+                        .collect::<Vec<_>>(),
+                ),
+                Box::new(api_key.scope.as_str().to_string()),
+                Box::new(api_key.created_by.0),
+                Box::new(api_key.created_at),
+            ],
+        )
+        .map(move |(mut v, conn)| (v.pop().unwrap_or(api_key), conn)),
+    )
+}
+
+/// Finds the key with the given secret digest unless it was revoked.
+pub fn find_active_by_hash(
+    conn: RepoConnection,
+    key_hash: String,
+) -> RepoConnectionFuture<Option<ApiKey>> {
+    Box::new(
+        query::<ApiKey>(
+            conn,
+            format!(
+                "SELECT * FROM {} WHERE key_hash = $1 AND revoked_at IS NULL",
+                TABLE
+            ),
+            vec![Box::new(key_hash)],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+pub fn get_api_key(
+    conn: RepoConnection,
+    api_key_id: ApiKeyId,
+) -> RepoConnectionFuture<Option<ApiKey>> {
+    Box::new(
+        query::<ApiKey>(
+            conn,
+            format!("SELECT * FROM {} WHERE id = $1", TABLE),
+            vec![Box::new(api_key_id.0)],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Keys of the store newest first, revoked ones included.
+pub fn store_api_keys(
+    conn: RepoConnection,
+    store_id: StoreId,
+) -> RepoConnectionFuture<Vec<ApiKey>> {
+    query::<ApiKey>(
+        conn,
+        format!(
+            "SELECT * FROM {} WHERE store_id = $1 ORDER BY created_at DESC, id",
+            TABLE
+        ),
+        vec![Box::new(store_id.0)],
+    )
+}
+
+pub fn revoke_api_key(
+    conn: RepoConnection,
+    api_key_id: ApiKeyId,
+    revoked_at: DateTime<Utc>,
+) -> RepoConnectionFuture<Option<ApiKey>> {
+    Box::new(
+        query::<ApiKey>(
+            conn,
+            format!(
+                "UPDATE {} SET revoked_at = COALESCE(revoked_at, $2) WHERE id = $1 RETURNING *",
+                TABLE
+            ),
+            vec![Box::new(api_key_id.0), Box::new(revoked_at)],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Number of the warehouses that do not belong to the store.
+pub fn count_foreign_warehouses(
+    conn: RepoConnection,
+    store_id: StoreId,
+    warehouse_ids: Vec<WarehouseId>,
+) -> RepoConnectionFuture<usize> {
+    let expected = warehouse_ids.len();
+    Box::new(
+        query::<Row>(
+            conn,
+            "SELECT id FROM warehouses WHERE store_id = $1 AND id = ANY($2)".to_string(),
+            vec![
+                Box::new(store_id.0),
+                Box::new(warehouse_ids.into_iter().map(|v| v.0).collect::<Vec<_>>()),
+            ],
+        )
+        .map(move |(v, conn)| (expected.saturating_sub(v.len()), conn)),
+    )
+}
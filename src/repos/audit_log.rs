@@ -0,0 +1,132 @@
+use models::*;
+use repos::query::*;
+
+use failure;
+use futures::prelude::*;
+use futures::stream;
+use stq_db::repo::*;
+use tokio_postgres::rows::Row;
+
+const TABLE: &str = "audit_log";
+const COLUMNS: usize = 9;
+
+/// Postgres accepts at most 65535 parameters per statement.
+const MAX_ROWS_PER_STATEMENT: usize = 65535 / COLUMNS;
+
+/// Appends the entries to the audit log, as few statements as the parameter limit allows.
+pub fn insert_audit_entries(
+    conn: RepoConnection,
+    entries: Vec<AuditEntry>,
+) -> RepoConnectionFuture<()> {
+    let mut chunks = vec![];
+    let mut entries = entries.into_iter().peekable();
+    while entries.peek().is_some() {
+        chunks.push(
+            entries
+                .by_ref()
+                .take(MAX_ROWS_PER_STATEMENT)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    Box::new(
+        stream::iter_ok::<_, (failure::Error, RepoConnection)>(chunks)
+            .fold(conn, |conn, chunk| {
+                insert_chunk(conn, chunk).map(|((), conn)| conn)
+            })
+            .map(|conn| ((), conn)),
+    )
+}
+
+fn insert_chunk(conn: RepoConnection, entries: Vec<AuditEntry>) -> RepoConnectionFuture<()> {
+    let mut args: QueryArgs = vec![];
+    let mut rows = vec![];
+    for entry in entries {
+        rows.push(format!(
+            "({})",
+            (args.len() + 1..args.len() + COLUMNS + 1)
+                .map(|i| format!("${}", i))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        args.push(Box::new(entry.id.0));
+        args.push(Box::new(entry.actor_id.map(|v| v.0)));
+        args.push(Box::new(entry.api_key_id.map(|v| v.0)));
+        args.push(Box::new(entry.entity_type.as_str().to_string()));
+        args.push(Box::new(entry.entity_id));
+        args.push(Box::new(entry.action.as_str().to_string()));
+        args.push(Box::new(entry.old_value));
+        args.push(Box::new(entry.new_value));
+        args.push(Box::new(entry.created_at));
+    }
+
+    Box::new(
+        query::<Row>(
+            conn,
+            format!(
+                "INSERT INTO {} (id, actor_id, api_key_id, entity_type, entity_id, action, old_value, new_value, created_at) \
+                 VALUES {}",
+                TABLE,
+                rows.join(", ")
+            ),
+            args,
+        )
+        .map(|(_, conn)| ((), conn)),
+    )
+}
+
+/// Pages through the entries matching the query newest first.
+pub fn find_audit_entries(
+    conn: RepoConnection,
+    audit_query: AuditLogQuery,
+) -> RepoConnectionFuture<Vec<AuditEntry>> {
+    let AuditLogQuery {
+        entity_type,
+        entity_id,
+        actor_id,
+        from,
+        to,
+        page,
+    } = audit_query;
+
+    let mut args: QueryArgs = vec![];
+    let mut conditions = vec!["TRUE".to_string()];
+
+    if let Some(entity_type) = entity_type {
+        args.push(Box::new(entity_type.as_str().to_string()));
+        conditions.push(format!("entity_type = ${}", args.len()));
+    }
+
+    if let Some(entity_id) = entity_id {
+        args.push(Box::new(entity_id));
+        conditions.push(format!("entity_id = ${}", args.len()));
+    }
+
+    if let Some(actor_id) = actor_id {
+        args.push(Box::new(actor_id.0));
+        conditions.push(format!("actor_id = ${}", args.len()));
+    }
+
+    if let Some(from) = from {
+        args.push(Box::new(from));
+        conditions.push(format!("created_at >= ${}", args.len()));
+    }
+
+    if let Some(to) = to {
+        args.push(Box::new(to));
+        conditions.push(format!("created_at < ${}", args.len()));
+    }
+
+    let page = page.normalized();
+    args.push(Box::new(page.count));
+    args.push(Box::new(page.offset));
+    let statement = format!(
+        "SELECT * FROM {} WHERE {} ORDER BY created_at DESC, id LIMIT ${} OFFSET ${}",
+        TABLE,
+        conditions.join(" AND "),
+        args.len() - 1,
+        args.len()
+    );
+
+    query::<AuditEntry>(conn, statement, args)
+}
@@ -0,0 +1,103 @@
+use models::*;
+use repos::query::*;
+
+use futures::prelude::*;
+use stq_db::repo::*;
+use stq_types::*;
+
+const EVENTS_TABLE: &str = "low_stock_events";
+
+/// Effective threshold of stock `s` in warehouse `w`.
+const THRESHOLD_EXPRESSION: &str = "COALESCE(s.reorder_threshold, w.default_reorder_threshold)";
+
+/// Records a low stock event if the movement took the stock from above its threshold to or below it.
+pub fn emit_low_stock_event(
+    conn: RepoConnection,
+    movement: &StockMovement,
+) -> RepoConnectionFuture<Option<LowStockEvent>> {
+    Box::new(
+        query::<LowStockEvent>(
+            conn,
+            format!(
+                "INSERT INTO {table} (id, stock_id, warehouse_id, product_id, movement_id, quantity, threshold) \
+                 SELECT $1, s.id, s.warehouse_id, s.product_id, $3, $4, {threshold} \
+                 FROM stocks s JOIN warehouses w ON w.id = s.warehouse_id \
+                 WHERE s.id = $2 AND $5 > {threshold} AND $4 <= {threshold} \
+                 RETURNING *",
+                table = EVENTS_TABLE,
+                threshold = THRESHOLD_EXPRESSION
+            ),
+            vec![
+                Box::new(LowStockEventId::new().0),
+                Box::new(movement.stock_id.0),
+                Box::new(movement.id.0),
+                Box::new(movement.quantity_after.0),
+                Box::new(movement.quantity_before.0),
+            ],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Stocks of the store at or below their effective reorder threshold,
+/// limited to the given warehouses if there are any.
+pub fn low_stocks(
+    conn: RepoConnection,
+    store_id: StoreId,
+    warehouse_scope: Option<Vec<WarehouseId>>,
+) -> RepoConnectionFuture<Vec<LowStock>> {
+    let mut args: QueryArgs = vec![Box::new(store_id.0)];
+    let mut scope_condition = String::new();
+    if let Some(warehouse_ids) = warehouse_scope {
+        args.push(Box::new(
+            warehouse_ids.into_iter().map(|v| v.0).collect::<Vec<_>>(),
+        ));
+        scope_condition = format!(" AND w.id = ANY(${})", args.len());
+    }
+
+    query::<LowStock>(
+        conn,
+        format!(
+            "SELECT s.*, {threshold} AS threshold \
+             FROM stocks s JOIN warehouses w ON w.id = s.warehouse_id \
+             WHERE w.store_id = $1{scope} AND NOT w.is_archived AND s.quantity <= {threshold} \
+             ORDER BY s.warehouse_id, s.product_id",
+            threshold = THRESHOLD_EXPRESSION,
+            scope = scope_condition
+        ),
+        args,
+    )
+}
+
+/// Pages through low stock events of the store newest first,
+/// limited to the given warehouses if there are any.
+pub fn page_low_stock_events(
+    conn: RepoConnection,
+    store_id: StoreId,
+    warehouse_scope: Option<Vec<WarehouseId>>,
+    page: Page,
+) -> RepoConnectionFuture<Vec<LowStockEvent>> {
+    let mut args: QueryArgs = vec![Box::new(store_id.0)];
+    let mut scope_condition = String::new();
+    if let Some(warehouse_ids) = warehouse_scope {
+        args.push(Box::new(
+            warehouse_ids.into_iter().map(|v| v.0).collect::<Vec<_>>(),
+        ));
+        scope_condition = format!(" AND warehouse_id = ANY(${})", args.len());
+    }
+
+    let page = page.normalized();
+    args.push(Box::new(page.count));
+    args.push(Box::new(page.offset));
+    let statement = format!(
+        "SELECT * FROM {} \
+         WHERE warehouse_id IN (SELECT id FROM warehouses WHERE store_id = $1){} \
+         ORDER BY created_at DESC, id LIMIT ${} OFFSET ${}",
+        EVENTS_TABLE,
+        scope_condition,
+        args.len() - 1,
+        args.len()
+    );
+
+    query::<LowStockEvent>(conn, statement, args)
+}
@@ -1,5 +1,31 @@
+pub mod query;
+
+pub mod api_keys;
+pub use self::api_keys::*;
+
+pub mod audit_log;
+pub use self::audit_log::*;
+
 pub mod warehouses;
 pub use self::warehouses::*;
 
+pub mod warehouse_status;
+pub use self::warehouse_status::*;
+
 pub mod stocks;
 pub use self::stocks::*;
+
+pub mod stock_visibility;
+pub use self::stock_visibility::*;
+
+pub mod reservations;
+pub use self::reservations::*;
+
+pub mod stock_movements;
+pub use self::stock_movements::*;
+
+pub mod transfers;
+pub use self::transfers::*;
+
+pub mod low_stock;
+pub use self::low_stock::*;
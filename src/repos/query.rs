@@ -0,0 +1,57 @@
+use futures::future;
+use futures::prelude::*;
+use futures_state_stream::StateStream;
+use stq_db::connection::*;
+use stq_db::repo::*;
+use tokio_postgres::rows::Row;
+use tokio_postgres::types::ToSql;
+
+pub type QueryArgs = Vec<Box<ToSql + Send + 'static>>;
+
+/// Runs a statement that does not fit into the generic repo builders and converts returned rows.
+pub fn query<T>(
+    conn: RepoConnection,
+    statement: String,
+    args: QueryArgs,
+) -> RepoConnectionFuture<Vec<T>>
+where
+    T: From<Row> + 'static,
+{
+    Box::new(
+        conn.prepare2(&statement)
+            .and_then(move |(statement, conn)| {
+                conn.query2(&statement, args).map(T::from).collect()
+            }),
+    )
+}
+
+/// Runs a statement ignoring its output.
+pub fn execute(conn: RepoConnection, statement: &str) -> RepoConnectionFuture<()> {
+    Box::new(query::<Row>(conn, statement.to_string(), vec![]).map(|(_, conn)| ((), conn)))
+}
+
+/// Runs the operation inside a transaction, rolling back if it fails.
+pub fn in_transaction<T, F>(conn: RepoConnection, f: F) -> RepoConnectionFuture<T>
+where
+    T: 'static,
+    F: FnOnce(RepoConnection) -> RepoConnectionFuture<T> + 'static,
+{
+    Box::new(
+        execute(conn, "BEGIN")
+            .and_then(move |((), conn)| f(conn))
+            .then(|res| -> RepoConnectionFuture<T> {
+                match res {
+                    Ok((v, conn)) => {
+                        Box::new(execute(conn, "COMMIT").map(move |((), conn)| (v, conn)))
+                    }
+                    Err((e, conn)) => Box::new(execute(conn, "ROLLBACK").then(move |res| {
+                        let conn = match res {
+                            Ok(((), conn)) => conn,
+                            Err((_, conn)) => conn,
+                        };
+                        future::err((e, conn))
+                    })),
+                }
+            }),
+    )
+}
@@ -0,0 +1,146 @@
+use models::*;
+use repos::query::*;
+
+use failure;
+use futures::prelude::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+use stq_acl::*;
+use stq_api::warehouses::Warehouse;
+use stq_db::repo::*;
+use stq_roles::models::RepoLogin;
+use stq_types::*;
+
+const TABLE: &str = "reservations";
+
+pub trait ReservationsRepo:
+    DbRepo<DbReservation, DbReservation, ReservationFilter, ReservationUpdater, RepoError>
+{
+}
+
+pub type ReservationsRepoImpl =
+    DbRepoImpl<DbReservation, DbReservation, ReservationFilter, ReservationUpdater>;
+impl ReservationsRepo for ReservationsRepoImpl {}
+
+type Repo = ReservationsRepoImpl;
+
+pub fn make_su_repo() -> Repo {
+    Repo::new(TABLE)
+}
+
+fn check_acl(
+    warehouse_source: &Rc<Fn(WarehouseId) -> Box<Future<Item = Warehouse, Error = failure::Error>>>,
+    login: UserLogin,
+    entry: DbReservation,
+    action: Action,
+) -> Verdict<(DbReservation, Action), failure::Error> {
+    Box::new(
+        (warehouse_source)(entry.0.warehouse_id)
+            .map({
+                move |warehouse| {
+                    use self::RepoLogin::*;
+                    use models::UserRole::*;
+
+                    if let User { caller_roles, .. } = login {
+                        for user_role in caller_roles {
+                            match user_role.role {
+                                // Superadmins can access in all cases.
+                                Superadmin => {
+                                    return true;
+                                }
+                                // Store managers can hold stock in the warehouses of the stores that they manage.
+                                StoreManager(managed_store_id) => {
+                                    if managed_store_id == warehouse.store_id {
+                                        return true;
+                                    }
+                                }
+                                // Warehouse managers can hold stock in the warehouse that they manage.
+                                WarehouseManager(managed_warehouse_id) => {
+                                    if managed_warehouse_id == warehouse.id {
+                                        return true;
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    // Reservations reveal orders, so unlike stocks they are not readable by everyone
+                    false
+                }
+            })
+            .then(move |v| match v {
+                Ok(d) => Ok((d, (entry, action))),
+                Err(e) => Err((e, (entry, action))),
+            }),
+    )
+}
+
+pub fn make_repo(
+    login: UserLogin,
+    warehouse_source: Rc<Fn(WarehouseId) -> Box<Future<Item = Warehouse, Error = failure::Error>>>,
+) -> Repo {
+    make_su_repo().with_afterop_acl_engine(AsyncACLFn({
+        move |(entry, action)| check_acl(&warehouse_source, login.clone(), entry, action)
+    }))
+}
+
+/// Reads the reservation bypassing the ACL, callers must authorize access to its warehouse.
+pub fn find_reservation(
+    conn: RepoConnection,
+    reservation_id: ReservationId,
+) -> RepoConnectionFuture<Option<DbReservation>> {
+    Box::new(
+        query::<DbReservation>(
+            conn,
+            format!("SELECT * FROM {} WHERE id = $1", TABLE),
+            vec![Box::new(reservation_id.0)],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Locks the reservation until the end of the current transaction.
+pub fn lock_reservation(
+    conn: RepoConnection,
+    reservation_id: ReservationId,
+) -> RepoConnectionFuture<Option<DbReservation>> {
+    Box::new(
+        query::<DbReservation>(
+            conn,
+            format!("SELECT * FROM {} WHERE id = $1 FOR UPDATE", TABLE),
+            vec![Box::new(reservation_id.0)],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Sums quantities of active holds per product in the warehouse.
+pub fn held_quantities(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    product_id: Option<ProductId>,
+) -> RepoConnectionFuture<HashMap<ProductId, Quantity>> {
+    let mut statement = format!(
+        "SELECT product_id, SUM(quantity)::INTEGER AS quantity FROM {} \
+         WHERE warehouse_id = $1 AND status = '{}' AND expires_at > now()",
+        TABLE,
+        ReservationStatus::Held.as_str()
+    );
+    let mut args: QueryArgs = vec![Box::new(warehouse_id.0)];
+    if let Some(product_id) = product_id {
+        statement.push_str(" AND product_id = $2");
+        args.push(Box::new(product_id.0));
+    }
+    statement.push_str(" GROUP BY product_id");
+
+    Box::new(
+        query::<HeldQuantity>(conn, statement, args).map(|(v, conn)| {
+            (
+                v.into_iter()
+                    .map(|HeldQuantity(product_id, quantity)| (product_id, quantity))
+                    .collect(),
+                conn,
+            )
+        }),
+    )
+}
@@ -0,0 +1,67 @@
+use models::*;
+use repos::query::*;
+
+use stq_db::repo::*;
+use stq_types::*;
+
+const TABLE: &str = "stock_movements";
+
+pub trait StockMovementsRepo:
+    DbRepo<DbStockMovement, DbStockMovement, StockMovementFilter, StockMovementUpdater, RepoError>
+{
+}
+
+pub type StockMovementsRepoImpl =
+    DbRepoImpl<DbStockMovement, DbStockMovement, StockMovementFilter, StockMovementUpdater>;
+impl StockMovementsRepo for StockMovementsRepoImpl {}
+
+type Repo = StockMovementsRepoImpl;
+
+/// Movements are only written by the service itself on behalf of already authorized stock mutations.
+pub fn make_su_repo() -> Repo {
+    Repo::new(TABLE)
+}
+
+/// Pages through movements newest first.
+/// `store_scope` limits results to warehouses of the given stores, `None` means no limit.
+pub fn page_movements(
+    conn: RepoConnection,
+    subject: StockMovementSubject,
+    store_scope: Option<Vec<StoreId>>,
+    warehouse_scope: Option<Vec<WarehouseId>>,
+    page: Page,
+) -> RepoConnectionFuture<Vec<DbStockMovement>> {
+    use self::StockMovementSubject::*;
+
+    let (subject_condition, mut args): (&str, QueryArgs) = match subject {
+        Warehouse(warehouse_id) => ("warehouse_id = $1", vec![Box::new(warehouse_id.0)]),
+        Product(product_id) => ("product_id = $1", vec![Box::new(product_id.0)]),
+        Stock(stock_id) => ("stock_id = $1", vec![Box::new(stock_id.0)]),
+    };
+
+    let mut statement = format!("SELECT * FROM {} WHERE {}", TABLE, subject_condition);
+    if let (Some(store_ids), Some(warehouse_ids)) = (store_scope, warehouse_scope) {
+        args.push(Box::new(
+            store_ids.into_iter().map(|v| v.0).collect::<Vec<i32>>(),
+        ));
+        args.push(Box::new(
+            warehouse_ids.into_iter().map(|v| v.0).collect::<Vec<_>>(),
+        ));
+        statement.push_str(&format!(
+            " AND (warehouse_id IN (SELECT id FROM warehouses WHERE store_id = ANY(${})) OR warehouse_id = ANY(${}))",
+            args.len() - 1,
+            args.len()
+        ));
+    }
+
+    let page = page.normalized();
+    args.push(Box::new(page.count));
+    args.push(Box::new(page.offset));
+    statement.push_str(&format!(
+        " ORDER BY created_at DESC, id LIMIT ${} OFFSET ${}",
+        args.len() - 1,
+        args.len()
+    ));
+
+    query::<DbStockMovement>(conn, statement, args)
+}
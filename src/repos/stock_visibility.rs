@@ -0,0 +1,126 @@
+use models::*;
+use repos;
+use repos::query::*;
+
+use futures::future;
+use futures::prelude::*;
+use std::collections::HashMap;
+use stq_db::repo::*;
+use stq_types::*;
+
+const TABLE: &str = "store_stock_visibility";
+
+/// Visibility configured by the store, the default one if it has not configured any.
+pub fn store_visibility(
+    conn: RepoConnection,
+    store_id: StoreId,
+) -> RepoConnectionFuture<StoreStockVisibility> {
+    Box::new(
+        query::<StoreStockVisibility>(
+            conn,
+            format!("SELECT * FROM {} WHERE store_id = $1", TABLE),
+            vec![Box::new(store_id.0)],
+        )
+        .map(move |(mut v, conn)| {
+            (
+                v.pop().unwrap_or(StoreStockVisibility {
+                    store_id,
+                    visibility: StockVisibility::default(),
+                }),
+                conn,
+            )
+        }),
+    )
+}
+
+pub fn set_store_visibility(
+    conn: RepoConnection,
+    store_id: StoreId,
+    visibility: StockVisibility,
+) -> RepoConnectionFuture<StoreStockVisibility> {
+    Box::new(
+        query::<StoreStockVisibility>(
+            conn,
+            format!(
+                "INSERT INTO {} (store_id, mode, cap) VALUES ($1, $2, $3) \
+                 ON CONFLICT (store_id) DO UPDATE SET mode = $2, cap = $3 RETURNING *",
+                TABLE
+            ),
+            vec![
+                Box::new(store_id.0),
+                Box::new(visibility.mode().to_string()),
+                Box::new(visibility.cap().map(|v| v.0)),
+            ],
+        )
+        .map(move |(mut v, conn)| {
+            (
+                v.pop().unwrap_or(StoreStockVisibility {
+                    store_id,
+                    visibility,
+                }),
+                conn,
+            )
+        }),
+    )
+}
+
+/// Owning store and its visibility for each of the warehouses.
+pub fn warehouses_visibility(
+    conn: RepoConnection,
+    warehouse_ids: Vec<WarehouseId>,
+) -> RepoConnectionFuture<Vec<WarehouseStockVisibility>> {
+    query::<WarehouseStockVisibility>(
+        conn,
+        format!(
+            "SELECT w.id AS warehouse_id, w.store_id, v.mode, v.cap \
+             FROM warehouses w LEFT JOIN {} v ON v.store_id = w.store_id \
+             WHERE w.id = ANY($1)",
+            TABLE
+        ),
+        vec![Box::new(
+            warehouse_ids.into_iter().map(|v| v.0).collect::<Vec<_>>(),
+        )],
+    )
+}
+
+/// Masks quantities of the stocks whose warehouses the caller does not manage,
+/// as configured by the stores owning them. Every read that returns stock quantities
+/// must either pass them through this or require the caller to manage the stocks.
+pub fn mask_stocks(
+    login: &UserLogin,
+    conn: RepoConnection,
+    stocks: Vec<StockRecord>,
+) -> RepoConnectionFuture<Vec<StockRecord>> {
+    if stocks.is_empty() || repos::warehouses::managed_store_ids(login).is_none() {
+        return Box::new(future::ok((stocks, conn)));
+    }
+
+    let login = login.clone();
+    let warehouse_ids = stocks.iter().map(|v| v.stock.warehouse_id).collect();
+    Box::new(
+        warehouses_visibility(conn, warehouse_ids).map(move |(visibility, conn)| {
+            let visibility = visibility
+                .into_iter()
+                .map(|v| (v.warehouse_id, v))
+                .collect::<HashMap<_, _>>();
+            let stocks = stocks
+                .into_iter()
+                .map(|stock| match visibility.get(&stock.stock.warehouse_id) {
+                    Some(v) => {
+                        if repos::stocks::manages_warehouse_stocks(
+                            &login,
+                            v.warehouse_id,
+                            v.store_id,
+                        ) {
+                            stock
+                        } else {
+                            v.visibility.apply(stock)
+                        }
+                    }
+                    None => StockVisibility::default().apply(stock),
+                })
+                .collect();
+            (stocks, conn)
+        }),
+    )
+}
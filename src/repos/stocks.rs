@@ -1,4 +1,5 @@
 use models::*;
+use repos::query::*;
 
 use failure;
 use futures::prelude::*;
@@ -22,6 +23,51 @@ pub fn make_su_repo() -> Repo {
     Repo::new(TABLE)
 }
 
+/// Checks whether the caller manages stocks of the warehouse and so sees their exact quantities.
+pub fn manages_warehouse_stocks(
+    login: &UserLogin,
+    warehouse_id: WarehouseId,
+    store_id: StoreId,
+) -> bool {
+    use self::RepoLogin::*;
+    use models::UserRole::*;
+
+    if let User { caller_roles, .. } = login {
+        for user_role in caller_roles {
+            match user_role.role {
+                // Superadmins can access in all cases.
+                Superadmin => {
+                    return true;
+                }
+                // Store managers can change products of the warehouses that belong to the stores that they manage.
+                StoreManager(managed_store_id) => {
+                    if managed_store_id == store_id {
+                        return true;
+                    }
+                }
+                // Warehouse managers can change products of the warehouse that they manage.
+                WarehouseManager(managed_warehouse_id) => {
+                    if managed_warehouse_id == warehouse_id {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    false
+}
+
+/// Checks whether the caller may perform the action on stocks of the warehouse.
+pub fn can_access_warehouse_stocks(
+    login: &UserLogin,
+    warehouse: &Warehouse,
+    action: &Action,
+) -> bool {
+    // Allow read-only access for everyone, services mask quantities for non-managers
+    manages_warehouse_stocks(login, warehouse.id, warehouse.store_id) || *action == Action::Select
+}
+
 fn check_acl(
     warehouse_source: &Rc<Fn(WarehouseId) -> Box<Future<Item = Warehouse, Error = failure::Error>>>,
     login: UserLogin,
@@ -29,36 +75,10 @@ fn check_acl(
     action: Action,
 ) -> Verdict<(DbStock, Action), failure::Error> {
     Box::new(
-        (warehouse_source)(entry.0.warehouse_id)
+        (warehouse_source)(entry.0.stock.warehouse_id)
             .map({
-                move |warehouse| {
-                    use self::RepoLogin::*;
-                    use models::UserRole::*;
-
-                    if let User { caller_roles, .. } = login {
-                        for user_role in caller_roles {
-                            match user_role.role {
-                                // Superadmins can access in all cases.
-                                Superadmin => {
-                                    return true;
-                                }
-                                // Store managers can change products of the warehouses that belong to the stores that they manage.
-                                StoreManager(managed_store_id) => {
-                                    if managed_store_id == warehouse.store_id {
-                                        return true;
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    // Allow read-only access for everyone
-                    if action == Action::Select {
-                        return true;
-                    }
-
-                    false
-                }
+                let action = action.clone();
+                move |warehouse| can_access_warehouse_stocks(&login, &warehouse, &action)
             })
             .then(move |v| match v {
                 Ok(d) => Ok((d, (entry, action))),
@@ -75,3 +95,284 @@ pub fn make_repo(
         move |(entry, action)| check_acl(&warehouse_source, login.clone(), entry, action)
     }))
 }
+
+/// Locks the stock of the product in the warehouse until the end of the current transaction.
+pub fn lock_stock(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    product_id: ProductId,
+) -> RepoConnectionFuture<Option<DbStock>> {
+    Box::new(
+        query::<DbStock>(
+            conn,
+            format!(
+                "SELECT * FROM {} WHERE warehouse_id = $1 AND product_id = $2 FOR UPDATE",
+                TABLE
+            ),
+            vec![Box::new(warehouse_id.0), Box::new(product_id.0)],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Units stored in the warehouse, not counting the given product.
+pub fn used_capacity(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    except_product_id: ProductId,
+) -> RepoConnectionFuture<i64> {
+    Box::new(
+        query::<UsedCapacity>(
+            conn,
+            format!(
+                "SELECT COALESCE(SUM(quantity), 0)::BIGINT AS used FROM {} \
+                 WHERE warehouse_id = $1 AND product_id <> $2",
+                TABLE
+            ),
+            vec![Box::new(warehouse_id.0), Box::new(except_product_id.0)],
+        )
+        .map(|(mut v, conn)| (v.pop().map(|v| v.0).unwrap_or(0), conn)),
+    )
+}
+
+/// Atomically adds `delta` to the stock quantity. Resolves to `None` if there is no such stock
+/// or the resulting quantity would be negative.
+/// Unlike repo methods this does not check ACL, so callers must authorize the change themselves.
+pub fn adjust_quantity(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    product_id: ProductId,
+    delta: i32,
+) -> RepoConnectionFuture<Option<DbStock>> {
+    Box::new(
+        query::<DbStock>(
+            conn,
+            format!(
+                "UPDATE {} SET quantity = quantity + $3 \
+                 WHERE warehouse_id = $1 AND product_id = $2 AND quantity + $3 >= 0 \
+                 RETURNING *",
+                TABLE
+            ),
+            vec![
+                Box::new(warehouse_id.0),
+                Box::new(product_id.0),
+                Box::new(delta),
+            ],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Like `adjust_quantity`, but also resolves to `None` if the resulting quantity would not cover
+/// the units held by active reservations, which must stay available for their commit.
+pub fn adjust_unreserved_quantity(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    product_id: ProductId,
+    delta: i32,
+) -> RepoConnectionFuture<Option<DbStock>> {
+    Box::new(
+        query::<DbStock>(
+            conn,
+            format!(
+                "UPDATE {table} SET quantity = quantity + $3 \
+                 WHERE warehouse_id = $1 AND product_id = $2 AND quantity + $3 >= {held} \
+                 RETURNING *",
+                table = TABLE,
+                held = held_quantity(TABLE),
+            ),
+            vec![
+                Box::new(warehouse_id.0),
+                Box::new(product_id.0),
+                Box::new(delta),
+            ],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Units of the stock row `stock` held by active reservations, as an SQL expression.
+fn held_quantity(stock: &str) -> String {
+    format!(
+        "COALESCE(( \
+         SELECT SUM(r.quantity) FROM reservations r \
+         WHERE r.warehouse_id = {stock}.warehouse_id AND r.product_id = {stock}.product_id \
+         AND r.status = '{held}' AND r.expires_at > now() \
+         ), 0)",
+        stock = stock,
+        held = ReservationStatus::Held.as_str(),
+    )
+}
+
+/// Atomically adds a non-negative `delta` to the stock quantity, creating the stock if needed.
+/// Unlike repo methods this does not check ACL, so callers must authorize the change themselves.
+pub fn add_quantity(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    product_id: ProductId,
+    delta: i32,
+) -> RepoConnectionFuture<DbStock> {
+    Box::new(
+        query::<DbStock>(
+            conn,
+            format!(
+                "INSERT INTO {table} (id, warehouse_id, product_id, quantity) VALUES ($1, $2, $3, $4) \
+                 ON CONFLICT (warehouse_id, product_id) DO UPDATE SET quantity = {table}.quantity + $4 \
+                 RETURNING *",
+                table = TABLE
+            ),
+            vec![
+                Box::new(StockId::new().0),
+                Box::new(warehouse_id.0),
+                Box::new(product_id.0),
+                Box::new(delta),
+            ],
+        )
+        .and_then(move |(mut v, conn)| match v.pop() {
+            Some(stock) => Ok((stock, conn)),
+            None => Err((
+                format_err!(
+                    "No stock returned for product {} in warehouse {}",
+                    product_id,
+                    warehouse_id
+                ),
+                conn,
+            )),
+        }),
+    )
+}
+
+/// Quantities of the products that warehouses of the store can promise, excluding active reservations.
+pub fn available_quantities(
+    conn: RepoConnection,
+    store_id: StoreId,
+    product_ids: Vec<ProductId>,
+) -> RepoConnectionFuture<Vec<AvailableQuantity>> {
+    query::<AvailableQuantity>(
+        conn,
+        format!(
+            "SELECT s.warehouse_id, s.product_id, (s.quantity - {held})::INTEGER AS quantity \
+             FROM {table} s JOIN warehouses w ON w.id = s.warehouse_id \
+             WHERE w.store_id = $1 AND NOT w.is_archived AND w.status = '{active}' \
+             AND s.product_id = ANY($2)",
+            held = held_quantity("s"),
+            active = WarehouseStatus::Active.as_str(),
+            table = TABLE
+        ),
+        vec![
+            Box::new(store_id.0),
+            Box::new(product_ids.into_iter().map(|v| v.0).collect::<Vec<i32>>()),
+        ],
+    )
+}
+
+/// Fetches the stocks matching the query that come after its cursor in the requested order.
+pub fn page_stocks(
+    conn: RepoConnection,
+    list_query: StockListQuery,
+    limit: i64,
+) -> RepoConnectionFuture<Vec<DbStock>> {
+    let mut args: QueryArgs = vec![];
+    let mut conditions = vec![];
+
+    // Stocks of archived, paused and closed warehouses are retained but not listed as available
+    let available_warehouses = format!(
+        "SELECT id FROM warehouses WHERE NOT is_archived AND status = '{}'",
+        WarehouseStatus::Active.as_str()
+    );
+    if let Some(store_id) = list_query.store_id {
+        args.push(Box::new(store_id.0));
+        conditions.push(format!(
+            "warehouse_id IN ({} AND store_id = ${})",
+            available_warehouses,
+            args.len()
+        ));
+    } else {
+        conditions.push(format!("warehouse_id IN ({})", available_warehouses));
+    }
+
+    if !list_query.warehouse_ids.is_empty() {
+        args.push(Box::new(
+            list_query
+                .warehouse_ids
+                .into_iter()
+                .map(|v| v.0)
+                .collect::<Vec<_>>(),
+        ));
+        conditions.push(format!("warehouse_id = ANY(${})", args.len()));
+    }
+
+    if !list_query.product_ids.is_empty() {
+        args.push(Box::new(
+            list_query
+                .product_ids
+                .into_iter()
+                .map(|v| v.0)
+                .collect::<Vec<_>>(),
+        ));
+        conditions.push(format!("product_id = ANY(${})", args.len()));
+    }
+
+    if let Some(min_quantity) = list_query.min_quantity {
+        args.push(Box::new(min_quantity.0));
+        conditions.push(format!("quantity >= ${}", args.len()));
+    }
+
+    if let Some(max_quantity) = list_query.max_quantity {
+        args.push(Box::new(max_quantity.0));
+        conditions.push(format!("quantity <= ${}", args.len()));
+    }
+
+    if let Some(updated_since) = list_query.updated_since {
+        args.push(Box::new(updated_since));
+        conditions.push(format!("updated_at >= ${}", args.len()));
+    }
+
+    let sort_column = list_query.sort.column();
+    let (comparison, direction) = if list_query.descending {
+        ("<", "DESC")
+    } else {
+        (">", "ASC")
+    };
+
+    if let Some(after) = list_query.after {
+        args.push(Box::new(after.key));
+        args.push(Box::new(after.id.0));
+        conditions.push(format!(
+            "({}, id) {} (${}, ${})",
+            sort_column,
+            comparison,
+            args.len() - 1,
+            args.len()
+        ));
+    }
+
+    let mut statement = format!("SELECT * FROM {} WHERE {}", TABLE, conditions.join(" AND "));
+
+    args.push(Box::new(limit));
+    statement.push_str(&format!(
+        " ORDER BY {column} {direction}, id {direction} LIMIT ${limit}",
+        column = sort_column,
+        direction = direction,
+        limit = args.len()
+    ));
+
+    query::<DbStock>(conn, statement, args)
+}
+
+/// Stocks of the product in all warehouses that are active and not archived.
+pub fn stocks_of_product(
+    conn: RepoConnection,
+    product_id: ProductId,
+) -> RepoConnectionFuture<Vec<DbStock>> {
+    query::<DbStock>(
+        conn,
+        format!(
+            "SELECT * FROM {} WHERE product_id = $1 \
+             AND warehouse_id IN (SELECT id FROM warehouses WHERE NOT is_archived AND status = '{}')",
+            TABLE,
+            WarehouseStatus::Active.as_str()
+        ),
+        vec![Box::new(product_id.0)],
+    )
+}
@@ -0,0 +1,81 @@
+use models::*;
+use repos::query::*;
+
+use futures::prelude::*;
+use std::collections::HashMap;
+use stq_db::repo::*;
+use stq_types::*;
+
+const TABLE: &str = "transfers";
+const LINES_TABLE: &str = "transfer_lines";
+
+pub trait TransfersRepo:
+    DbRepo<DbTransfer, DbTransfer, TransferFilter, TransferUpdater, RepoError>
+{
+}
+
+pub type TransfersRepoImpl = DbRepoImpl<DbTransfer, DbTransfer, TransferFilter, TransferUpdater>;
+impl TransfersRepo for TransfersRepoImpl {}
+
+pub trait TransferLinesRepo:
+    DbRepo<DbTransferLine, DbTransferLine, TransferLineFilter, TransferLineUpdater, RepoError>
+{
+}
+
+pub type TransferLinesRepoImpl =
+    DbRepoImpl<DbTransferLine, DbTransferLine, TransferLineFilter, TransferLineUpdater>;
+impl TransferLinesRepo for TransferLinesRepoImpl {}
+
+/// Transfers touch two warehouses at once, so the service authorizes both sides itself.
+pub fn make_su_repo() -> TransfersRepoImpl {
+    TransfersRepoImpl::new(TABLE)
+}
+
+pub fn make_lines_su_repo() -> TransferLinesRepoImpl {
+    TransferLinesRepoImpl::new(LINES_TABLE)
+}
+
+/// Locks the transfer until the end of the current transaction.
+pub fn lock_transfer(
+    conn: RepoConnection,
+    transfer_id: TransferId,
+) -> RepoConnectionFuture<Option<DbTransfer>> {
+    Box::new(
+        query::<DbTransfer>(
+            conn,
+            format!("SELECT * FROM {} WHERE id = $1 FOR UPDATE", TABLE),
+            vec![Box::new(transfer_id.0)],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Sums quantities of shipped but not yet received goods per product heading to the warehouse.
+pub fn in_transit_quantities(
+    conn: RepoConnection,
+    destination_warehouse_id: WarehouseId,
+) -> RepoConnectionFuture<HashMap<ProductId, Quantity>> {
+    Box::new(
+        query::<InTransitQuantity>(
+            conn,
+            format!(
+                "SELECT l.product_id, SUM(l.quantity)::INTEGER AS quantity \
+                 FROM {} l JOIN {} t ON t.id = l.transfer_id \
+                 WHERE t.destination_warehouse_id = $1 AND t.status = '{}' \
+                 GROUP BY l.product_id",
+                LINES_TABLE,
+                TABLE,
+                TransferStatus::Shipped.as_str()
+            ),
+            vec![Box::new(destination_warehouse_id.0)],
+        )
+        .map(|(v, conn)| {
+            (
+                v.into_iter()
+                    .map(|InTransitQuantity(product_id, quantity)| (product_id, quantity))
+                    .collect(),
+                conn,
+            )
+        }),
+    )
+}
@@ -0,0 +1,57 @@
+use models::*;
+use repos::query::*;
+
+use futures::prelude::*;
+use stq_db::repo::*;
+use stq_types::*;
+
+const TABLE: &str = "warehouse_status_changes";
+
+/// Appends the change to the status history of the warehouse.
+pub fn record_status_change(
+    conn: RepoConnection,
+    change: WarehouseStatusChange,
+) -> RepoConnectionFuture<WarehouseStatusChange> {
+    Box::new(
+        query::<WarehouseStatusChange>(
+            conn,
+            format!(
+                "INSERT INTO {} (id, warehouse_id, status_before, status, changed_by, api_key_id, changed_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+                TABLE
+            ),
+            vec![
+                Box::new(change.id.0),
+                Box::new(change.warehouse_id.0),
+                Box::new(change.status_before.as_str().to_string()),
+                Box::new(change.status.as_str().to_string()),
+                Box::new(change.changed_by.map(|v| v.0)),
+                Box::new(change.api_key_id.map(|v| v.0)),
+                Box::new(change.changed_at),
+            ],
+        )
+        .map(move |(mut v, conn)| (v.pop().unwrap_or(change), conn)),
+    )
+}
+
+/// Pages through status changes of the warehouse newest first.
+pub fn page_status_changes(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    page: Page,
+) -> RepoConnectionFuture<Vec<WarehouseStatusChange>> {
+    let page = page.normalized();
+    query::<WarehouseStatusChange>(
+        conn,
+        format!(
+            "SELECT * FROM {} WHERE warehouse_id = $1 \
+             ORDER BY changed_at DESC, id LIMIT $2 OFFSET $3",
+            TABLE
+        ),
+        vec![
+            Box::new(warehouse_id.0),
+            Box::new(page.count),
+            Box::new(page.offset),
+        ],
+    )
+}
@@ -1,11 +1,16 @@
 use models::*;
+use repos::query::*;
 
+use futures::prelude::*;
 use stq_acl::*;
 use stq_db::repo::*;
 use stq_db::sequence::*;
+use stq_types::*;
+use tokio_postgres::rows::Row;
 
 const TABLE: &str = "warehouses";
 const SLUG_SEQUENCE: &str = "warehouse_slug_seq";
+const SLUG_HISTORY_TABLE: &str = "warehouse_slug_history";
 
 pub trait WarehouseRepo:
     DbRepo<DbWarehouse, DbWarehouse, WarehouseFilter, WarehouseUpdater, RepoError>
@@ -37,10 +42,12 @@ fn check_acl(login: UserLogin, (entry, action): &mut AclContext) -> bool {
                 }
                 // Store managers can do anything to the warehouses of their stores.
                 StoreManager(managed_store_id) => {
-                    if managed_store_id == entry.0.store_id {
+                    if managed_store_id == entry.0.warehouse.store_id {
                         return true;
                     }
                 }
+                // Warehouse managers only manage stock, the warehouse itself is read-only to them.
+                WarehouseManager(_) => {}
             }
         }
     }
@@ -53,6 +60,53 @@ fn check_acl(login: UserLogin, (entry, action): &mut AclContext) -> bool {
     false
 }
 
+/// Stores whose data the caller may manage, `None` if the caller may manage all of them.
+pub fn managed_store_ids(login: &UserLogin) -> Option<Vec<StoreId>> {
+    use self::RepoLogin::*;
+    use models::UserRole::*;
+
+    let mut store_ids = vec![];
+    if let User { caller_roles, .. } = login {
+        for user_entry in caller_roles {
+            match user_entry.role {
+                Superadmin => {
+                    return None;
+                }
+                StoreManager(managed_store_id) => {
+                    store_ids.push(managed_store_id);
+                }
+                WarehouseManager(_) => {}
+            }
+        }
+    }
+
+    Some(store_ids)
+}
+
+/// Warehouses whose stocks the caller manages directly, `None` if the caller may manage all of them.
+/// Warehouses of the stores in `managed_store_ids` are not included.
+pub fn managed_warehouse_ids(login: &UserLogin) -> Option<Vec<WarehouseId>> {
+    use self::RepoLogin::*;
+    use models::UserRole::*;
+
+    let mut warehouse_ids = vec![];
+    if let User { caller_roles, .. } = login {
+        for user_entry in caller_roles {
+            match user_entry.role {
+                Superadmin => {
+                    return None;
+                }
+                StoreManager(_) => {}
+                WarehouseManager(managed_warehouse_id) => {
+                    warehouse_ids.push(managed_warehouse_id);
+                }
+            }
+        }
+    }
+
+    Some(warehouse_ids)
+}
+
 pub fn make_repo(login: UserLogin) -> Repo {
     make_su_repo().with_afterop_acl_engine(InfallibleSyncACLFn(move |ctx: &mut AclContext| {
         check_acl(login.clone(), ctx)
@@ -66,3 +120,241 @@ impl WarehouseSlugSequence for WarehouseSlugSequenceImpl {}
 pub fn make_slug_sequence() -> WarehouseSlugSequenceImpl {
     WarehouseSlugSequenceImpl::new(SLUG_SEQUENCE)
 }
+
+/// Finds the warehouse by its id, or by its current or a former slug.
+pub fn find_by_identifier(
+    conn: RepoConnection,
+    identifier: WarehouseIdentifier,
+) -> RepoConnectionFuture<Option<DbWarehouse>> {
+    let (statement, args): (String, QueryArgs) = match identifier {
+        WarehouseIdentifier::Id(id) => (
+            format!("SELECT * FROM {} WHERE id = $1", TABLE),
+            vec![Box::new(id.0)],
+        ),
+        WarehouseIdentifier::Slug(slug) => (
+            format!(
+                "SELECT * FROM {table} WHERE slug = $1 \
+                 OR id IN (SELECT warehouse_id FROM {history} WHERE slug = $1) \
+                 ORDER BY slug = $1 DESC LIMIT 1",
+                table = TABLE,
+                history = SLUG_HISTORY_TABLE
+            ),
+            vec![Box::new(slug.0)],
+        ),
+    };
+
+    Box::new(query::<DbWarehouse>(conn, statement, args).map(|(mut v, conn)| (v.pop(), conn)))
+}
+
+/// Serializes claims of the slug until the end of the current transaction.
+pub fn lock_slug(conn: RepoConnection, slug: &WarehouseSlug) -> RepoConnectionFuture<()> {
+    Box::new(
+        query::<Row>(
+            conn,
+            "SELECT 1 AS locked FROM (SELECT pg_advisory_xact_lock(hashtext($1))) AS l".to_string(),
+            vec![Box::new(slug.0.clone())],
+        )
+        .map(|(_, conn)| ((), conn)),
+    )
+}
+
+/// Keeps the former slug pointing at the warehouse, releasing the new one from the history.
+pub fn record_slug_change(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    former_slug: WarehouseSlug,
+    slug: WarehouseSlug,
+) -> RepoConnectionFuture<()> {
+    Box::new(
+        query::<Row>(
+            conn,
+            format!("DELETE FROM {} WHERE slug = $1", SLUG_HISTORY_TABLE),
+            vec![Box::new(slug.0)],
+        )
+        .and_then(move |(_, conn)| {
+            query::<Row>(
+                conn,
+                format!(
+                    "INSERT INTO {} (slug, warehouse_id) VALUES ($1, $2)",
+                    SLUG_HISTORY_TABLE
+                ),
+                vec![Box::new(former_slug.0), Box::new(warehouse_id.0)],
+            )
+        })
+        .map(|(_, conn)| ((), conn)),
+    )
+}
+
+/// Locks the warehouse until the end of the current transaction.
+pub fn lock_warehouse(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+) -> RepoConnectionFuture<Option<DbWarehouse>> {
+    Box::new(
+        query::<DbWarehouse>(
+            conn,
+            format!("SELECT * FROM {} WHERE id = $1 FOR UPDATE", TABLE),
+            vec![Box::new(warehouse_id.0)],
+        )
+        .map(|(mut v, conn)| (v.pop(), conn)),
+    )
+}
+
+/// Locks the active warehouses of the store until the end of the current transaction.
+pub fn lock_store_warehouses(
+    conn: RepoConnection,
+    store_id: StoreId,
+) -> RepoConnectionFuture<Vec<DbWarehouse>> {
+    query::<DbWarehouse>(
+        conn,
+        format!(
+            "SELECT * FROM {} WHERE store_id = $1 AND NOT is_archived FOR UPDATE",
+            TABLE
+        ),
+        vec![Box::new(store_id.0)],
+    )
+}
+
+/// Warehouses with a location ordered by great-circle (haversine) distance from the queried point.
+pub fn nearest_warehouses(
+    conn: RepoConnection,
+    search: NearestWarehouseQuery,
+    page: Page,
+) -> RepoConnectionFuture<Vec<WarehouseWithDistance>> {
+    // POINT stores longitude as [0] and latitude as [1]
+    let distance = format!(
+        "{radius} * 2 * ASIN(LEAST(1, SQRT(\
+         POWER(SIN(RADIANS(location[1] - ($1::point)[1]) / 2), 2) + \
+         COS(RADIANS(($1::point)[1])) * COS(RADIANS(location[1])) * \
+         POWER(SIN(RADIANS(location[0] - ($1::point)[0]) / 2), 2))))",
+        radius = EARTH_RADIUS_METERS
+    );
+
+    let mut args: QueryArgs = vec![Box::new(search.location)];
+    let mut conditions = vec![
+        "location IS NOT NULL".to_string(),
+        "NOT is_archived".to_string(),
+    ];
+    if let Some(store_id) = search.store_id {
+        args.push(Box::new(store_id.0));
+        conditions.push(format!("store_id = ${}", args.len()));
+    }
+
+    let mut statement = format!(
+        "SELECT * FROM (SELECT *, {} AS distance FROM {} WHERE {}) AS w",
+        distance,
+        TABLE,
+        conditions.join(" AND ")
+    );
+    if let Some(radius_meters) = search.radius_meters {
+        args.push(Box::new(radius_meters));
+        statement.push_str(&format!(" WHERE distance <= ${}", args.len()));
+    }
+
+    let page = page.normalized();
+    args.push(Box::new(page.count));
+    args.push(Box::new(page.offset));
+    statement.push_str(&format!(
+        " ORDER BY distance, id LIMIT ${} OFFSET ${}",
+        args.len() - 1,
+        args.len()
+    ));
+
+    query::<WarehouseWithDistance>(conn, statement, args)
+}
+
+/// Turns user input into a case-insensitive substring pattern for `ILIKE`.
+fn contains_pattern(value: &str) -> String {
+    format!(
+        "%{}%",
+        value
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    )
+}
+
+/// Pages through warehouses matching the search ordered by store and slug.
+pub fn search_warehouses(
+    conn: RepoConnection,
+    search: WarehouseSearch,
+) -> RepoConnectionFuture<Vec<DbWarehouse>> {
+    let WarehouseSearch {
+        filter,
+        name,
+        address,
+        archived,
+        updated_since,
+        page,
+    } = search;
+
+    let mut args: QueryArgs = vec![Box::new(archived)];
+    let mut conditions = vec!["is_archived = $1".to_string()];
+
+    if let Some(store_id) = filter.store_id {
+        args.push(Box::new(store_id.value.0));
+        conditions.push(format!("store_id = ${}", args.len()));
+    }
+
+    if let Some(slug) = filter.slug {
+        args.push(Box::new(slug.value.0));
+        conditions.push(format!("slug = ${}", args.len()));
+    }
+
+    let optional_columns = vec![
+        ("country", filter.country.map(|v| v.value)),
+        (
+            "country_code",
+            filter.country_code.map(|v| v.value.map(|v| v.0)),
+        ),
+        (
+            "administrative_area_level_1",
+            filter.administrative_area_level_1.map(|v| v.value),
+        ),
+        (
+            "administrative_area_level_2",
+            filter.administrative_area_level_2.map(|v| v.value),
+        ),
+        ("locality", filter.locality.map(|v| v.value)),
+        ("political", filter.political.map(|v| v.value)),
+        ("postal_code", filter.postal_code.map(|v| v.value)),
+        ("route", filter.route.map(|v| v.value)),
+        ("street_number", filter.street_number.map(|v| v.value)),
+        ("place_id", filter.place_id.map(|v| v.value)),
+    ];
+    for (column, value) in optional_columns {
+        match value {
+            None => {}
+            Some(None) => conditions.push(format!("{} IS NULL", column)),
+            Some(Some(value)) => {
+                args.push(Box::new(value));
+                conditions.push(format!("{} = ${}", column, args.len()));
+            }
+        }
+    }
+
+    if let Some(updated_since) = updated_since {
+        args.push(Box::new(updated_since));
+        conditions.push(format!("updated_at >= ${}", args.len()));
+    }
+
+    for (column, value) in vec![("name", name), ("address", address)] {
+        if let Some(value) = value {
+            args.push(Box::new(contains_pattern(&value)));
+            conditions.push(format!("{} ILIKE ${}", column, args.len()));
+        }
+    }
+
+    let mut statement = format!("SELECT * FROM {} WHERE {}", TABLE, conditions.join(" AND "));
+
+    let page = page.normalized();
+    args.push(Box::new(page.count));
+    args.push(Box::new(page.offset));
+    statement.push_str(&format!(
+        " ORDER BY store_id, slug LIMIT ${} OFFSET ${}",
+        args.len() - 1,
+        args.len()
+    ));
+
+    query::<DbWarehouse>(conn, statement, args)
+}
@@ -1,3 +1,57 @@
+table! {
+    api_keys (id) {
+        id -> Uuid,
+        key_hash -> Varchar,
+        name -> Varchar,
+        store_id -> Int4,
+        warehouse_ids -> Array<Uuid>,
+        scope -> Varchar,
+        created_by -> Int4,
+        created_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Uuid,
+        actor_id -> Nullable<Int4>,
+        api_key_id -> Nullable<Uuid>,
+        entity_type -> Varchar,
+        entity_id -> Varchar,
+        action -> Varchar,
+        old_value -> Nullable<Jsonb>,
+        new_value -> Nullable<Jsonb>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    low_stock_events (id) {
+        id -> Uuid,
+        stock_id -> Uuid,
+        warehouse_id -> Uuid,
+        product_id -> Int4,
+        movement_id -> Uuid,
+        quantity -> Int4,
+        threshold -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    reservations (id) {
+        id -> Uuid,
+        warehouse_id -> Uuid,
+        product_id -> Int4,
+        order_id -> Varchar,
+        quantity -> Int4,
+        status -> Varchar,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     roles (id) {
         id -> Uuid,
@@ -7,12 +61,83 @@ table! {
     }
 }
 
+table! {
+    stock_movements (id) {
+        id -> Uuid,
+        stock_id -> Uuid,
+        warehouse_id -> Uuid,
+        product_id -> Int4,
+        quantity_before -> Int4,
+        quantity_after -> Int4,
+        delta -> Int4,
+        user_id -> Nullable<Int4>,
+        reason -> Varchar,
+        created_at -> Timestamptz,
+        api_key_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    store_stock_visibility (store_id) {
+        store_id -> Int4,
+        mode -> Varchar,
+        cap -> Nullable<Int4>,
+    }
+}
+
 table! {
     stocks (id) {
         id -> Uuid,
         warehouse_id -> Nullable<Uuid>,
         product_id -> Int4,
         quantity -> Int4,
+        version -> Int4,
+        reorder_threshold -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    transfer_lines (transfer_id, product_id) {
+        transfer_id -> Uuid,
+        product_id -> Int4,
+        quantity -> Int4,
+    }
+}
+
+table! {
+    transfers (id) {
+        id -> Uuid,
+        source_warehouse_id -> Uuid,
+        destination_warehouse_id -> Uuid,
+        status -> Varchar,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        shipped_at -> Nullable<Timestamptz>,
+        received_at -> Nullable<Timestamptz>,
+        cancelled_at -> Nullable<Timestamptz>,
+        api_key_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    warehouse_slug_history (slug) {
+        slug -> Varchar,
+        warehouse_id -> Uuid,
+        replaced_at -> Timestamptz,
+    }
+}
+
+table! {
+    warehouse_status_changes (id) {
+        id -> Uuid,
+        warehouse_id -> Uuid,
+        status_before -> Varchar,
+        status -> Varchar,
+        changed_by -> Nullable<Int4>,
+        changed_at -> Timestamptz,
+        api_key_id -> Nullable<Uuid>,
     }
 }
 
@@ -34,13 +159,41 @@ table! {
         address -> Nullable<Varchar>,
         place_id -> Nullable<Varchar>,
         country_code -> Nullable<Varchar>,
+        version -> Int4,
+        default_reorder_threshold -> Nullable<Int4>,
+        is_archived -> Bool,
+        archived_at -> Nullable<Timestamptz>,
+        status -> Varchar,
+        time_zone -> Nullable<Varchar>,
+        opening_hours -> Jsonb,
+        holidays -> Array<Date>,
+        capacity -> Nullable<Int4>,
+        product_capacities -> Jsonb,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
+joinable!(low_stock_events -> stocks (stock_id));
+joinable!(low_stock_events -> warehouses (warehouse_id));
+joinable!(reservations -> warehouses (warehouse_id));
 joinable!(stocks -> warehouses (warehouse_id));
+joinable!(transfer_lines -> transfers (transfer_id));
+joinable!(warehouse_slug_history -> warehouses (warehouse_id));
+joinable!(warehouse_status_changes -> warehouses (warehouse_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_log,
+    low_stock_events,
+    reservations,
     roles,
+    stock_movements,
     stocks,
+    store_stock_visibility,
+    transfer_lines,
+    transfers,
+    warehouse_slug_history,
+    warehouse_status_changes,
     warehouses,
 );
@@ -0,0 +1,121 @@
+use super::warehouse::{ensure_manages_store, RepoFactory};
+use super::ServiceFuture;
+use cache::Caches;
+use models::*;
+use repos;
+use types::DbPool;
+
+use futures::future;
+use futures::prelude::*;
+use std::collections::HashMap;
+use stq_types::*;
+
+pub trait AllocationService {
+    /// Propose which warehouses of the store should ship which lines of the basket, without reserving anything
+    fn plan_allocation(
+        &self,
+        store_id: StoreId,
+        input: AllocationInput,
+    ) -> ServiceFuture<AllocationPlan>;
+}
+
+pub struct AllocationServiceImpl {
+    pub repo_factory: RepoFactory,
+    pub db_pool: DbPool,
+    pub login: UserLogin,
+}
+
+impl AllocationServiceImpl {
+    pub fn new(db_pool: &DbPool, login: &UserLogin, caches: &Caches) -> Self {
+        Self {
+            db_pool: db_pool.clone(),
+            login: login.clone(),
+            repo_factory: RepoFactory::new(db_pool, login, caches),
+        }
+    }
+}
+
+impl AllocationService for AllocationServiceImpl {
+    fn plan_allocation(
+        &self,
+        store_id: StoreId,
+        input: AllocationInput,
+    ) -> ServiceFuture<AllocationPlan> {
+        // Plans reveal exact availability, so they are only made for managers of the store
+        if let Err(e) = input
+            .validate()
+            .and_then(|()| ensure_manages_store(&self.login, store_id))
+        {
+            return Box::new(future::err(e));
+        }
+
+        let repo_factory = self.repo_factory.clone();
+        Box::new(
+            self.db_pool
+                .run({
+                    let input = input.clone();
+                    move |conn| {
+                        (repo_factory.warehouse_repo_factory)()
+                            .select(
+                                conn,
+                                WarehouseFilter {
+                                    store_id: Some(store_id.into()),
+                                    ..Default::default()
+                                },
+                            )
+                            .and_then(move |(warehouses, conn)| {
+                                let product_ids =
+                                    input.lines.iter().map(|v| v.product_id).collect();
+                                repos::stocks::available_quantities(conn, store_id, product_ids)
+                                    .map(move |(quantities, conn)| {
+                                        let mut available = HashMap::<
+                                            WarehouseId,
+                                            HashMap<ProductId, Quantity>,
+                                        >::new(
+                                        );
+                                        for AvailableQuantity(warehouse_id, product_id, quantity) in
+                                            quantities
+                                        {
+                                            available
+                                                .entry(warehouse_id)
+                                                .or_default()
+                                                .insert(product_id, quantity);
+                                        }
+
+                                        let destination = input.destination();
+                                        let candidates = warehouses
+                                            .into_iter()
+                                            .filter_map(|DbWarehouse(warehouse)| {
+                                                let available =
+                                                    available.remove(&warehouse.warehouse.id)?;
+                                                Some(AllocationCandidate {
+                                                    warehouse_id: warehouse.warehouse.id,
+                                                    distance_meters: warehouse
+                                                        .warehouse
+                                                        .location
+                                                        .map(|location| {
+                                                            great_circle_distance(
+                                                                location,
+                                                                destination,
+                                                            )
+                                                        }),
+                                                    available,
+                                                })
+                                            })
+                                            .collect();
+
+                                        (plan_allocation(&input.lines, candidates), conn)
+                                    })
+                            })
+                    }
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to plan allocation of {:?} for store {}",
+                        input, store_id
+                    ))
+                    .into()
+                }),
+        )
+    }
+}
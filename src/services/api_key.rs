@@ -0,0 +1,188 @@
+use super::warehouse::ensure_manages_store;
+use super::ServiceFuture;
+use errors::*;
+use models::*;
+use repos;
+use types::DbPool;
+
+use chrono::prelude::*;
+use failure;
+use futures::future;
+use futures::prelude::*;
+use stq_db::repo::*;
+use stq_roles::service::get_login_data;
+use stq_types::*;
+
+pub trait ApiKeyService {
+    /// Issue a key for the store, returning its secret once
+    fn create_api_key(&self, input: NewApiKey) -> ServiceFuture<IssuedApiKey>;
+    fn list_api_keys(&self, store_id: StoreId) -> ServiceFuture<Vec<ApiKey>>;
+    /// Stop accepting the key, keeping it listed
+    fn revoke_api_key(&self, api_key_id: ApiKeyId) -> ServiceFuture<Option<ApiKey>>;
+}
+
+pub struct ApiKeyServiceImpl {
+    pub db_pool: DbPool,
+    pub login: UserLogin,
+}
+
+impl ApiKeyServiceImpl {
+    pub fn new(db_pool: &DbPool, login: &UserLogin) -> Self {
+        Self {
+            db_pool: db_pool.clone(),
+            login: login.clone(),
+        }
+    }
+}
+
+impl ApiKeyService for ApiKeyServiceImpl {
+    fn create_api_key(&self, mut input: NewApiKey) -> ServiceFuture<IssuedApiKey> {
+        let created_by = match caller_id(&self.login) {
+            Some(v) => v,
+            None => {
+                return Box::new(future::err(
+                    format_err!("Anonymous callers cannot create API keys")
+                        .context(Error::Forbidden)
+                        .into(),
+                ))
+            }
+        };
+        if let Err(e) = input
+            .validate()
+            .and_then(|()| ensure_manages_store(&self.login, input.store_id))
+        {
+            return Box::new(future::err(e));
+        }
+
+        input.warehouse_ids.sort_by_key(|v| v.0);
+        input.warehouse_ids.dedup();
+
+        let key = generate_api_key();
+        let api_key = ApiKey {
+            id: ApiKeyId::new(),
+            name: input.name.clone(),
+            store_id: input.store_id,
+            warehouse_ids: input.warehouse_ids.clone(),
+            scope: input.scope,
+            created_by,
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+        Box::new(
+            self.db_pool
+                .run({
+                    let key_hash = hash_api_key(&key);
+                    move |conn| {
+                        repos::api_keys::count_foreign_warehouses(
+                            conn,
+                            api_key.store_id,
+                            api_key.warehouse_ids.clone(),
+                        )
+                        .and_then(move |(foreign, conn)| {
+                            if foreign > 0 {
+                                Box::new(future::err((
+                                    format_err!(
+                                        "API key may only be limited to warehouses of store {}",
+                                        api_key.store_id
+                                    )
+                                    .context(Error::InvalidInput)
+                                    .into(),
+                                    conn,
+                                ))) as RepoConnectionFuture<ApiKey>
+                            } else {
+                                repos::api_keys::insert_api_key(conn, api_key, key_hash)
+                            }
+                        })
+                    }
+                })
+                .map(move |api_key| {
+                    info!(
+                        "User {} created API key {} for store {}",
+                        created_by.0, api_key.id, api_key.store_id
+                    );
+                    IssuedApiKey { api_key, key }
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to create API key {:?}", input))
+                        .into()
+                }),
+        )
+    }
+
+    fn list_api_keys(&self, store_id: StoreId) -> ServiceFuture<Vec<ApiKey>> {
+        if let Err(e) = ensure_manages_store(&self.login, store_id) {
+            return Box::new(future::err(e));
+        }
+
+        Box::new(
+            self.db_pool
+                .run(move |conn| repos::api_keys::store_api_keys(conn, store_id))
+                .map_err(move |e| {
+                    e.context(format!("Failed to list API keys of store {}", store_id))
+                        .into()
+                }),
+        )
+    }
+
+    fn revoke_api_key(&self, api_key_id: ApiKeyId) -> ServiceFuture<Option<ApiKey>> {
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    repos::api_keys::get_api_key(conn, api_key_id).and_then(
+                        move |(api_key, conn)| match api_key {
+                            None => Box::new(future::ok((None, conn)))
+                                as RepoConnectionFuture<Option<ApiKey>>,
+                            Some(api_key) => match ensure_manages_store(&login, api_key.store_id) {
+                                Ok(()) => {
+                                    repos::api_keys::revoke_api_key(conn, api_key_id, Utc::now())
+                                }
+                                Err(e) => Box::new(future::err((e, conn))),
+                            },
+                        },
+                    )
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to revoke API key {}", api_key_id))
+                        .into()
+                }),
+        )
+    }
+}
+
+/// Finds the active key with the given secret, failing with `Unauthorized` if there is none
+/// or if the user who created it no longer manages its store.
+pub fn find_api_key(db_pool: &DbPool, key: String) -> ServiceFuture<ApiKey> {
+    let key_hash = hash_api_key(&key);
+    Box::new(
+        db_pool
+            .run(move |conn| repos::api_keys::find_active_by_hash(conn, key_hash))
+            .and_then(|api_key| {
+                api_key.ok_or_else(|| {
+                    format_err!("Unknown or revoked API key")
+                        .context(Error::Unauthorized)
+                        .into()
+                })
+            })
+            .and_then({
+                let db_pool = db_pool.clone();
+                move |api_key| {
+                    // Keys act with the rights of their creator, which may have been taken away since
+                    get_login_data(&db_pool, Some(api_key.created_by)).and_then(move |creator| {
+                        match ensure_manages_store(&creator, api_key.store_id) {
+                            Ok(()) => Ok(api_key),
+                            Err(_) => Err(format_err!(
+                                "API key {} was issued by user {} who no longer manages store {}",
+                                api_key.id,
+                                api_key.created_by.0,
+                                api_key.store_id
+                            )
+                            .context(Error::Unauthorized)
+                            .into()),
+                        }
+                    })
+                }
+            })
+            .map_err(|e: failure::Error| e.context("Failed to authenticate API key").into()),
+    )
+}
@@ -0,0 +1,86 @@
+use super::warehouse::ensure_superadmin;
+use super::ServiceFuture;
+use models::*;
+use repos;
+use types::DbPool;
+
+use futures::future;
+use futures::prelude::*;
+use serde_json::Value;
+
+pub trait AuditService {
+    /// Record what a mutation of the caller outside of this service changed, given the affected
+    /// entities before and after it. Warehouse changes are recorded in their own transactions instead.
+    fn record_changes(
+        &self,
+        entity_type: AuditEntityType,
+        action: AuditAction,
+        before: Vec<Value>,
+        after: Vec<Value>,
+    ) -> ServiceFuture<()>;
+    fn find_audit_entries(&self, audit_query: AuditLogQuery) -> ServiceFuture<Vec<AuditEntry>>;
+}
+
+pub struct AuditServiceImpl {
+    pub db_pool: DbPool,
+    pub login: UserLogin,
+    pub api_key_id: Option<ApiKeyId>,
+}
+
+impl AuditServiceImpl {
+    pub fn new(db_pool: &DbPool, login: &UserLogin, api_key_id: Option<ApiKeyId>) -> Self {
+        Self {
+            db_pool: db_pool.clone(),
+            login: login.clone(),
+            api_key_id,
+        }
+    }
+}
+
+impl AuditService for AuditServiceImpl {
+    fn record_changes(
+        &self,
+        entity_type: AuditEntityType,
+        action: AuditAction,
+        before: Vec<Value>,
+        after: Vec<Value>,
+    ) -> ServiceFuture<()> {
+        let actor = AuditActor::new(&self.login, self.api_key_id);
+        let entries = actor.entries(entity_type, action, before, after);
+        if entries.is_empty() {
+            return Box::new(future::ok(()));
+        }
+
+        Box::new(
+            self.db_pool
+                .run(move |conn| repos::audit_log::insert_audit_entries(conn, entries))
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to record {} of {} by {:?}",
+                        action.as_str(),
+                        entity_type,
+                        actor
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn find_audit_entries(&self, audit_query: AuditLogQuery) -> ServiceFuture<Vec<AuditEntry>> {
+        if let Err(e) = ensure_superadmin(&self.login) {
+            return Box::new(future::err(e));
+        }
+
+        Box::new(
+            self.db_pool
+                .run({
+                    let audit_query = audit_query.clone();
+                    move |conn| repos::audit_log::find_audit_entries(conn, audit_query)
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to find audit entries: {:?}", audit_query))
+                        .into()
+                }),
+        )
+    }
+}
@@ -1,3 +1,15 @@
+pub mod allocation;
+pub use self::allocation::*;
+
+pub mod api_key;
+pub use self::api_key::*;
+
+pub mod audit;
+pub use self::audit::*;
+
+pub mod transfer;
+pub use self::transfer::*;
+
 pub mod warehouse;
 pub use self::warehouse::*;
 
@@ -0,0 +1,515 @@
+use super::warehouse::{authorize_stock_write, ensure_capacity, record_movement, RepoFactory};
+use super::ServiceFuture;
+use cache::Caches;
+use errors::*;
+use models::*;
+use repos;
+use repos::query::*;
+use types::DbPool;
+
+use chrono::prelude::*;
+use failure;
+use futures::future;
+use futures::prelude::*;
+use futures::stream;
+use std::collections::HashMap;
+use stq_db::repo::*;
+use stq_types::*;
+
+pub trait TransferService {
+    /// Create a draft transfer of goods between two warehouses of the same store
+    fn create_transfer(&self, input: TransferInput) -> ServiceFuture<TransferDocument>;
+    fn get_transfer(&self, transfer_id: TransferId) -> ServiceFuture<Option<TransferDocument>>;
+    /// Take goods from the source warehouse, they stay in transit until received
+    fn ship_transfer(&self, transfer_id: TransferId) -> ServiceFuture<TransferDocument>;
+    /// Put goods in transit into the destination warehouse
+    fn receive_transfer(&self, transfer_id: TransferId) -> ServiceFuture<TransferDocument>;
+    /// Abandon a draft, stocks are not affected
+    fn cancel_transfer(&self, transfer_id: TransferId) -> ServiceFuture<TransferDocument>;
+    /// Quantities of goods shipped to the warehouse but not yet received
+    fn list_in_transit(
+        &self,
+        warehouse_id: WarehouseId,
+    ) -> ServiceFuture<HashMap<ProductId, Quantity>>;
+}
+
+pub struct TransferServiceImpl {
+    pub repo_factory: RepoFactory,
+    pub db_pool: DbPool,
+    pub login: UserLogin,
+    /// Key the caller authenticated with, credited with the changes instead of the caller.
+    pub api_key_id: Option<ApiKeyId>,
+}
+
+impl TransferServiceImpl {
+    pub fn new(db_pool: &DbPool, login: &UserLogin, caches: &Caches) -> Self {
+        Self {
+            db_pool: db_pool.clone(),
+            login: login.clone(),
+            repo_factory: RepoFactory::new(db_pool, login, caches),
+            api_key_id: None,
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key_id: Option<ApiKeyId>) -> Self {
+        self.api_key_id = api_key_id;
+        self
+    }
+}
+
+impl TransferService for TransferServiceImpl {
+    fn create_transfer(&self, input: TransferInput) -> ServiceFuture<TransferDocument> {
+        if let Err(e) = input.validate() {
+            return Box::new(future::err(e));
+        }
+
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        let actor = AuditActor::new(&self.login, self.api_key_id);
+        let transfer = Transfer {
+            id: TransferId::new(),
+            source_warehouse_id: input.source_warehouse_id,
+            destination_warehouse_id: input.destination_warehouse_id,
+            status: TransferStatus::Draft,
+            created_by: actor.actor_id,
+            api_key_id: actor.api_key_id,
+            created_at: Utc::now(),
+            shipped_at: None,
+            received_at: None,
+            cancelled_at: None,
+        };
+        Box::new(
+            self.db_pool
+                .run({
+                    let input = input.clone();
+                    move |conn| {
+                        in_transaction(conn, move |conn| {
+                            Box::new(
+                                authorize_transfer(&repo_factory, &login, conn, &transfer)
+                                    .and_then({
+                                        let repo_factory = repo_factory.clone();
+                                        move |((), conn)| {
+                                            (repo_factory.transfers_repo_factory)()
+                                                .insert_exactly_one(conn, DbTransfer(transfer))
+                                        }
+                                    })
+                                    .and_then(move |(transfer, conn)| {
+                                        let lines = input
+                                            .lines
+                                            .into_iter()
+                                            .map(|line| TransferLine {
+                                                transfer_id: transfer.0.id,
+                                                product_id: line.product_id,
+                                                quantity: line.quantity,
+                                            })
+                                            .collect::<Vec<_>>();
+                                        stream::iter_ok::<_, (failure::Error, RepoConnection)>(
+                                            lines.clone(),
+                                        )
+                                        .fold(conn, move |conn, line| {
+                                            (repo_factory.transfer_lines_repo_factory)()
+                                                .insert_exactly_one(conn, DbTransferLine(line))
+                                                .map(|(_, conn)| conn)
+                                        })
+                                        .map(move |conn| {
+                                            (
+                                                TransferDocument {
+                                                    transfer: transfer.0,
+                                                    lines,
+                                                },
+                                                conn,
+                                            )
+                                        })
+                                    }),
+                            ) as RepoConnectionFuture<TransferDocument>
+                        })
+                    }
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to create transfer with data: {:?}", input))
+                        .into()
+                }),
+        )
+    }
+
+    fn get_transfer(&self, transfer_id: TransferId) -> ServiceFuture<Option<TransferDocument>> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    (repo_factory.transfers_repo_factory)()
+                        .select(
+                            conn,
+                            TransferFilter {
+                                id: Some(transfer_id.into()),
+                                ..Default::default()
+                            },
+                        )
+                        .and_then(move |(mut v, conn)| match v.pop() {
+                            None => Box::new(future::ok((None, conn)))
+                                as RepoConnectionFuture<Option<TransferDocument>>,
+                            Some(DbTransfer(transfer)) => Box::new(
+                                authorize_transfer(&repo_factory, &login, conn, &transfer)
+                                    .and_then(move |((), conn)| {
+                                        load_document(&repo_factory, conn, transfer)
+                                    })
+                                    .map(|(document, conn)| (Some(document), conn)),
+                            ),
+                        })
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to get transfer {}", transfer_id))
+                        .into()
+                }),
+        )
+    }
+
+    fn ship_transfer(&self, transfer_id: TransferId) -> ServiceFuture<TransferDocument> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        let actor = AuditActor::new(&self.login, self.api_key_id);
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    in_transaction(conn, move |conn| {
+                        Box::new(
+                            lock_transfer_in_status(
+                                &repo_factory,
+                                &login,
+                                conn,
+                                transfer_id,
+                                TransferStatus::Draft,
+                            )
+                            .and_then({
+                                let repo_factory = repo_factory.clone();
+                                move |(document, conn)| {
+                                    let warehouse_id = document.transfer.source_warehouse_id;
+                                    stream::iter_ok::<_, (failure::Error, RepoConnection)>(
+                                        document.lines,
+                                    )
+                                    .fold(conn, move |conn, line| {
+                                        let repo_factory = repo_factory.clone();
+                                        repos::stocks::adjust_unreserved_quantity(
+                                            conn,
+                                            warehouse_id,
+                                            line.product_id,
+                                            -line.quantity.0,
+                                        )
+                                        .and_then(move |(stock, conn)| match stock {
+                                            Some(stock) => Ok((stock, conn)),
+                                            None => Err((
+                                                format_err!(
+                                                    "Not enough unreserved units of product {} in warehouse {} to ship {}",
+                                                    line.product_id, warehouse_id, line.quantity
+                                                )
+                                                .context(Error::InsufficientStock)
+                                                .into(),
+                                                conn,
+                                            )),
+                                        })
+                                        .and_then(move |(stock, conn)| {
+                                            record_movement(
+                                                &repo_factory,
+                                                conn,
+                                                StockMovement::new(
+                                                    &stock.0.stock,
+                                                    Quantity(stock.0.stock.quantity.0 + line.quantity.0),
+                                                    actor,
+                                                    StockMovementReason::TransferShipment,
+                                                ),
+                                            )
+                                            .map(|((), conn)| conn)
+                                        })
+                                    })
+                                }
+                            })
+                            .and_then(move |conn| {
+                                set_transfer_status(
+                                    &repo_factory,
+                                    conn,
+                                    transfer_id,
+                                    TransferUpdateData {
+                                        status: Some(TransferStatus::Shipped.into()),
+                                        shipped_at: Some(Some(Utc::now()).into()),
+                                        ..Default::default()
+                                    },
+                                )
+                            }),
+                        ) as RepoConnectionFuture<TransferDocument>
+                    })
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to ship transfer {}", transfer_id))
+                        .into()
+                }),
+        )
+    }
+
+    fn receive_transfer(&self, transfer_id: TransferId) -> ServiceFuture<TransferDocument> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        let actor = AuditActor::new(&self.login, self.api_key_id);
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    in_transaction(conn, move |conn| {
+                        Box::new(
+                            lock_transfer_in_status(
+                                &repo_factory,
+                                &login,
+                                conn,
+                                transfer_id,
+                                TransferStatus::Shipped,
+                            )
+                            .and_then({
+                                let repo_factory = repo_factory.clone();
+                                move |(document, conn)| {
+                                    let warehouse_id = document.transfer.destination_warehouse_id;
+                                    stream::iter_ok::<_, (failure::Error, RepoConnection)>(
+                                        document.lines,
+                                    )
+                                    .fold(
+                                        conn,
+                                        move |conn, line| {
+                                            let repo_factory = repo_factory.clone();
+                                            repos::stocks::add_quantity(
+                                                conn,
+                                                warehouse_id,
+                                                line.product_id,
+                                                line.quantity.0,
+                                            )
+                                            .and_then(move |(stock, conn)| {
+                                                let quantity_before = Quantity(
+                                                    stock.0.stock.quantity.0 - line.quantity.0,
+                                                );
+                                                ensure_capacity(conn, quantity_before, stock)
+                                            })
+                                            .and_then(
+                                                move |(stock, conn)| {
+                                                    record_movement(
+                                                        &repo_factory,
+                                                        conn,
+                                                        StockMovement::new(
+                                                            &stock.0.stock,
+                                                            Quantity(
+                                                                stock.0.stock.quantity.0
+                                                                    - line.quantity.0,
+                                                            ),
+                                                            actor,
+                                                            StockMovementReason::TransferReceipt,
+                                                        ),
+                                                    )
+                                                    .map(|((), conn)| conn)
+                                                },
+                                            )
+                                        },
+                                    )
+                                }
+                            })
+                            .and_then(move |conn| {
+                                set_transfer_status(
+                                    &repo_factory,
+                                    conn,
+                                    transfer_id,
+                                    TransferUpdateData {
+                                        status: Some(TransferStatus::Received.into()),
+                                        received_at: Some(Some(Utc::now()).into()),
+                                        ..Default::default()
+                                    },
+                                )
+                            }),
+                        ) as RepoConnectionFuture<TransferDocument>
+                    })
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to receive transfer {}", transfer_id))
+                        .into()
+                }),
+        )
+    }
+
+    fn cancel_transfer(&self, transfer_id: TransferId) -> ServiceFuture<TransferDocument> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    in_transaction(conn, move |conn| {
+                        Box::new(
+                            lock_transfer_in_status(
+                                &repo_factory,
+                                &login,
+                                conn,
+                                transfer_id,
+                                TransferStatus::Draft,
+                            )
+                            .and_then(move |(_document, conn)| {
+                                set_transfer_status(
+                                    &repo_factory,
+                                    conn,
+                                    transfer_id,
+                                    TransferUpdateData {
+                                        status: Some(TransferStatus::Cancelled.into()),
+                                        cancelled_at: Some(Some(Utc::now()).into()),
+                                        ..Default::default()
+                                    },
+                                )
+                            }),
+                        ) as RepoConnectionFuture<TransferDocument>
+                    })
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to cancel transfer {}", transfer_id))
+                        .into()
+                }),
+        )
+    }
+
+    fn list_in_transit(
+        &self,
+        warehouse_id: WarehouseId,
+    ) -> ServiceFuture<HashMap<ProductId, Quantity>> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    authorize_stock_write(&repo_factory, &login, conn, warehouse_id).and_then(
+                        move |(_warehouse, conn)| {
+                            repos::transfers::in_transit_quantities(conn, warehouse_id)
+                        },
+                    )
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to list goods in transit to warehouse {}",
+                        warehouse_id
+                    ))
+                    .into()
+                }),
+        )
+    }
+}
+
+/// Makes sure the caller manages stocks on both sides and that both warehouses belong to one store.
+fn authorize_transfer(
+    repo_factory: &RepoFactory,
+    login: &UserLogin,
+    conn: RepoConnection,
+    transfer: &Transfer,
+) -> RepoConnectionFuture<()> {
+    let repo_factory = repo_factory.clone();
+    let login = login.clone();
+    let destination_warehouse_id = transfer.destination_warehouse_id;
+    Box::new(
+        authorize_stock_write(&repo_factory, &login, conn, transfer.source_warehouse_id)
+            .and_then(move |(source, conn)| {
+                authorize_stock_write(&repo_factory, &login, conn, destination_warehouse_id)
+                    .map(move |(destination, conn)| (source, destination, conn))
+            })
+            .and_then(|(source, destination, conn)| {
+                if source.store_id == destination.store_id {
+                    Ok(((), conn))
+                } else {
+                    Err((
+                        format_err!(
+                            "Warehouses {} and {} belong to different stores",
+                            source.id,
+                            destination.id
+                        )
+                        .context(Error::InvalidInput)
+                        .into(),
+                        conn,
+                    ))
+                }
+            }),
+    )
+}
+
+fn load_document(
+    repo_factory: &RepoFactory,
+    conn: RepoConnection,
+    transfer: Transfer,
+) -> RepoConnectionFuture<TransferDocument> {
+    Box::new(
+        (repo_factory.transfer_lines_repo_factory)()
+            .select(
+                conn,
+                TransferLineFilter {
+                    transfer_id: Some(transfer.id.into()),
+                },
+            )
+            .map(move |(lines, conn)| {
+                (
+                    TransferDocument {
+                        transfer,
+                        lines: lines.into_iter().map(|v| v.0).collect(),
+                    },
+                    conn,
+                )
+            }),
+    )
+}
+
+/// Locks the transfer and makes sure it can move on from the given status.
+fn lock_transfer_in_status(
+    repo_factory: &RepoFactory,
+    login: &UserLogin,
+    conn: RepoConnection,
+    transfer_id: TransferId,
+    status: TransferStatus,
+) -> RepoConnectionFuture<TransferDocument> {
+    let repo_factory = repo_factory.clone();
+    let login = login.clone();
+    Box::new(
+        repos::transfers::lock_transfer(conn, transfer_id)
+            .and_then(move |(transfer, conn)| match transfer {
+                None => Err((
+                    format_err!("Transfer {} does not exist", transfer_id)
+                        .context(Error::NotFound)
+                        .into(),
+                    conn,
+                )),
+                Some(DbTransfer(ref transfer)) if transfer.status != status => Err((
+                    format_err!(
+                        "Transfer {} is {:?}, expected {:?}",
+                        transfer_id,
+                        transfer.status,
+                        status
+                    )
+                    .context(Error::InvalidTransferStatus)
+                    .into(),
+                    conn,
+                )),
+                Some(DbTransfer(transfer)) => Ok((transfer, conn)),
+            })
+            .and_then(move |(transfer, conn)| {
+                authorize_transfer(&repo_factory, &login, conn, &transfer)
+                    .and_then(move |((), conn)| load_document(&repo_factory, conn, transfer))
+            }),
+    )
+}
+
+fn set_transfer_status(
+    repo_factory: &RepoFactory,
+    conn: RepoConnection,
+    transfer_id: TransferId,
+    data: TransferUpdateData,
+) -> RepoConnectionFuture<TransferDocument> {
+    let repo_factory = repo_factory.clone();
+    Box::new(
+        (repo_factory.transfers_repo_factory)()
+            .update_exactly_one(
+                conn,
+                TransferUpdater {
+                    mask: TransferFilter {
+                        id: Some(transfer_id.into()),
+                        ..Default::default()
+                    },
+                    data,
+                },
+            )
+            .and_then(move |(transfer, conn)| load_document(&repo_factory, conn, transfer.0)),
+    )
+}
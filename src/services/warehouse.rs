@@ -1,52 +1,189 @@
 use super::ServiceFuture;
+use cache::Caches;
 use errors::*;
 use models::*;
 use repos;
+use repos::query::*;
 use repos::*;
 use types::DbPool;
 
+use chrono::prelude::*;
+use chrono::Duration;
 use failure;
 use futures::future;
 use futures::prelude::*;
+use futures::stream;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::rc::Rc;
+use stq_acl::Action;
 use stq_api::warehouses::*;
 use stq_db::repo::*;
 use stq_types::*;
 
 pub trait WarehouseService {
-    fn create_warehouse(&self, new_warehouse: WarehouseInput) -> ServiceFuture<Warehouse>;
-    fn get_warehouse(&self, warehouse_id: WarehouseIdentifier) -> ServiceFuture<Option<Warehouse>>;
+    fn create_warehouse(&self, new_warehouse: NewWarehouseRecord)
+        -> ServiceFuture<WarehouseRecord>;
+    fn get_warehouse(
+        &self,
+        warehouse_id: WarehouseIdentifier,
+    ) -> ServiceFuture<Option<WarehouseRecord>>;
     fn update_warehouse(
         &self,
         warehouse_id: WarehouseIdentifier,
-        update_data: WarehouseUpdateData,
-    ) -> ServiceFuture<Option<Warehouse>>;
-    fn delete_warehouse(
+        update_data: WarehouseRecordUpdate,
+        expected_version: Option<Version>,
+    ) -> ServiceFuture<Option<WarehouseRecord>>;
+    /// Hides the warehouse from listings and availability, keeping its stocks.
+    fn archive_warehouse(
+        &self,
+        warehouse_id: WarehouseIdentifier,
+    ) -> ServiceFuture<Option<WarehouseRecord>>;
+    fn restore_warehouse(
         &self,
         warehouse_id: WarehouseIdentifier,
-    ) -> ServiceFuture<Option<Warehouse>>;
-    fn delete_all_warehouses(&self) -> ServiceFuture<Vec<Warehouse>>;
-    fn get_warehouses_for_store(&self, store_id: StoreId) -> ServiceFuture<Vec<Warehouse>>;
+    ) -> ServiceFuture<Option<WarehouseRecord>>;
+    /// Removes the warehouse with all its data, superadmin only.
+    fn purge_warehouse(
+        &self,
+        warehouse_id: WarehouseIdentifier,
+    ) -> ServiceFuture<Option<WarehouseRecord>>;
+    /// Archives all warehouses of the store, e.g. when the store is offboarded.
+    fn archive_store_warehouses(&self, store_id: StoreId) -> ServiceFuture<Vec<WarehouseRecord>>;
+    /// Removes all warehouses of the store with all their data, superadmin only.
+    fn purge_store_warehouses(&self, store_id: StoreId) -> ServiceFuture<Vec<WarehouseRecord>>;
+    /// Removes all warehouses with all their data, superadmin only and in maintenance mode.
+    fn delete_all_warehouses(&self) -> ServiceFuture<Vec<WarehouseRecord>>;
+    fn get_warehouses_for_store(&self, store_id: StoreId) -> ServiceFuture<Vec<WarehouseRecord>>;
+    /// Warehouses matching address attributes, e.g. pickup locations in a city
+    fn search_warehouses(&self, search: WarehouseSearch) -> ServiceFuture<Vec<WarehouseRecord>>;
+    /// Warehouses ordered by distance from the given point, closest first
+    fn find_nearest_warehouses(
+        &self,
+        search: NearestWarehouseQuery,
+        page: Page,
+    ) -> ServiceFuture<Vec<WarehouseWithDistance>>;
 
     fn set_product_in_warehouse(
         &self,
         warehouse_id: WarehouseId,
         product_id: ProductId,
         quantity: Quantity,
-    ) -> ServiceFuture<Stock>;
+        expected_version: Option<Version>,
+    ) -> ServiceFuture<StockRecord>;
+    /// Atomically add a positive or negative amount to stock, refusing to go below zero
+    fn adjust_product_in_warehouse(
+        &self,
+        warehouse_id: WarehouseId,
+        product_id: ProductId,
+        delta: i32,
+    ) -> ServiceFuture<StockRecord>;
+    /// Set quantities of many products at once, applying valid lines and reporting the rejected ones
+    fn bulk_set_products_in_warehouses(
+        &self,
+        lines: Vec<StockUpsertLine>,
+    ) -> ServiceFuture<Vec<StockUpsertResult>>;
     fn get_product_in_warehouse(
         &self,
         warehouse_id: WarehouseId,
         product_id: ProductId,
-    ) -> ServiceFuture<Option<Stock>>;
+    ) -> ServiceFuture<Option<StockRecord>>;
     fn list_products_in_warehouse(&self, warehouse_id: WarehouseId) -> ServiceFuture<StockMap>;
+    fn export_products_in_warehouse(
+        &self,
+        warehouse_id: WarehouseId,
+    ) -> ServiceFuture<Vec<StockCsvRecord>>;
+    /// Set quantities from uploaded rows, or only report what would change if `dry_run` is set
+    fn import_products_in_warehouse(
+        &self,
+        warehouse_id: WarehouseId,
+        rows: Vec<Result<StockCsvImportRow, String>>,
+        dry_run: bool,
+    ) -> ServiceFuture<StockImportReport>;
 
-    fn get_warehouse_product(&self, warehouse_product_id: StockId) -> ServiceFuture<Option<Stock>>;
+    fn get_warehouse_product(
+        &self,
+        warehouse_product_id: StockId,
+    ) -> ServiceFuture<Option<StockRecord>>;
 
     /// Find all products with id in all warehouses
-    fn find_by_product_id(&self, product_id: ProductId) -> ServiceFuture<Vec<Stock>>;
-    /// Find all products
-    fn find_products(&self) -> ServiceFuture<Vec<Stock>>;
+    fn find_by_product_id(&self, product_id: ProductId) -> ServiceFuture<Vec<StockRecord>>;
+    /// Find the first page of stocks matching the query, `page_products` also returns the cursor to the next one
+    fn find_products(&self, list_query: StockListQuery) -> ServiceFuture<Vec<StockRecord>>;
+    /// Page through stocks of all warehouses matching the query
+    fn page_products(&self, list_query: StockListQuery) -> ServiceFuture<StockPage>;
+
+    /// Hold units of a product for an order until committed, released or expired
+    fn hold_stock(&self, input: ReservationInput) -> ServiceFuture<Reservation>;
+    fn get_reservation(&self, reservation_id: ReservationId) -> ServiceFuture<Option<Reservation>>;
+    /// Take held units from stock
+    fn commit_reservation(&self, reservation_id: ReservationId) -> ServiceFuture<Reservation>;
+    /// Return held units to available quantity
+    fn release_reservation(&self, reservation_id: ReservationId) -> ServiceFuture<Reservation>;
+
+    /// Page through stock movements of a warehouse, a product or a single stock, newest first
+    fn list_stock_movements(
+        &self,
+        subject: StockMovementSubject,
+        page: Page,
+    ) -> ServiceFuture<Vec<StockMovement>>;
+
+    /// Set the reorder threshold of a single stock, `None` falling back to the warehouse default
+    fn set_stock_reorder_threshold(
+        &self,
+        warehouse_id: WarehouseId,
+        product_id: ProductId,
+        reorder_threshold: Option<Quantity>,
+    ) -> ServiceFuture<Option<StockRecord>>;
+    fn set_warehouse_reorder_threshold(
+        &self,
+        warehouse_id: WarehouseId,
+        default_reorder_threshold: Option<Quantity>,
+    ) -> ServiceFuture<Option<WarehouseRecord>>;
+    /// Replace the total and per-product capacity of the warehouse. Stocks already above
+    /// the new limits are kept, but cannot grow until they fit
+    fn set_warehouse_capacity(
+        &self,
+        warehouse_id: WarehouseId,
+        capacity: WarehouseCapacity,
+    ) -> ServiceFuture<Option<WarehouseRecord>>;
+    fn get_warehouse_utilization(
+        &self,
+        warehouse_id: WarehouseId,
+    ) -> ServiceFuture<Option<WarehouseUtilization>>;
+    /// Stocks of the store at or below their reorder threshold
+    fn list_low_stock(&self, store_id: StoreId) -> ServiceFuture<Vec<LowStock>>;
+    fn list_low_stock_events(
+        &self,
+        store_id: StoreId,
+        page: Page,
+    ) -> ServiceFuture<Vec<LowStockEvent>>;
+    /// What readers who do not manage stocks of the store learn about their quantities
+    fn get_stock_visibility(&self, store_id: StoreId) -> ServiceFuture<StoreStockVisibility>;
+    fn set_stock_visibility(
+        &self,
+        store_id: StoreId,
+        visibility: StockVisibility,
+    ) -> ServiceFuture<StoreStockVisibility>;
+
+    /// Change the operational status of the warehouse, recording who changed it
+    fn set_warehouse_status(
+        &self,
+        warehouse_id: WarehouseId,
+        status: WarehouseStatus,
+    ) -> ServiceFuture<Option<WarehouseRecord>>;
+    fn list_warehouse_status_changes(
+        &self,
+        warehouse_id: WarehouseId,
+        page: Page,
+    ) -> ServiceFuture<Vec<WarehouseStatusChange>>;
+
+    /// Whether the warehouse is open at the given moment and when it opens or closes next
+    fn get_opening_status(
+        &self,
+        warehouse_id: WarehouseId,
+        at: DateTime<Utc>,
+    ) -> ServiceFuture<Option<OpeningStatus>>;
 }
 
 #[derive(Clone)]
@@ -54,86 +191,232 @@ pub struct RepoFactory {
     pub warehouse_repo_factory: Rc<Fn() -> Box<WarehouseRepo>>,
     pub warehouse_slug_sequence_factory: Rc<Fn() -> Box<WarehouseSlugSequence>>,
     pub stocks_repo_factory: Rc<Fn() -> Box<StocksRepo>>,
+    pub reservations_repo_factory: Rc<Fn() -> Box<ReservationsRepo>>,
+    pub stock_movements_repo_factory: Rc<Fn() -> Box<StockMovementsRepo>>,
+    pub transfers_repo_factory: Rc<Fn() -> Box<TransfersRepo>>,
+    pub transfer_lines_repo_factory: Rc<Fn() -> Box<TransferLinesRepo>>,
+    pub caches: Caches,
+}
+
+impl RepoFactory {
+    pub fn new(db_pool: &DbPool, login: &UserLogin, caches: &Caches) -> Self {
+        let warehouse_source = Rc::new({
+            let db_pool = db_pool.clone();
+            let warehouses = caches.warehouses.clone();
+            move |warehouse_id: WarehouseId| {
+                if let Some(warehouse) = warehouses.get(&warehouse_id) {
+                    return Box::new(future::ok(warehouse))
+                        as Box<Future<Item = Warehouse, Error = failure::Error>>;
+                }
+
+                let warehouses = warehouses.clone();
+                Box::new(
+                    db_pool
+                        .run(move |conn| {
+                            repos::warehouses::make_su_repo().select_exactly_one(
+                                conn,
+                                WarehouseFilter {
+                                    id: Some(warehouse_id.into()),
+                                    ..Default::default()
+                                },
+                            )
+                        })
+                        .map(move |v| {
+                            warehouses.insert(warehouse_id, v.0.warehouse.clone());
+                            v.0.warehouse
+                        }),
+                ) as Box<Future<Item = Warehouse, Error = failure::Error>>
+            }
+        });
+
+        Self {
+            warehouse_repo_factory: Rc::new({
+                let login = login.clone();
+                move || Box::new(repos::warehouses::make_repo(login.clone()))
+            }),
+            warehouse_slug_sequence_factory: Rc::new({
+                || Box::new(repos::warehouses::make_slug_sequence())
+            }),
+            stocks_repo_factory: Rc::new({
+                let login = login.clone();
+                let warehouse_source = warehouse_source.clone();
+                move || {
+                    Box::new(repos::stocks::make_repo(
+                        login.clone(),
+                        warehouse_source.clone(),
+                    ))
+                }
+            }),
+            reservations_repo_factory: Rc::new({
+                let login = login.clone();
+                let warehouse_source = warehouse_source.clone();
+                move || {
+                    Box::new(repos::reservations::make_repo(
+                        login.clone(),
+                        warehouse_source.clone(),
+                    ))
+                }
+            }),
+            stock_movements_repo_factory: Rc::new({
+                || Box::new(repos::stock_movements::make_su_repo())
+            }),
+            transfers_repo_factory: Rc::new({ || Box::new(repos::transfers::make_su_repo()) }),
+            transfer_lines_repo_factory: Rc::new({
+                || Box::new(repos::transfers::make_lines_su_repo())
+            }),
+            caches: caches.clone(),
+        }
+    }
 }
 
 pub struct WarehouseServiceImpl {
     pub repo_factory: RepoFactory,
     pub db_pool: DbPool,
+    pub login: UserLogin,
+    /// Allows wiping all warehouses at once.
+    pub maintenance_mode: bool,
+    /// Key the caller authenticated with, credited with the changes instead of the caller.
+    pub api_key_id: Option<ApiKeyId>,
 }
 
 impl WarehouseServiceImpl {
-    pub fn new(db_pool: &DbPool, login: &UserLogin) -> Self {
+    pub fn new(db_pool: &DbPool, login: &UserLogin, caches: &Caches) -> Self {
         Self {
             db_pool: db_pool.clone(),
-            repo_factory: RepoFactory {
-                warehouse_repo_factory: Rc::new({
-                    let login = login.clone();
-                    move || Box::new(repos::warehouses::make_repo(login.clone()))
-                }),
-                warehouse_slug_sequence_factory: Rc::new({
-                    || Box::new(repos::warehouses::make_slug_sequence())
-                }),
-                stocks_repo_factory: Rc::new({
-                    let login = login.clone();
-                    let warehouse_source = Rc::new({
-                        let db_pool = db_pool.clone();
-                        move |warehouse_id: WarehouseId| {
-                            Box::new(
-                                db_pool
-                                    .run(move |conn| {
-                                        repos::warehouses::make_su_repo().select_exactly_one(
-                                            conn,
-                                            WarehouseFilter {
-                                                id: Some(warehouse_id.into()),
-                                                ..Default::default()
-                                            },
-                                        )
-                                    })
-                                    .map(|v| v.0),
-                            )
-                                as Box<Future<Item = Warehouse, Error = failure::Error>>
-                        }
-                    });
-                    move || {
-                        Box::new(repos::stocks::make_repo(
-                            login.clone(),
-                            warehouse_source.clone(),
-                        ))
-                    }
-                }),
-            },
+            login: login.clone(),
+            repo_factory: RepoFactory::new(db_pool, login, caches),
+            maintenance_mode: false,
+            api_key_id: None,
         }
     }
+
+    pub fn with_maintenance_mode(mut self, maintenance_mode: bool) -> Self {
+        self.maintenance_mode = maintenance_mode;
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key_id: Option<ApiKeyId>) -> Self {
+        self.api_key_id = api_key_id;
+        self
+    }
+
+    fn audit_actor(&self) -> AuditActor {
+        AuditActor::new(&self.login, self.api_key_id)
+    }
+}
+
+/// Reduces on-hand quantity by the units held by active reservations.
+fn available_stock(mut stock: StockRecord, held: &HashMap<ProductId, Quantity>) -> StockRecord {
+    if let Some(held) = held.get(&stock.stock.product_id) {
+        stock.stock.quantity = Quantity(::std::cmp::max(stock.stock.quantity.0 - held.0, 0));
+    }
+    stock
+}
+
+/// Reduces on-hand quantities by held units, to nothing if the warehouse is unavailable.
+fn available_stocks(
+    stocks: Vec<StockRecord>,
+    held: &Option<HashMap<ProductId, Quantity>>,
+) -> Vec<StockRecord> {
+    stocks
+        .into_iter()
+        .map(|mut stock| match *held {
+            Some(ref held) => available_stock(stock, held),
+            None => {
+                stock.stock.quantity = Quantity(0);
+                stock
+            }
+        })
+        .collect()
+}
+
+/// Units held by active reservations in the warehouse, `None` if it is paused, closed or archived
+/// and so has no stock available at all.
+fn held_quantities_if_available(
+    repo_factory: &RepoFactory,
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+    product_id: Option<ProductId>,
+) -> RepoConnectionFuture<Option<HashMap<ProductId, Quantity>>> {
+    Box::new(
+        (repo_factory.warehouse_repo_factory)()
+            .select(
+                conn,
+                WarehouseFilter {
+                    id: Some(warehouse_id.into()),
+                    ..Default::default()
+                },
+            )
+            .and_then(move |(mut v, conn)| match v.pop() {
+                Some(DbWarehouse(ref warehouse))
+                    if warehouse.archived_at.is_none()
+                        && warehouse.status == WarehouseStatus::Active =>
+                {
+                    Box::new(
+                        repos::reservations::held_quantities(conn, warehouse_id, product_id)
+                            .map(|(held, conn)| (Some(held), conn)),
+                    )
+                        as RepoConnectionFuture<Option<HashMap<ProductId, Quantity>>>
+                }
+                _ => Box::new(future::ok((None, conn))),
+            }),
+    )
 }
 
 impl WarehouseService for WarehouseServiceImpl {
-    fn create_warehouse(&self, new_warehouse: WarehouseInput) -> ServiceFuture<Warehouse> {
+    fn create_warehouse(
+        &self,
+        new_warehouse: NewWarehouseRecord,
+    ) -> ServiceFuture<WarehouseRecord> {
+        if let Err(e) = new_warehouse.validate() {
+            return Box::new(future::err(e));
+        }
+
         let repo_factory = self.repo_factory.clone();
+        let actor = self.audit_actor();
         Box::new(
             self.db_pool
                 .run({
                     let new_warehouse = new_warehouse.clone();
                     move |conn| {
-                        future::ok(conn)
-                            .and_then({
-                                let f = repo_factory.warehouse_slug_sequence_factory.clone();
-                                move |conn| (f)().next_val(conn)
-                            })
-                            .and_then({
-                                let f = repo_factory.warehouse_repo_factory.clone();
-                                move |(slug, conn)| {
-                                    (f)().insert_exactly_one(
-                                        conn,
-                                        DbWarehouse(
-                                            new_warehouse
-                                                .with_slug(WarehouseSlug(slug.to_string())),
-                                        ),
+                        audited_warehouse_change(
+                            conn,
+                            actor,
+                            AuditAction::Create,
+                            no_warehouses,
+                            move |conn| {
+                                let NewWarehouseRecord {
+                                    input,
+                                    slug,
+                                    schedule,
+                                } = new_warehouse;
+                                let slug = match slug {
+                                    Some(slug) => Box::new(
+                                        claim_slug(conn, slug.clone(), None)
+                                            .map(move |((), conn)| (slug, conn)),
                                     )
-                                }
-                            })
+                                        as RepoConnectionFuture<WarehouseSlug>,
+                                    None => Box::new(
+                                        (repo_factory.warehouse_slug_sequence_factory)()
+                                            .next_val(conn)
+                                            .map(|(v, conn)| (WarehouseSlug(v.to_string()), conn)),
+                                    ),
+                                };
+                                Box::new(slug.and_then(move |(slug, conn)| {
+                                    (repo_factory.warehouse_repo_factory)()
+                                        .insert_exactly_one(
+                                            conn,
+                                            DbWarehouse(WarehouseRecord {
+                                                schedule,
+                                                ..WarehouseRecord::new(input.with_slug(slug))
+                                            }),
+                                        )
+                                        .map(|(v, conn)| (v.0, conn))
+                                }))
+                            },
+                        )
                     }
                 })
-                .map(|v| v.0)
                 .map_err(move |e| {
                     e.context(format!(
                         "Failed to create warehouse with data: {:?}",
@@ -144,18 +427,23 @@ impl WarehouseService for WarehouseServiceImpl {
         )
     }
 
-    fn get_warehouse(&self, warehouse_id: WarehouseIdentifier) -> ServiceFuture<Option<Warehouse>> {
+    fn get_warehouse(
+        &self,
+        warehouse_id: WarehouseIdentifier,
+    ) -> ServiceFuture<Option<WarehouseRecord>> {
         let repo_factory = self.repo_factory.clone();
         Box::new(
             self.db_pool
                 .run(move |conn| {
-                    (repo_factory.warehouse_repo_factory)().select(conn, warehouse_id.into())
+                    resolve_identifier(conn, warehouse_id).and_then(move |(warehouse_id, conn)| {
+                        (repo_factory.warehouse_repo_factory)().select(conn, warehouse_id.into())
+                    })
                 })
                 .map(|mut v| v.pop().map(|v| v.0)),
         )
     }
 
-    fn get_warehouses_for_store(&self, store_id: StoreId) -> ServiceFuture<Vec<Warehouse>> {
+    fn get_warehouses_for_store(&self, store_id: StoreId) -> ServiceFuture<Vec<WarehouseRecord>> {
         let repo_factory = self.repo_factory.clone();
         Box::new(
             self.db_pool
@@ -164,6 +452,7 @@ impl WarehouseService for WarehouseServiceImpl {
                         conn,
                         WarehouseFilter {
                             store_id: Some(store_id.into()),
+                            archived: Some(false.into()),
                             ..Default::default()
                         },
                     )
@@ -179,28 +468,142 @@ impl WarehouseService for WarehouseServiceImpl {
         )
     }
 
+    fn search_warehouses(&self, search: WarehouseSearch) -> ServiceFuture<Vec<WarehouseRecord>> {
+        Box::new(
+            self.db_pool
+                .run({
+                    let search = search.clone();
+                    move |conn| repos::warehouses::search_warehouses(conn, search)
+                })
+                .map(|v| v.into_iter().map(|v| v.0).collect())
+                .map_err(move |e| {
+                    e.context(format!("Failed to search warehouses with {:?}", search))
+                        .into()
+                }),
+        )
+    }
+
+    fn find_nearest_warehouses(
+        &self,
+        search: NearestWarehouseQuery,
+        page: Page,
+    ) -> ServiceFuture<Vec<WarehouseWithDistance>> {
+        if let Err(e) = search.validate() {
+            return Box::new(future::err(e));
+        }
+
+        Box::new(
+            self.db_pool
+                .run({
+                    let search = search.clone();
+                    move |conn| repos::warehouses::nearest_warehouses(conn, search, page)
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to find warehouses nearest to {:?}", search))
+                        .into()
+                }),
+        )
+    }
+
     fn update_warehouse(
         &self,
         warehouse_id: WarehouseIdentifier,
-        update_data: WarehouseUpdateData,
-    ) -> ServiceFuture<Option<Warehouse>> {
+        update_data: WarehouseRecordUpdate,
+        expected_version: Option<Version>,
+    ) -> ServiceFuture<Option<WarehouseRecord>> {
+        if let Err(e) = update_data.validate() {
+            return Box::new(future::err(e));
+        }
+
         let repo_factory = self.repo_factory.clone();
+        let cached_warehouses = self.repo_factory.caches.warehouses.clone();
+        let actor = self.audit_actor();
         Box::new(
             self.db_pool
                 .run({
                     let update_data = update_data.clone();
                     let warehouse_id = warehouse_id.clone();
                     move |conn| {
-                        (repo_factory.warehouse_repo_factory)().update(
-                            conn,
-                            WarehouseUpdater {
-                                mask: warehouse_id.into(),
-                                data: update_data,
-                            },
-                        )
+                        let lock = {
+                            let warehouse_id = warehouse_id.clone();
+                            move |conn| lock_identified_warehouse(conn, warehouse_id)
+                        };
+                        audited_warehouse_change(conn, actor, AuditAction::Update, lock, move |conn| {
+                            let new_slug = update_data.data.slug.clone().map(|v| v.value);
+                            Box::new(
+                                resolve_identifier(conn, warehouse_id)
+                                    .and_then({
+                                        let new_slug = new_slug.clone();
+                                        move |(warehouse_id, conn)| {
+                                            prepare_slug_change(conn, warehouse_id, new_slug)
+                                        }
+                                    })
+                                    .and_then(move |((warehouse_id, former_slug), conn)| {
+                                        let mut mask = WarehouseFilter::from(warehouse_id.clone());
+                                        mask.version = expected_version.map(From::from);
+                                        (repo_factory.warehouse_repo_factory)()
+                                            .update(
+                                                conn,
+                                                WarehouseUpdater {
+                                                    mask,
+                                                    data: update_data.data,
+                                                    extra: WarehouseRecordUpdateData {
+                                                        schedule: update_data.schedule,
+                                                        ..Default::default()
+                                                    },
+                                                },
+                                            )
+                                            .and_then(move |(mut v, conn)| match (v.pop(), expected_version) {
+                                                (None, Some(expected_version)) => {
+                                                    // Nothing matched, so the warehouse is either gone or was changed concurrently
+                                                    Box::new(
+                                                        (repo_factory.warehouse_repo_factory)()
+                                                            .select(conn, warehouse_id.clone().into())
+                                                            .and_then(move |(current, conn)| match current.first() {
+                                                                None => Ok((None, conn)),
+                                                                Some(current) => Err((
+                                                                    format_err!(
+                                                                        "Warehouse {:?} has version {}, expected {}",
+                                                                        warehouse_id,
+                                                                        current.0.version,
+                                                                        expected_version
+                                                                    )
+                                                                    .context(Error::VersionConflict)
+                                                                    .into(),
+                                                                    conn,
+                                                                )),
+                                                            }),
+                                                    )
+                                                        as RepoConnectionFuture<Option<DbWarehouse>>
+                                                }
+                                                (updated, _) => Box::new(future::ok((updated, conn))),
+                                            })
+                                            .and_then(move |(updated, conn)| match (updated, former_slug) {
+                                                (Some(updated), Some(former_slug)) => Box::new(
+                                                    repos::warehouses::record_slug_change(
+                                                        conn,
+                                                        updated.0.warehouse.id,
+                                                        former_slug,
+                                                        updated.0.warehouse.slug.clone(),
+                                                    )
+                                                    .map(move |((), conn)| (Some(updated), conn)),
+                                                )
+                                                    as RepoConnectionFuture<Option<DbWarehouse>>,
+                                                (updated, _) => Box::new(future::ok((updated, conn))),
+                                            })
+                                    })
+                                    .map(|(v, conn)| (v.map(|v| v.0), conn)),
+                            ) as RepoConnectionFuture<Option<WarehouseRecord>>
+                        })
                     }
                 })
-                .map(|mut v| v.pop().map(|v| v.0))
+                .map(move |v| {
+                    // The store owning the warehouse is cached for the stock ACL
+                    if let Some(ref v) = v {
+                        cached_warehouses.invalidate(&v.warehouse.id);
+                    }
+                    v
+                })
                 .map_err(move |e| {
                     e.context(format!(
                         "Failed to update warehouse {:?} with data {:?}",
@@ -211,208 +614,965 @@ impl WarehouseService for WarehouseServiceImpl {
         )
     }
 
-    fn delete_warehouse(
+    fn archive_warehouse(
         &self,
         warehouse_id: WarehouseIdentifier,
-    ) -> ServiceFuture<Option<Warehouse>> {
+    ) -> ServiceFuture<Option<WarehouseRecord>> {
         let repo_factory = self.repo_factory.clone();
+        let actor = self.audit_actor();
         Box::new(
             self.db_pool
                 .run({
                     let warehouse_id = warehouse_id.clone();
                     move |conn| {
-                        (repo_factory.warehouse_repo_factory)().delete(conn, warehouse_id.into())
+                        let lock = {
+                            let warehouse_id = warehouse_id.clone();
+                            move |conn| lock_identified_warehouse(conn, warehouse_id)
+                        };
+                        audited_warehouse_change(
+                            conn,
+                            actor,
+                            AuditAction::Update,
+                            lock,
+                            move |conn| {
+                                Box::new(
+                                    set_archived_at(
+                                        &repo_factory,
+                                        conn,
+                                        warehouse_id,
+                                        Some(Utc::now()),
+                                    )
+                                    .map(|(v, conn)| (v.map(|v| v.0), conn)),
+                                )
+                            },
+                        )
                     }
                 })
-                .map(|mut v| v.pop().map(|v| v.0))
                 .map_err(move |e| {
-                    e.context(format!("Failed to delete warehouse {:?}", warehouse_id))
+                    e.context(format!("Failed to archive warehouse {:?}", warehouse_id))
                         .into()
                 }),
         )
     }
 
-    fn delete_all_warehouses(&self) -> ServiceFuture<Vec<Warehouse>> {
+    fn restore_warehouse(
+        &self,
+        warehouse_id: WarehouseIdentifier,
+    ) -> ServiceFuture<Option<WarehouseRecord>> {
         let repo_factory = self.repo_factory.clone();
+        let actor = self.audit_actor();
         Box::new(
             self.db_pool
-                .run(move |conn| {
-                    (repo_factory.warehouse_repo_factory)().delete(
-                        conn,
-                        WarehouseFilter {
-                            ..Default::default()
-                        },
-                    )
+                .run({
+                    let warehouse_id = warehouse_id.clone();
+                    move |conn| {
+                        let lock = {
+                            let warehouse_id = warehouse_id.clone();
+                            move |conn| lock_identified_warehouse(conn, warehouse_id)
+                        };
+                        audited_warehouse_change(
+                            conn,
+                            actor,
+                            AuditAction::Update,
+                            lock,
+                            move |conn| {
+                                Box::new(
+                                    set_archived_at(&repo_factory, conn, warehouse_id, None)
+                                        .map(|(v, conn)| (v.map(|v| v.0), conn)),
+                                )
+                            },
+                        )
+                    }
                 })
-                .map(|data| data.into_iter().map(|v| v.0).collect())
-                .map_err(|e| e.context("Failed to delete all warehouses").into()),
+                .map_err(move |e| {
+                    e.context(format!("Failed to restore warehouse {:?}", warehouse_id))
+                        .into()
+                }),
         )
     }
 
-    fn set_product_in_warehouse(
+    fn purge_warehouse(
         &self,
-        warehouse_id: WarehouseId,
-        product_id: ProductId,
-        quantity: Quantity,
-    ) -> ServiceFuture<Stock> {
-        let repo_factory = self.repo_factory.clone();
+        warehouse_id: WarehouseIdentifier,
+    ) -> ServiceFuture<Option<WarehouseRecord>> {
+        if let Err(e) = ensure_superadmin(&self.login) {
+            return Box::new(future::err(e));
+        }
+
+        let cached_warehouses = self.repo_factory.caches.warehouses.clone();
+        let actor = self.audit_actor();
         Box::new(
             self.db_pool
                 .run({
+                    let warehouse_id = warehouse_id.clone();
                     move |conn| {
-                        future::ok(conn)
-                            .and_then({
-                                let repo_factory = repo_factory.clone();
-                                move |conn| {
-                                    let repo = (repo_factory.warehouse_repo_factory)();
-
-                                    repo.select(
-                                        conn,
-                                        WarehouseFilter {
-                                            id: Some(warehouse_id.into()),
-                                            ..Default::default()
-                                        },
-                                    )
-                                    .and_then(
-                                        move |(v, conn)| {
-                                            if v.is_empty() {
-                                                Err((
-                                                    format_err!(
-                                                        "Warehouse {} does not exist",
-                                                        warehouse_id
-                                                    )
-                                                    .context(Error::NotFound)
-                                                    .into(),
-                                                    conn,
-                                                ))
-                                            } else {
-                                                Ok(conn)
-                                            }
-                                        },
-                                    )
-                                }
-                            })
-                            .and_then({
-                                let repo_factory = repo_factory.clone();
-                                move |conn| {
-                                    let repo = (repo_factory.stocks_repo_factory)();
-
-                                    repo.insert_exactly_one(
-                                        conn,
-                                        DbStock(Stock {
-                                            id: StockId::new(),
-                                            warehouse_id,
-                                            product_id,
-                                            quantity,
+                        audited_warehouse_change(
+                            conn,
+                            actor,
+                            AuditAction::Delete,
+                            no_warehouses,
+                            move |conn| {
+                                Box::new(
+                                    resolve_identifier(conn, warehouse_id)
+                                        .and_then(|(warehouse_id, conn)| {
+                                            repos::warehouses::make_su_repo()
+                                                .delete(conn, warehouse_id.into())
+                                        })
+                                        .map(|(v, conn)| {
+                                            (v.into_iter().map(|v| v.0).collect::<Vec<_>>(), conn)
                                         }),
-                                    )
-                                }
-                            })
+                                )
+                            },
+                        )
                     }
                 })
-                .map(|v| v.0)
+                .map(move |mut v| {
+                    for deleted in &v {
+                        cached_warehouses.invalidate(&deleted.warehouse.id);
+                    }
+                    v.pop()
+                })
                 .map_err(move |e| {
-                    e.context(format!(
-                        "Failed to set product {} in warehouse {} to quantity {}",
-                        product_id.0, warehouse_id.0, quantity.0
-                    ))
-                    .into()
+                    e.context(format!("Failed to purge warehouse {:?}", warehouse_id))
+                        .into()
                 }),
         )
     }
-    fn get_product_in_warehouse(
-        &self,
-        warehouse_id: WarehouseId,
-        product_id: ProductId,
-    ) -> ServiceFuture<Option<Stock>> {
+
+    fn archive_store_warehouses(&self, store_id: StoreId) -> ServiceFuture<Vec<WarehouseRecord>> {
+        if let Err(e) = ensure_manages_store(&self.login, store_id) {
+            return Box::new(future::err(e));
+        }
+
         let repo_factory = self.repo_factory.clone();
+        let actor = self.audit_actor();
         Box::new(
             self.db_pool
                 .run(move |conn| {
-                    (repo_factory.stocks_repo_factory)().select(
-                        conn,
-                        StockFilter {
-                            warehouse_id: Some(warehouse_id.into()),
-                            product_id: Some(product_id.into()),
-                            ..Default::default()
-                        },
-                    )
+                    let lock = move |conn| repos::warehouses::lock_store_warehouses(conn, store_id);
+                    audited_warehouse_change(conn, actor, AuditAction::Update, lock, move |conn| {
+                        Box::new(
+                            (repo_factory.warehouse_repo_factory)()
+                                .update(
+                                    conn,
+                                    WarehouseUpdater {
+                                        mask: WarehouseFilter {
+                                            store_id: Some(store_id.into()),
+                                            archived: Some(false.into()),
+                                            ..Default::default()
+                                        },
+                                        extra: WarehouseRecordUpdateData {
+                                            archived_at: Some(Some(Utc::now()).into()),
+                                            ..Default::default()
+                                        },
+                                        ..Default::default()
+                                    },
+                                )
+                                .map(|(v, conn)| {
+                                    (v.into_iter().map(|v| v.0).collect::<Vec<_>>(), conn)
+                                }),
+                        )
+                    })
                 })
-                .map(|mut warehouse_products| warehouse_products.pop().map(|v| v.0))
                 .map_err(move |e| {
                     e.context(format!(
-                        "Failed to get product {} in warehouse {}",
-                        product_id.0, warehouse_id.0
+                        "Failed to archive warehouses of store {}",
+                        store_id.0
                     ))
                     .into()
                 }),
         )
     }
-    fn list_products_in_warehouse(&self, warehouse_id: WarehouseId) -> ServiceFuture<StockMap> {
-        let repo_factory = self.repo_factory.clone();
+
+    fn purge_store_warehouses(&self, store_id: StoreId) -> ServiceFuture<Vec<WarehouseRecord>> {
+        if let Err(e) = ensure_superadmin(&self.login) {
+            return Box::new(future::err(e));
+        }
+
+        let cached_warehouses = self.repo_factory.caches.warehouses.clone();
+        let actor = self.audit_actor();
         Box::new(
             self.db_pool
                 .run(move |conn| {
-                    (repo_factory.stocks_repo_factory)().select(
+                    audited_warehouse_change(
                         conn,
-                        StockFilter {
-                            warehouse_id: Some(warehouse_id.into()),
-                            ..Default::default()
+                        actor,
+                        AuditAction::Delete,
+                        no_warehouses,
+                        move |conn| {
+                            Box::new(
+                                repos::warehouses::make_su_repo()
+                                    .delete(
+                                        conn,
+                                        WarehouseFilter {
+                                            store_id: Some(store_id.into()),
+                                            ..Default::default()
+                                        },
+                                    )
+                                    .map(|(v, conn)| {
+                                        (v.into_iter().map(|v| v.0).collect::<Vec<_>>(), conn)
+                                    }),
+                            )
                         },
                     )
                 })
-                .map(|v| {
-                    v.into_iter()
-                        .map(|v| v.0)
-                        .map(<(ProductId, StockMeta)>::from)
-                        .collect::<StockMap>()
+                .map(move |data| {
+                    for v in &data {
+                        cached_warehouses.invalidate(&v.warehouse.id);
+                    }
+                    data
                 })
                 .map_err(move |e| {
                     e.context(format!(
-                        "Failed to list products in warehouse {}",
-                        warehouse_id.0
+                        "Failed to purge warehouses of store {}",
+                        store_id.0
                     ))
                     .into()
                 }),
         )
     }
-    fn find_by_product_id(&self, product_id: ProductId) -> ServiceFuture<Vec<Stock>> {
-        let repo_factory = self.repo_factory.clone();
+
+    fn delete_all_warehouses(&self) -> ServiceFuture<Vec<WarehouseRecord>> {
+        if !self.maintenance_mode {
+            return Box::new(future::err(
+                format_err!("Deleting all warehouses is only allowed in maintenance mode")
+                    .context(Error::Forbidden)
+                    .into(),
+            ));
+        }
+        if let Err(e) = ensure_superadmin(&self.login) {
+            return Box::new(future::err(e));
+        }
+
+        let cached_warehouses = self.repo_factory.caches.warehouses.clone();
+        let actor = self.audit_actor();
         Box::new(
             self.db_pool
                 .run(move |conn| {
-                    (repo_factory.stocks_repo_factory)().select(
+                    audited_warehouse_change(
                         conn,
-                        StockFilter {
-                            product_id: Some(product_id.into()),
-                            ..Default::default()
+                        actor,
+                        AuditAction::Delete,
+                        no_warehouses,
+                        move |conn| {
+                            Box::new(
+                                repos::warehouses::make_su_repo()
+                                    .delete(
+                                        conn,
+                                        WarehouseFilter {
+                                            ..Default::default()
+                                        },
+                                    )
+                                    .map(|(v, conn)| {
+                                        (v.into_iter().map(|v| v.0).collect::<Vec<_>>(), conn)
+                                    }),
+                            )
                         },
                     )
                 })
-                .map(|data| data.into_iter().map(|v| v.0).collect())
-                .map_err(move |e| {
-                    e.context(format!(
-                        "Failed to find warehouse products with product_id {}",
-                        product_id.0
-                    ))
-                    .into()
-                }),
+                .map(move |data| {
+                    cached_warehouses.clear();
+                    data
+                })
+                .map_err(|e| e.context("Failed to delete all warehouses").into()),
         )
     }
-    fn get_warehouse_product(&self, warehouse_product_id: StockId) -> ServiceFuture<Option<Stock>> {
-        let repo_factory = self.repo_factory.clone();
-        Box::new(
-            self.db_pool
-                .run(move |conn| {
-                    (repo_factory.stocks_repo_factory)().select(
-                        conn,
-                        StockFilter {
-                            id: Some(warehouse_product_id.into()),
-                            ..Default::default()
+
+    fn set_product_in_warehouse(
+        &self,
+        warehouse_id: WarehouseId,
+        product_id: ProductId,
+        quantity: Quantity,
+        expected_version: Option<Version>,
+    ) -> ServiceFuture<StockRecord> {
+        let repo_factory = self.repo_factory.clone();
+        let actor = self.audit_actor();
+        Box::new(
+            self.db_pool
+                .run({
+                    move |conn| {
+                        in_transaction(conn, move |conn| {
+                            Box::new(
+                                future::ok(conn)
+                                    .and_then({
+                                        let repo_factory = repo_factory.clone();
+                                        move |conn| {
+                                            let repo = (repo_factory.warehouse_repo_factory)();
+
+                                            repo.select(
+                                                conn,
+                                                WarehouseFilter {
+                                                    id: Some(warehouse_id.into()),
+                                                    ..Default::default()
+                                                },
+                                            )
+                                            .and_then(
+                                                move |(v, conn)| {
+                                                    if v.is_empty() {
+                                                        Err((
+                                                            format_err!(
+                                                                "Warehouse {} does not exist",
+                                                                warehouse_id
+                                                            )
+                                                            .context(Error::NotFound)
+                                                            .into(),
+                                                            conn,
+                                                        ))
+                                                    } else {
+                                                        Ok(conn)
+                                                    }
+                                                },
+                                            )
+                                        }
+                                    })
+                                    .and_then(move |conn| {
+                                        repos::stocks::lock_stock(conn, warehouse_id, product_id)
+                                    })
+                                    .and_then(move |(before, conn)| {
+                                        match ensure_version(
+                                            before.as_ref().map(|v| v.0.version),
+                                            expected_version,
+                                        ) {
+                                            Ok(()) => Ok((before, conn)),
+                                            Err(e) => Err((e, conn)),
+                                        }
+                                    })
+                                    .and_then({
+                                        let repo_factory = repo_factory.clone();
+                                        move |(before, conn)| {
+                                            let repo = (repo_factory.stocks_repo_factory)();
+
+                                            repo.insert_exactly_one(
+                                                conn,
+                                                DbStock(StockRecord::new(Stock {
+                                                    id: StockId::new(),
+                                                    warehouse_id,
+                                                    product_id,
+                                                    quantity,
+                                                })),
+                                            )
+                                            .map(move |(after, conn)| (before, after, conn))
+                                        }
+                                    })
+                                    .and_then(move |(before, after, conn)| {
+                                        let quantity_before = before
+                                            .as_ref()
+                                            .map(|v| v.0.stock.quantity)
+                                            .unwrap_or(Quantity(0));
+                                        ensure_capacity(conn, quantity_before, after)
+                                            .map(move |(after, conn)| (before, after, conn))
+                                    })
+                                    .and_then(move |(before, after, conn)| {
+                                        record_movement(
+                                            &repo_factory,
+                                            conn,
+                                            StockMovement::new(
+                                                &after.0.stock,
+                                                before
+                                                    .map(|v| v.0.stock.quantity)
+                                                    .unwrap_or(Quantity(0)),
+                                                actor,
+                                                StockMovementReason::Set,
+                                            ),
+                                        )
+                                        .map(move |((), conn)| (after, conn))
+                                    }),
+                            ) as RepoConnectionFuture<DbStock>
+                        })
+                    }
+                })
+                .map(|v| v.0)
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to set product {} in warehouse {} to quantity {}",
+                        product_id.0, warehouse_id.0, quantity.0
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn adjust_product_in_warehouse(
+        &self,
+        warehouse_id: WarehouseId,
+        product_id: ProductId,
+        delta: i32,
+    ) -> ServiceFuture<StockRecord> {
+        if delta == 0 {
+            return Box::new(future::err(
+                format_err!("Stock adjustment must not be zero")
+                    .context(Error::InvalidInput)
+                    .into(),
+            ));
+        }
+
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        let actor = self.audit_actor();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    in_transaction(conn, move |conn| {
+                        Box::new(
+                            authorize_stock_write(&repo_factory, &login, conn, warehouse_id)
+                                .and_then(move |(_warehouse, conn)| {
+                                    if delta > 0 {
+                                        Box::new(
+                                            repos::stocks::add_quantity(
+                                                conn,
+                                                warehouse_id,
+                                                product_id,
+                                                delta,
+                                            )
+                                            .and_then(move |(stock, conn)| {
+                                                let quantity_before =
+                                                    Quantity(stock.0.stock.quantity.0 - delta);
+                                                ensure_capacity(conn, quantity_before, stock)
+                                            }),
+                                        )
+                                            as RepoConnectionFuture<DbStock>
+                                    } else {
+                                        Box::new(
+                                            repos::stocks::adjust_unreserved_quantity(
+                                                conn,
+                                                warehouse_id,
+                                                product_id,
+                                                delta,
+                                            )
+                                            .and_then(move |(stock, conn)| match stock {
+                                                Some(stock) => Ok((stock, conn)),
+                                                None => Err((
+                                                    format_err!(
+                                                        "Removing {} units of product {} from warehouse {} would take units that are missing or reserved",
+                                                        i64::from(delta).abs(), product_id, warehouse_id
+                                                    )
+                                                    .context(Error::InsufficientStock)
+                                                    .into(),
+                                                    conn,
+                                                )),
+                                            }),
+                                        )
+                                    }
+                                })
+                                .and_then(move |(stock, conn)| {
+                                    record_movement(
+                                        &repo_factory,
+                                        conn,
+                                        StockMovement::new(
+                                            &stock.0.stock,
+                                            Quantity(stock.0.stock.quantity.0 - delta),
+                                            actor,
+                                            StockMovementReason::Adjustment,
+                                        ),
+                                    )
+                                    .map(move |((), conn)| (stock, conn))
+                                }),
+                        ) as RepoConnectionFuture<DbStock>
+                    })
+                })
+                .map(|v| v.0)
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to adjust product {} in warehouse {} by {}",
+                        product_id.0, warehouse_id.0, delta
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn bulk_set_products_in_warehouses(
+        &self,
+        lines: Vec<StockUpsertLine>,
+    ) -> ServiceFuture<Vec<StockUpsertResult>> {
+        if lines.len() > MAX_BULK_UPSERT_LINES {
+            return Box::new(future::err(
+                format_err!(
+                    "Bulk upsert accepts at most {} lines, got {}",
+                    MAX_BULK_UPSERT_LINES,
+                    lines.len()
+                )
+                .context(Error::InvalidInput)
+                .into(),
+            ));
+        }
+
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        let actor = self.audit_actor();
+        let line_count = lines.len();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    in_transaction(conn, move |conn| {
+                        let mut rejections = check_upsert_lines(&lines);
+                        let mut warehouse_ids = lines
+                            .iter()
+                            .zip(rejections.iter())
+                            .filter(|(_, rejection)| rejection.is_none())
+                            .map(|(line, _)| line.warehouse_id)
+                            .collect::<Vec<_>>();
+                        warehouse_ids.sort_by_key(|v| v.0);
+                        warehouse_ids.dedup();
+
+                        Box::new(
+                            // Authorize each warehouse once instead of once per line
+                            stream::iter_ok::<_, (failure::Error, RepoConnection)>(warehouse_ids)
+                                .fold((HashMap::new(), conn), {
+                                    let repo_factory = repo_factory.clone();
+                                    move |(mut verdicts, conn), warehouse_id| {
+                                        let login = login.clone();
+                                        (repo_factory.warehouse_repo_factory)()
+                                            .select(
+                                                conn,
+                                                WarehouseFilter {
+                                                    id: Some(warehouse_id.into()),
+                                                    ..Default::default()
+                                                },
+                                            )
+                                            .map(move |(mut v, conn)| {
+                                                let verdict = match v.pop() {
+                                                    None => Some(format!(
+                                                        "Warehouse {} does not exist",
+                                                        warehouse_id
+                                                    )),
+                                                    Some(DbWarehouse(record)) => {
+                                                        if repos::stocks::can_access_warehouse_stocks(
+                                                            &login,
+                                                            &record.warehouse,
+                                                            &Action::Update,
+                                                        ) {
+                                                            None
+                                                        } else {
+                                                            Some(format!(
+                                                                "Caller may not change stocks of warehouse {}",
+                                                                warehouse_id
+                                                            ))
+                                                        }
+                                                    }
+                                                };
+                                                verdicts.insert(warehouse_id, verdict);
+                                                (verdicts, conn)
+                                            })
+                                    }
+                                })
+                                .and_then(move |(verdicts, conn)| {
+                                    for (line, rejection) in lines.iter().zip(rejections.iter_mut()) {
+                                        if rejection.is_none() {
+                                            *rejection = verdicts[&line.warehouse_id].clone();
+                                        }
+                                    }
+
+                                    // Lock stocks in a stable order so that concurrent bulk upserts do not deadlock
+                                    let mut accepted = lines
+                                        .iter()
+                                        .cloned()
+                                        .enumerate()
+                                        .filter(|(i, _)| rejections[*i].is_none())
+                                        .collect::<Vec<_>>();
+                                    accepted.sort_by_key(|(_, line)| (line.warehouse_id.0, line.product_id.0));
+
+                                    stream::iter_ok::<_, (failure::Error, RepoConnection)>(accepted)
+                                        .fold((HashMap::new(), conn), move |(mut applied, conn), (i, line)| {
+                                            let repo_factory = repo_factory.clone();
+                                            repos::stocks::lock_stock(conn, line.warehouse_id, line.product_id)
+                                                .and_then(move |(before, conn)| write_stock(&repo_factory, conn, actor, line, before))
+                                                .map(move |(stock, conn)| {
+                                                    applied.insert(i, stock);
+                                                    (applied, conn)
+                                                })
+                                        })
+                                        .map(move |(mut applied, conn)| {
+                                            let results = lines
+                                                .into_iter()
+                                                .zip(rejections.into_iter())
+                                                .enumerate()
+                                                .map(|(i, (line, rejection))| match rejection {
+                                                    Some(reason) => StockUpsertResult::rejected(line, reason),
+                                                    None => StockUpsertResult::applied(
+                                                        line,
+                                                        applied.remove(&i).expect("Accepted line was not applied"),
+                                                    ),
+                                                })
+                                                .collect::<Vec<_>>();
+                                            (results, conn)
+                                        })
+                                }),
+                        ) as RepoConnectionFuture<Vec<StockUpsertResult>>
+                    })
+                })
+                .map_err(move |e| {
+                    e.context(format!("Failed to bulk upsert {} stock lines", line_count))
+                        .into()
+                }),
+        )
+    }
+    fn get_product_in_warehouse(
+        &self,
+        warehouse_id: WarehouseId,
+        product_id: ProductId,
+    ) -> ServiceFuture<Option<StockRecord>> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    (repo_factory.stocks_repo_factory)()
+                        .select(
+                            conn,
+                            StockFilter {
+                                warehouse_id: Some(warehouse_id.into()),
+                                product_id: Some(product_id.into()),
+                                ..Default::default()
+                            },
+                        )
+                        .and_then({
+                            let repo_factory = repo_factory.clone();
+                            move |(warehouse_products, conn)| {
+                                held_quantities_if_available(
+                                    &repo_factory,
+                                    conn,
+                                    warehouse_id,
+                                    Some(product_id),
+                                )
+                                .map(move |(held, conn)| {
+                                    (
+                                        available_stocks(
+                                            warehouse_products.into_iter().map(|v| v.0).collect(),
+                                            &held,
+                                        ),
+                                        conn,
+                                    )
+                                })
+                            }
+                        })
+                        .and_then(move |(stocks, conn)| {
+                            repos::stock_visibility::mask_stocks(&login, conn, stocks)
+                        })
+                        .map(|(mut stocks, conn)| (stocks.pop(), conn))
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to get product {} in warehouse {}",
+                        product_id.0, warehouse_id.0
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn list_products_in_warehouse(&self, warehouse_id: WarehouseId) -> ServiceFuture<StockMap> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    (repo_factory.stocks_repo_factory)()
+                        .select(
+                            conn,
+                            StockFilter {
+                                warehouse_id: Some(warehouse_id.into()),
+                                ..Default::default()
+                            },
+                        )
+                        .and_then({
+                            let repo_factory = repo_factory.clone();
+                            move |(v, conn)| {
+                                held_quantities_if_available(
+                                    &repo_factory,
+                                    conn,
+                                    warehouse_id,
+                                    None,
+                                )
+                                .map(move |(held, conn)| {
+                                    (
+                                        available_stocks(
+                                            v.into_iter().map(|v| v.0).collect(),
+                                            &held,
+                                        ),
+                                        conn,
+                                    )
+                                })
+                            }
+                        })
+                        .and_then(move |(stocks, conn)| {
+                            repos::stock_visibility::mask_stocks(&login, conn, stocks)
+                        })
+                        .map(|(stocks, conn)| {
+                            (
+                                stocks
+                                    .into_iter()
+                                    .map(|v| <(ProductId, StockMeta)>::from(v.stock))
+                                    .collect::<StockMap>(),
+                                conn,
+                            )
+                        })
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to list products in warehouse {}",
+                        warehouse_id.0
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn export_products_in_warehouse(
+        &self,
+        warehouse_id: WarehouseId,
+    ) -> ServiceFuture<Vec<StockCsvRecord>> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    (repo_factory.stocks_repo_factory)()
+                        .select(
+                            conn,
+                            StockFilter {
+                                warehouse_id: Some(warehouse_id.into()),
+                                ..Default::default()
+                            },
+                        )
+                        .and_then({
+                            let repo_factory = repo_factory.clone();
+                            move |(v, conn)| {
+                                held_quantities_if_available(
+                                    &repo_factory,
+                                    conn,
+                                    warehouse_id,
+                                    None,
+                                )
+                                .map(move |(held, conn)| {
+                                    let stocks = v.into_iter().map(|v| v.0).collect::<Vec<_>>();
+                                    let available = available_stocks(stocks.clone(), &held);
+                                    ((stocks, available), conn)
+                                })
+                            }
+                        })
+                        .and_then({
+                            let login = login.clone();
+                            move |((stocks, available), conn)| {
+                                repos::stock_visibility::mask_stocks(&login, conn, stocks)
+                                    .map(move |(stocks, conn)| ((stocks, available), conn))
+                            }
+                        })
+                        .and_then(move |((stocks, available), conn)| {
+                            repos::stock_visibility::mask_stocks(&login, conn, available)
+                                .map(move |(available, conn)| ((stocks, available), conn))
+                        })
+                        .map(|((stocks, available), conn)| {
+                            let mut records = stocks
+                                .into_iter()
+                                .zip(available.into_iter())
+                                .map(|(stock, available)| {
+                                    StockCsvRecord::new(stock, available.stock.quantity)
+                                })
+                                .collect::<Vec<_>>();
+                            records.sort_by_key(|v| v.product_id.0);
+                            (records, conn)
+                        })
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to export products in warehouse {}",
+                        warehouse_id.0
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn import_products_in_warehouse(
+        &self,
+        warehouse_id: WarehouseId,
+        rows: Vec<Result<StockCsvImportRow, String>>,
+        dry_run: bool,
+    ) -> ServiceFuture<StockImportReport> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        let actor = self.audit_actor();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    in_transaction(conn, move |conn| {
+                        Box::new(
+                            authorize_stock_write(&repo_factory, &login, conn, warehouse_id)
+                                .and_then(move |(_warehouse, conn)| {
+                                    let parsed = rows
+                                        .iter()
+                                        .enumerate()
+                                        .filter_map(|(i, row)| {
+                                            row.as_ref().ok().map(|row| {
+                                                (
+                                                    i,
+                                                    StockUpsertLine {
+                                                        warehouse_id,
+                                                        product_id: row.product_id,
+                                                        quantity: row.quantity,
+                                                    },
+                                                )
+                                            })
+                                        })
+                                        .collect::<Vec<_>>();
+                                    let lines = parsed
+                                        .iter()
+                                        .map(|(_, line)| line.clone())
+                                        .collect::<Vec<_>>();
+                                    let mut errors = rows
+                                        .iter()
+                                        .map(|row| row.as_ref().err().cloned())
+                                        .collect::<Vec<_>>();
+                                    for ((i, _), rejection) in
+                                        parsed.iter().zip(check_upsert_lines(&lines))
+                                    {
+                                        errors[*i] = rejection;
+                                    }
+
+                                    // Lock stocks in a stable order so that concurrent imports do not deadlock
+                                    let mut accepted = parsed
+                                        .into_iter()
+                                        .filter(|(i, _)| errors[*i].is_none())
+                                        .collect::<Vec<_>>();
+                                    accepted.sort_by_key(|(_, line)| line.product_id.0);
+
+                                    stream::iter_ok::<_, (failure::Error, RepoConnection)>(accepted)
+                                        .fold(
+                                            (HashMap::new(), conn),
+                                            move |(mut outcomes, conn), (i, line)| {
+                                                let repo_factory = repo_factory.clone();
+                                                repos::stocks::lock_stock(
+                                                    conn,
+                                                    warehouse_id,
+                                                    line.product_id,
+                                                )
+                                                .and_then(move |(before, conn)| {
+                                                    let quantity_before =
+                                                        before.as_ref().map(|v| v.0.stock.quantity);
+                                                    let outcome = match quantity_before {
+                                                        None => StockImportOutcome::Created,
+                                                        Some(quantity)
+                                                            if quantity != line.quantity =>
+                                                        {
+                                                            StockImportOutcome::Changed
+                                                        }
+                                                        Some(_) => StockImportOutcome::Unchanged,
+                                                    };
+                                                    outcomes.insert(i, (outcome, quantity_before));
+
+                                                    if dry_run
+                                                        || outcome == StockImportOutcome::Unchanged
+                                                    {
+                                                        Box::new(future::ok((outcomes, conn)))
+                                                            as RepoConnectionFuture<
+                                                                HashMap<
+                                                                    usize,
+                                                                    (
+                                                                        StockImportOutcome,
+                                                                        Option<Quantity>,
+                                                                    ),
+                                                                >,
+                                                            >
+                                                    } else {
+                                                        Box::new(
+                                                            write_stock(
+                                                                &repo_factory,
+                                                                conn,
+                                                                actor,
+                                                                line,
+                                                                before,
+                                                            )
+                                                            .map(move |(_, conn)| (outcomes, conn)),
+                                                        )
+                                                    }
+                                                })
+                                            },
+                                        )
+                                        .map(move |(mut outcomes, conn)| {
+                                            let rows = rows
+                                                .into_iter()
+                                                .zip(errors.into_iter())
+                                                .enumerate()
+                                                .map(|(i, (row, error))| {
+                                                    let (outcome, quantity_before) =
+                                                        outcomes.remove(&i).unwrap_or((
+                                                            StockImportOutcome::Rejected,
+                                                            None,
+                                                        ));
+                                                    let row = row.ok();
+                                                    StockImportRow {
+                                                        row: i + 1,
+                                                        product_id: row
+                                                            .as_ref()
+                                                            .map(|v| v.product_id),
+                                                        quantity: row.as_ref().map(|v| v.quantity),
+                                                        quantity_before,
+                                                        outcome,
+                                                        error,
+                                                    }
+                                                })
+                                                .collect();
+                                            (StockImportReport { dry_run, rows }, conn)
+                                        })
+                                }),
+                        ) as RepoConnectionFuture<StockImportReport>
+                    })
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to import products in warehouse {}",
+                        warehouse_id.0
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn find_by_product_id(&self, product_id: ProductId) -> ServiceFuture<Vec<StockRecord>> {
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    repos::stocks::stocks_of_product(conn, product_id).and_then(
+                        move |(data, conn)| {
+                            repos::stock_visibility::mask_stocks(
+                                &login,
+                                conn,
+                                data.into_iter().map(|v| v.0).collect(),
+                            )
                         },
                     )
                 })
-                .map(|mut v| v.pop().map(|v| v.0))
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to find warehouse products with product_id {}",
+                        product_id.0
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn get_warehouse_product(
+        &self,
+        warehouse_product_id: StockId,
+    ) -> ServiceFuture<Option<StockRecord>> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    (repo_factory.stocks_repo_factory)()
+                        .select(
+                            conn,
+                            StockFilter {
+                                id: Some(warehouse_product_id.into()),
+                                ..Default::default()
+                            },
+                        )
+                        .and_then(move |(v, conn)| {
+                            repos::stock_visibility::mask_stocks(
+                                &login,
+                                conn,
+                                v.into_iter().map(|v| v.0).collect(),
+                            )
+                        })
+                })
+                .map(|mut v| v.pop())
                 .map_err(move |e| {
                     e.context(format!(
                         "Failed to get warehouse product {}",
@@ -422,23 +1582,1378 @@ impl WarehouseService for WarehouseServiceImpl {
                 }),
         )
     }
-    fn find_products(&self) -> ServiceFuture<Vec<Stock>> {
+    fn find_products(&self, list_query: StockListQuery) -> ServiceFuture<Vec<StockRecord>> {
+        Box::new(self.page_products(list_query).map(|page| page.items))
+    }
+
+    fn page_products(&self, list_query: StockListQuery) -> ServiceFuture<StockPage> {
+        // Filtering or sorting by masked quantities would reveal the exact ones
+        if list_query.sort == StockSortField::Quantity
+            || list_query.min_quantity.is_some()
+            || list_query.max_quantity.is_some()
+        {
+            let manages_store = match (
+                list_query.store_id,
+                repos::warehouses::managed_store_ids(&self.login),
+            ) {
+                (_, None) => true,
+                (Some(store_id), Some(store_ids)) => store_ids.contains(&store_id),
+                (None, Some(_)) => false,
+            };
+            let manages_warehouses = match repos::warehouses::managed_warehouse_ids(&self.login) {
+                None => true,
+                Some(warehouse_ids) => {
+                    !list_query.warehouse_ids.is_empty()
+                        && list_query
+                            .warehouse_ids
+                            .iter()
+                            .all(|warehouse_id| warehouse_ids.contains(warehouse_id))
+                }
+            };
+            if !manages_store && !manages_warehouses {
+                return Box::new(future::err(
+                    format_err!(
+                        "Filtering and sorting by quantity needs store_id of a store or warehouse_ids of warehouses managed by the caller"
+                    )
+                    .context(Error::Forbidden)
+                    .into(),
+                ));
+            }
+        }
+
+        let page = list_query.page.normalized();
+        let sort = list_query.sort;
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run({
+                    let list_query = list_query.clone();
+                    // One extra row tells whether there is a next page
+                    move |conn| {
+                        repos::stocks::page_stocks(conn, list_query, page.count + 1).and_then(
+                            move |(v, conn)| {
+                                repos::stock_visibility::mask_stocks(
+                                    &login,
+                                    conn,
+                                    v.into_iter().map(|v| v.0).collect(),
+                                )
+                            },
+                        )
+                    }
+                })
+                .map(move |mut items| {
+                    let next_cursor = if items.len() as i64 > page.count {
+                        items.truncate(page.count as usize);
+                        items.last().map(|last| {
+                            StockCursor {
+                                key: sort.key(last),
+                                id: last.stock.id,
+                            }
+                            .to_string()
+                        })
+                    } else {
+                        None
+                    };
+                    StockPage { items, next_cursor }
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to find warehouse products with {:?}",
+                        list_query
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn hold_stock(&self, input: ReservationInput) -> ServiceFuture<Reservation> {
+        if input.quantity.0 <= 0 || input.ttl_seconds == 0 {
+            return Box::new(future::err(
+                format_err!("Invalid reservation input: {:?}", input)
+                    .context(Error::InvalidInput)
+                    .into(),
+            ));
+        }
+
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run({
+                    let input = input.clone();
+                    move |conn| {
+                        in_transaction(conn, move |conn| {
+                            let ReservationInput {
+                                warehouse_id,
+                                product_id,
+                                order_id,
+                                quantity,
+                                ttl_seconds,
+                            } = input;
+
+                            Box::new(
+                                ensure_warehouse_available(&repo_factory, conn, warehouse_id)
+                                    .and_then({
+                                        let repo_factory = repo_factory.clone();
+                                        move |((), conn)| {
+                                            // Authorize before looking at quantities, so that the outcome
+                                            // never reveals them to callers who do not manage the warehouse
+                                            authorize_stock_write(
+                                                &repo_factory,
+                                                &login,
+                                                conn,
+                                                warehouse_id,
+                                            )
+                                        }
+                                    })
+                                    .and_then(move |(_, conn)| {
+                                        repos::stocks::lock_stock(conn, warehouse_id, product_id)
+                                    })
+                                    .and_then(move |(stock, conn)| {
+                                        repos::reservations::held_quantities(
+                                            conn,
+                                            warehouse_id,
+                                            Some(product_id),
+                                        )
+                                        .map(move |(held, conn)| (stock, held, conn))
+                                    })
+                                    .and_then(move |(stock, held, conn)| {
+                                        let available = stock
+                                            .map(|v| available_stock(v.0, &held).stock.quantity.0)
+                                            .unwrap_or(0);
+                                        // The caller manages the warehouse at this point, so it may see the quantity
+                                        if available < quantity.0 {
+                                            return Box::new(future::err((
+                                                format_err!(
+                                                    "Only {} units of product {} are available in warehouse {}",
+                                                    available, product_id, warehouse_id
+                                                )
+                                                .context(Error::InsufficientStock)
+                                                .into(),
+                                                conn,
+                                            )))
+                                                as RepoConnectionFuture<DbReservation>;
+                                        }
+
+                                        let now = Utc::now();
+                                        (repo_factory.reservations_repo_factory)()
+                                            .insert_exactly_one(
+                                                conn,
+                                                DbReservation(Reservation {
+                                                    id: ReservationId::new(),
+                                                    warehouse_id,
+                                                    product_id,
+                                                    order_id,
+                                                    quantity,
+                                                    status: ReservationStatus::Held,
+                                                    expires_at: now
+                                                        + Duration::seconds(ttl_seconds.into()),
+                                                    created_at: now,
+                                                }),
+                                            )
+                                    }),
+                            ) as RepoConnectionFuture<DbReservation>
+                        })
+                    }
+                })
+                .map(|v| v.0)
+                .map_err(move |e| {
+                    e.context(format!("Failed to hold stock with data: {:?}", input))
+                        .into()
+                }),
+        )
+    }
+
+    fn get_reservation(&self, reservation_id: ReservationId) -> ServiceFuture<Option<Reservation>> {
         let repo_factory = self.repo_factory.clone();
         Box::new(
             self.db_pool
                 .run(move |conn| {
-                    (repo_factory.stocks_repo_factory)().select(
+                    (repo_factory.reservations_repo_factory)().select(
                         conn,
-                        StockFilter {
+                        ReservationFilter {
+                            id: Some(reservation_id.into()),
                             ..Default::default()
                         },
                     )
                 })
-                .map(|data| data.into_iter().map(|v| v.0).collect())
+                .map(|mut v| v.pop().map(|v| v.0))
                 .map_err(move |e| {
-                    e.context(format!("Failed to find warehouse products"))
+                    e.context(format!("Failed to get reservation {}", reservation_id))
                         .into()
                 }),
         )
     }
+
+    fn commit_reservation(&self, reservation_id: ReservationId) -> ServiceFuture<Reservation> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        let actor = self.audit_actor();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    in_transaction(conn, move |conn| {
+                        Box::new(
+                            authorize_reservation(&repo_factory, &login, conn, reservation_id)
+                                .and_then(move |((), conn)| {
+                                    lock_active_reservation(conn, reservation_id)
+                                })
+                                .and_then(move |(reservation, conn)| {
+                                    repos::stocks::adjust_quantity(
+                                        conn,
+                                        reservation.warehouse_id,
+                                        reservation.product_id,
+                                        -reservation.quantity.0,
+                                    )
+                                    .and_then(move |(stock, conn)| match stock {
+                                        Some(stock) => Ok((stock, reservation.quantity, conn)),
+                                        None => Err((
+                                            format_err!(
+                                                "Not enough units of product {} left in warehouse {}",
+                                                reservation.product_id, reservation.warehouse_id
+                                            )
+                                            .context(Error::InsufficientStock)
+                                            .into(),
+                                            conn,
+                                        )),
+                                    })
+                                })
+                                .and_then({
+                                    let repo_factory = repo_factory.clone();
+                                    move |(stock, taken, conn)| {
+                                        record_movement(
+                                            &repo_factory,
+                                            conn,
+                                            StockMovement::new(
+                                                &stock.0.stock,
+                                                Quantity(stock.0.stock.quantity.0 + taken.0),
+                                                actor,
+                                                StockMovementReason::ReservationCommit,
+                                            ),
+                                        )
+                                    }
+                                })
+                                .and_then(move |((), conn)| {
+                                    set_reservation_status(
+                                        &repo_factory,
+                                        conn,
+                                        reservation_id,
+                                        ReservationStatus::Committed,
+                                    )
+                                }),
+                        ) as RepoConnectionFuture<DbReservation>
+                    })
+                })
+                .map(|v| v.0)
+                .map_err(move |e| {
+                    e.context(format!("Failed to commit reservation {}", reservation_id))
+                        .into()
+                }),
+        )
+    }
+
+    fn release_reservation(&self, reservation_id: ReservationId) -> ServiceFuture<Reservation> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    in_transaction(conn, move |conn| {
+                        Box::new(
+                            authorize_reservation(&repo_factory, &login, conn, reservation_id)
+                                .and_then(move |((), conn)| {
+                                    repos::reservations::lock_reservation(conn, reservation_id)
+                                })
+                                .and_then(move |(reservation, conn)| match reservation {
+                                    Some(DbReservation(ref reservation))
+                                        if reservation.status == ReservationStatus::Held =>
+                                    {
+                                        Ok(conn)
+                                    }
+                                    Some(_) => Err((
+                                        format_err!(
+                                            "Reservation {} was already committed or released",
+                                            reservation_id
+                                        )
+                                        .context(Error::ReservationNotActive)
+                                        .into(),
+                                        conn,
+                                    )),
+                                    None => Err((
+                                        format_err!(
+                                            "Reservation {} does not exist",
+                                            reservation_id
+                                        )
+                                        .context(Error::NotFound)
+                                        .into(),
+                                        conn,
+                                    )),
+                                })
+                                .and_then(move |conn| {
+                                    set_reservation_status(
+                                        &repo_factory,
+                                        conn,
+                                        reservation_id,
+                                        ReservationStatus::Released,
+                                    )
+                                }),
+                        ) as RepoConnectionFuture<DbReservation>
+                    })
+                })
+                .map(|v| v.0)
+                .map_err(move |e| {
+                    e.context(format!("Failed to release reservation {}", reservation_id))
+                        .into()
+                }),
+        )
+    }
+
+    fn list_stock_movements(
+        &self,
+        subject: StockMovementSubject,
+        page: Page,
+    ) -> ServiceFuture<Vec<StockMovement>> {
+        let store_scope = repos::warehouses::managed_store_ids(&self.login);
+        let warehouse_scope = repos::warehouses::managed_warehouse_ids(&self.login);
+        let manages_nothing = match (&store_scope, &warehouse_scope) {
+            (Some(store_ids), Some(warehouse_ids)) => {
+                store_ids.is_empty() && warehouse_ids.is_empty()
+            }
+            _ => false,
+        };
+        if manages_nothing {
+            return Box::new(future::err(
+                format_err!("Only store and warehouse managers can read stock movements")
+                    .context(Error::Forbidden)
+                    .into(),
+            ));
+        }
+
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    repos::stock_movements::page_movements(
+                        conn,
+                        subject,
+                        store_scope,
+                        warehouse_scope,
+                        page,
+                    )
+                })
+                .map(|v| v.into_iter().map(|v| v.0).collect())
+                .map_err(move |e| {
+                    e.context(format!("Failed to list stock movements of {:?}", subject))
+                        .into()
+                }),
+        )
+    }
+
+    fn set_stock_reorder_threshold(
+        &self,
+        warehouse_id: WarehouseId,
+        product_id: ProductId,
+        reorder_threshold: Option<Quantity>,
+    ) -> ServiceFuture<Option<StockRecord>> {
+        if let Err(e) = validate_reorder_threshold(reorder_threshold) {
+            return Box::new(future::err(e));
+        }
+
+        let repo_factory = self.repo_factory.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    (repo_factory.stocks_repo_factory)().update(
+                        conn,
+                        StockUpdater {
+                            mask: StockFilter {
+                                warehouse_id: Some(warehouse_id.into()),
+                                product_id: Some(product_id.into()),
+                                ..Default::default()
+                            },
+                            data: StockUpdateData {
+                                reorder_threshold: Some(reorder_threshold.into()),
+                                ..Default::default()
+                            },
+                        },
+                    )
+                })
+                .map(|mut v| v.pop().map(|v| v.0))
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to set reorder threshold of product {} in warehouse {} to {:?}",
+                        product_id.0, warehouse_id.0, reorder_threshold
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn set_warehouse_reorder_threshold(
+        &self,
+        warehouse_id: WarehouseId,
+        default_reorder_threshold: Option<Quantity>,
+    ) -> ServiceFuture<Option<WarehouseRecord>> {
+        if let Err(e) = validate_reorder_threshold(default_reorder_threshold) {
+            return Box::new(future::err(e));
+        }
+
+        let repo_factory = self.repo_factory.clone();
+        let actor = self.audit_actor();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    let lock = move |conn| lock_warehouses(conn, warehouse_id);
+                    audited_warehouse_change(conn, actor, AuditAction::Update, lock, move |conn| {
+                        Box::new(
+                            (repo_factory.warehouse_repo_factory)()
+                                .update(
+                                    conn,
+                                    WarehouseUpdater {
+                                        mask: WarehouseFilter {
+                                            id: Some(warehouse_id.into()),
+                                            ..Default::default()
+                                        },
+                                        extra: WarehouseRecordUpdateData {
+                                            default_reorder_threshold: Some(
+                                                default_reorder_threshold.into(),
+                                            ),
+                                            ..Default::default()
+                                        },
+                                        ..Default::default()
+                                    },
+                                )
+                                .map(|(mut v, conn)| (v.pop().map(|v| v.0), conn)),
+                        )
+                    })
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to set default reorder threshold of warehouse {} to {:?}",
+                        warehouse_id.0, default_reorder_threshold
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn set_warehouse_capacity(
+        &self,
+        warehouse_id: WarehouseId,
+        capacity: WarehouseCapacity,
+    ) -> ServiceFuture<Option<WarehouseRecord>> {
+        if let Err(e) = capacity.validate() {
+            return Box::new(future::err(e));
+        }
+
+        let repo_factory = self.repo_factory.clone();
+        let actor = self.audit_actor();
+        Box::new(
+            self.db_pool
+                .run({
+                    let capacity = capacity.clone();
+                    move |conn| {
+                        let lock = move |conn| lock_warehouses(conn, warehouse_id);
+                        audited_warehouse_change(
+                            conn,
+                            actor,
+                            AuditAction::Update,
+                            lock,
+                            move |conn| {
+                                Box::new(
+                                    (repo_factory.warehouse_repo_factory)()
+                                        .update(
+                                            conn,
+                                            WarehouseUpdater {
+                                                mask: WarehouseFilter {
+                                                    id: Some(warehouse_id.into()),
+                                                    ..Default::default()
+                                                },
+                                                extra: WarehouseRecordUpdateData {
+                                                    capacity: Some(capacity.into()),
+                                                    ..Default::default()
+                                                },
+                                                ..Default::default()
+                                            },
+                                        )
+                                        .map(|(mut v, conn)| (v.pop().map(|v| v.0), conn)),
+                                )
+                            },
+                        )
+                    }
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to set capacity of warehouse {} to {:?}",
+                        warehouse_id.0, capacity
+                    ))
+                    .into()
+                }),
+        )
+    }
+    fn get_warehouse_utilization(
+        &self,
+        warehouse_id: WarehouseId,
+    ) -> ServiceFuture<Option<WarehouseUtilization>> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    // Utilization reveals exact quantities, so only managers of the stocks may see it
+                    authorize_stock_write(&repo_factory, &login, conn, warehouse_id).and_then(
+                        move |(_warehouse, conn)| {
+                            (repo_factory.warehouse_repo_factory)()
+                                .select(
+                                    conn,
+                                    WarehouseFilter {
+                                        id: Some(warehouse_id.into()),
+                                        ..Default::default()
+                                    },
+                                )
+                                .and_then(move |(mut v, conn)| match v.pop() {
+                                    None => Box::new(future::ok((None, conn)))
+                                        as RepoConnectionFuture<Option<WarehouseUtilization>>,
+                                    Some(DbWarehouse(warehouse)) => Box::new(
+                                        (repo_factory.stocks_repo_factory)()
+                                            .select(
+                                                conn,
+                                                StockFilter {
+                                                    warehouse_id: Some(warehouse_id.into()),
+                                                    ..Default::default()
+                                                },
+                                            )
+                                            .map(move |(stocks, conn)| {
+                                                let stocks = stocks
+                                                    .into_iter()
+                                                    .map(|v| v.0)
+                                                    .collect::<Vec<_>>();
+                                                (
+                                                    Some(WarehouseUtilization::new(
+                                                        warehouse_id,
+                                                        &warehouse.capacity,
+                                                        &stocks,
+                                                    )),
+                                                    conn,
+                                                )
+                                            }),
+                                    ),
+                                })
+                        },
+                    )
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to get utilization of warehouse {}",
+                        warehouse_id.0
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn list_low_stock(&self, store_id: StoreId) -> ServiceFuture<Vec<LowStock>> {
+        let warehouse_scope = match store_warehouse_scope(&self.login, store_id) {
+            Ok(warehouse_scope) => warehouse_scope,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        Box::new(
+            self.db_pool
+                .run(move |conn| repos::low_stock::low_stocks(conn, store_id, warehouse_scope))
+                .map_err(move |e| {
+                    e.context(format!("Failed to list low stocks of store {}", store_id))
+                        .into()
+                }),
+        )
+    }
+    fn list_low_stock_events(
+        &self,
+        store_id: StoreId,
+        page: Page,
+    ) -> ServiceFuture<Vec<LowStockEvent>> {
+        let warehouse_scope = match store_warehouse_scope(&self.login, store_id) {
+            Ok(warehouse_scope) => warehouse_scope,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    repos::low_stock::page_low_stock_events(conn, store_id, warehouse_scope, page)
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to list low stock events of store {}",
+                        store_id
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn get_stock_visibility(&self, store_id: StoreId) -> ServiceFuture<StoreStockVisibility> {
+        Box::new(
+            self.db_pool
+                .run(move |conn| repos::stock_visibility::store_visibility(conn, store_id))
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to get stock visibility of store {}",
+                        store_id
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn set_stock_visibility(
+        &self,
+        store_id: StoreId,
+        visibility: StockVisibility,
+    ) -> ServiceFuture<StoreStockVisibility> {
+        if let Err(e) =
+            ensure_manages_store(&self.login, store_id).and_then(|()| visibility.validate())
+        {
+            return Box::new(future::err(e));
+        }
+
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    repos::stock_visibility::set_store_visibility(conn, store_id, visibility)
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to set stock visibility of store {} to {:?}",
+                        store_id, visibility
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn set_warehouse_status(
+        &self,
+        warehouse_id: WarehouseId,
+        status: WarehouseStatus,
+    ) -> ServiceFuture<Option<WarehouseRecord>> {
+        let repo_factory = self.repo_factory.clone();
+        let actor = self.audit_actor();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    let lock = move |conn| lock_warehouses(conn, warehouse_id);
+                    audited_warehouse_change(conn, actor, AuditAction::Update, lock, move |conn| {
+                        Box::new(
+                            repos::warehouses::lock_warehouse(conn, warehouse_id).and_then(
+                                move |(current, conn)| {
+                                    let status_before = match current {
+                                        None => {
+                                            return Box::new(future::ok((None, conn)))
+                                                as RepoConnectionFuture<Option<DbWarehouse>>
+                                        }
+                                        Some(DbWarehouse(ref current))
+                                            if current.status == status =>
+                                        {
+                                            return Box::new(future::ok((
+                                                Some(DbWarehouse(current.clone())),
+                                                conn,
+                                            )));
+                                        }
+                                        Some(DbWarehouse(current)) => current.status,
+                                    };
+
+                                    Box::new(
+                                        (repo_factory.warehouse_repo_factory)()
+                                            .update_exactly_one(
+                                                conn,
+                                                WarehouseUpdater {
+                                                    mask: WarehouseFilter {
+                                                        id: Some(warehouse_id.into()),
+                                                        ..Default::default()
+                                                    },
+                                                    extra: WarehouseRecordUpdateData {
+                                                        status: Some(status.into()),
+                                                        ..Default::default()
+                                                    },
+                                                    ..Default::default()
+                                                },
+                                            )
+                                            .and_then(move |(updated, conn)| {
+                                                repos::warehouse_status::record_status_change(
+                                                    conn,
+                                                    WarehouseStatusChange {
+                                                        id: WarehouseStatusChangeId::new(),
+                                                        warehouse_id,
+                                                        status_before,
+                                                        status,
+                                                        changed_by: actor.actor_id,
+                                                        api_key_id: actor.api_key_id,
+                                                        changed_at: Utc::now(),
+                                                    },
+                                                )
+                                                .map(move |(change, conn)| {
+                                                    info!(
+                                                        "Warehouse {} status changed from {} to {} by {:?}",
+                                                        warehouse_id,
+                                                        change.status_before,
+                                                        change.status,
+                                                        actor
+                                                    );
+                                                    (Some(updated), conn)
+                                                })
+                                            }),
+                                    )
+                                },
+                            )
+                            .map(|(v, conn)| (v.map(|v| v.0), conn)),
+                        ) as RepoConnectionFuture<Option<WarehouseRecord>>
+                    })
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to set status of warehouse {} to {}",
+                        warehouse_id, status
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn list_warehouse_status_changes(
+        &self,
+        warehouse_id: WarehouseId,
+        page: Page,
+    ) -> ServiceFuture<Vec<WarehouseStatusChange>> {
+        let repo_factory = self.repo_factory.clone();
+        let login = self.login.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    (repo_factory.warehouse_repo_factory)()
+                        .select(
+                            conn,
+                            WarehouseFilter {
+                                id: Some(warehouse_id.into()),
+                                ..Default::default()
+                            },
+                        )
+                        .and_then(move |(mut v, conn)| {
+                            let result = match v.pop() {
+                                None => {
+                                    Err(format_err!("Warehouse {} does not exist", warehouse_id)
+                                        .context(Error::NotFound)
+                                        .into())
+                                }
+                                Some(DbWarehouse(warehouse)) => {
+                                    if repos::stocks::manages_warehouse_stocks(
+                                        &login,
+                                        warehouse_id,
+                                        warehouse.warehouse.store_id,
+                                    ) {
+                                        Ok(())
+                                    } else {
+                                        Err(format_err!(
+                                            "Caller does not manage warehouse {}",
+                                            warehouse_id
+                                        )
+                                        .context(Error::Forbidden)
+                                        .into())
+                                    }
+                                }
+                            };
+                            match result {
+                                Ok(()) => Box::new(repos::warehouse_status::page_status_changes(
+                                    conn,
+                                    warehouse_id,
+                                    page,
+                                ))
+                                    as RepoConnectionFuture<Vec<WarehouseStatusChange>>,
+                                Err(e) => Box::new(future::err((e, conn))),
+                            }
+                        })
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to list status changes of warehouse {}",
+                        warehouse_id
+                    ))
+                    .into()
+                }),
+        )
+    }
+
+    fn get_opening_status(
+        &self,
+        warehouse_id: WarehouseId,
+        at: DateTime<Utc>,
+    ) -> ServiceFuture<Option<OpeningStatus>> {
+        let repo_factory = self.repo_factory.clone();
+        Box::new(
+            self.db_pool
+                .run(move |conn| {
+                    (repo_factory.warehouse_repo_factory)().select(
+                        conn,
+                        WarehouseFilter {
+                            id: Some(warehouse_id.into()),
+                            ..Default::default()
+                        },
+                    )
+                })
+                .and_then(move |mut v| match v.pop() {
+                    None => Ok(None),
+                    Some(DbWarehouse(WarehouseRecord { schedule: None, .. })) => Err(format_err!(
+                        "Warehouse {} has no opening schedule",
+                        warehouse_id
+                    )
+                    .context(Error::NotFound)
+                    .into()),
+                    Some(DbWarehouse(WarehouseRecord {
+                        schedule: Some(schedule),
+                        ..
+                    })) => schedule.opening_status(at).map(Some),
+                })
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Failed to get opening status of warehouse {} at {}",
+                        warehouse_id, at
+                    ))
+                    .into()
+                }),
+        )
+    }
+}
+
+fn validate_reorder_threshold(threshold: Option<Quantity>) -> Result<(), failure::Error> {
+    match threshold {
+        Some(threshold) if threshold.0 < 0 => Err(format_err!(
+            "Reorder threshold must not be negative, got {}",
+            threshold
+        )
+        .context(Error::InvalidInput)
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Makes sure the caller manages the store, needed for store-wide queries that bypass repo ACL.
+pub fn ensure_manages_store(login: &UserLogin, store_id: StoreId) -> Result<(), failure::Error> {
+    match repos::warehouses::managed_store_ids(login) {
+        Some(ref store_ids) if !store_ids.contains(&store_id) => {
+            Err(format_err!("Caller does not manage store {}", store_id)
+                .context(Error::Forbidden)
+                .into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Warehouses of the store whose stocks the caller may read, `None` if the caller manages the whole store.
+fn store_warehouse_scope(
+    login: &UserLogin,
+    store_id: StoreId,
+) -> Result<Option<Vec<WarehouseId>>, failure::Error> {
+    if ensure_manages_store(login, store_id).is_ok() {
+        return Ok(None);
+    }
+
+    match repos::warehouses::managed_warehouse_ids(login) {
+        Some(ref warehouse_ids) if warehouse_ids.is_empty() => {
+            Err(format_err!("Caller does not manage store {}", store_id)
+                .context(Error::Forbidden)
+                .into())
+        }
+        warehouse_scope => Ok(warehouse_scope),
+    }
+}
+
+/// Checks that the caller may change stocks of the warehouse the reservation holds stock in,
+/// before anything about the reservation is revealed.
+fn authorize_reservation(
+    repo_factory: &RepoFactory,
+    login: &UserLogin,
+    conn: RepoConnection,
+    reservation_id: ReservationId,
+) -> RepoConnectionFuture<()> {
+    let repo_factory = repo_factory.clone();
+    let login = login.clone();
+    Box::new(
+        repos::reservations::find_reservation(conn, reservation_id).and_then(
+            move |(reservation, conn)| match reservation {
+                Some(DbReservation(reservation)) => Box::new(
+                    authorize_stock_write(&repo_factory, &login, conn, reservation.warehouse_id)
+                        .map(|(_, conn)| ((), conn)),
+                ) as RepoConnectionFuture<()>,
+                None => Box::new(future::err((
+                    format_err!("Reservation {} does not exist", reservation_id)
+                        .context(Error::NotFound)
+                        .into(),
+                    conn,
+                ))),
+            },
+        ),
+    )
+}
+
+/// Locks the reservation, failing unless it is held and not yet expired.
+fn lock_active_reservation(
+    conn: RepoConnection,
+    reservation_id: ReservationId,
+) -> RepoConnectionFuture<Reservation> {
+    Box::new(
+        repos::reservations::lock_reservation(conn, reservation_id).and_then(
+            move |(reservation, conn)| match reservation {
+                Some(DbReservation(reservation)) => {
+                    if reservation.is_active(Utc::now()) {
+                        Ok((reservation, conn))
+                    } else {
+                        Err((
+                            format_err!(
+                                "Reservation {} is {:?} and expires at {}",
+                                reservation_id,
+                                reservation.status,
+                                reservation.expires_at
+                            )
+                            .context(Error::ReservationNotActive)
+                            .into(),
+                            conn,
+                        ))
+                    }
+                }
+                None => Err((
+                    format_err!("Reservation {} does not exist", reservation_id)
+                        .context(Error::NotFound)
+                        .into(),
+                    conn,
+                )),
+            },
+        ),
+    )
+}
+
+fn set_reservation_status(
+    repo_factory: &RepoFactory,
+    conn: RepoConnection,
+    reservation_id: ReservationId,
+    status: ReservationStatus,
+) -> RepoConnectionFuture<DbReservation> {
+    (repo_factory.reservations_repo_factory)().update_exactly_one(
+        conn,
+        ReservationUpdater {
+            mask: ReservationFilter {
+                id: Some(reservation_id.into()),
+                ..Default::default()
+            },
+            data: ReservationUpdateData {
+                status: Some(status.into()),
+            },
+        },
+    )
+}
+
+/// Appends the movement to the ledger and emits a low stock event if it crossed the reorder threshold.
+pub fn record_movement(
+    repo_factory: &RepoFactory,
+    conn: RepoConnection,
+    movement: StockMovement,
+) -> RepoConnectionFuture<()> {
+    Box::new(
+        (repo_factory.stock_movements_repo_factory)()
+            .insert_exactly_one(conn, DbStockMovement(movement))
+            .and_then(|(movement, conn)| {
+                if movement.0.delta >= 0 {
+                    return Box::new(future::ok(((), conn))) as RepoConnectionFuture<()>;
+                }
+
+                Box::new(
+                    repos::low_stock::emit_low_stock_event(conn, &movement.0).map(
+                        |(event, conn)| {
+                            if let Some(event) = event {
+                                info!(
+                                    "Stock of product {} in warehouse {} is low: {} at threshold {}",
+                                    event.product_id, event.warehouse_id, event.quantity, event.threshold
+                                );
+                            }
+                            ((), conn)
+                        },
+                    ),
+                )
+            }),
+    )
+}
+
+/// Stores the absolute quantity of a previously locked stock and records the change in the ledger.
+/// Unlike repo methods this does not check ACL, so callers must authorize the change themselves.
+fn write_stock(
+    repo_factory: &RepoFactory,
+    conn: RepoConnection,
+    actor: AuditActor,
+    line: StockUpsertLine,
+    before: Option<DbStock>,
+) -> RepoConnectionFuture<StockRecord> {
+    let repo_factory = repo_factory.clone();
+    let quantity_before = before.map(|v| v.0.stock.quantity).unwrap_or(Quantity(0));
+    Box::new(
+        repos::stocks::make_su_repo()
+            .insert_exactly_one(
+                conn,
+                DbStock(StockRecord::new(Stock {
+                    id: StockId::new(),
+                    warehouse_id: line.warehouse_id,
+                    product_id: line.product_id,
+                    quantity: line.quantity,
+                })),
+            )
+            .and_then(move |(after, conn)| ensure_capacity(conn, quantity_before, after))
+            .and_then(move |(after, conn)| {
+                record_movement(
+                    &repo_factory,
+                    conn,
+                    StockMovement::new(
+                        &after.0.stock,
+                        quantity_before,
+                        actor,
+                        StockMovementReason::Set,
+                    ),
+                )
+                .map(move |((), conn)| (after.0, conn))
+            }),
+    )
+}
+
+/// Runs the change of warehouses in a transaction and records it in the audit log before committing.
+/// Warehouses returned by `lock` stay locked until then, so their recorded state is the one the change
+/// was applied to. Deleted warehouses are taken from the result of the change.
+fn audited_warehouse_change<T, L, F>(
+    conn: RepoConnection,
+    actor: AuditActor,
+    action: AuditAction,
+    lock: L,
+    change: F,
+) -> RepoConnectionFuture<T>
+where
+    T: Serialize + 'static,
+    L: FnOnce(RepoConnection) -> RepoConnectionFuture<Vec<DbWarehouse>> + 'static,
+    F: FnOnce(RepoConnection) -> RepoConnectionFuture<T> + 'static,
+{
+    in_transaction(conn, move |conn| {
+        Box::new(lock(conn).and_then(move |(locked, conn)| {
+            change(conn).and_then(move |(result, conn)| {
+                let locked = locked.into_iter().map(|v| v.0).collect::<Vec<_>>();
+                let (before, after) = match action {
+                    AuditAction::Create => (vec![], audit_values(&result)),
+                    AuditAction::Update => (audit_values(&locked), audit_values(&result)),
+                    AuditAction::Delete => (audit_values(&result), vec![]),
+                };
+                let entries = actor.entries(AuditEntityType::Warehouse, action, before, after);
+                repos::audit_log::insert_audit_entries(conn, entries)
+                    .map(move |((), conn)| (result, conn))
+            })
+        }))
+    })
+}
+
+/// For changes that do not need the state of warehouses before them.
+fn no_warehouses(conn: RepoConnection) -> RepoConnectionFuture<Vec<DbWarehouse>> {
+    Box::new(future::ok((vec![], conn)))
+}
+
+fn lock_warehouses(
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+) -> RepoConnectionFuture<Vec<DbWarehouse>> {
+    Box::new(
+        repos::warehouses::lock_warehouse(conn, warehouse_id)
+            .map(|(v, conn)| (v.into_iter().collect(), conn)),
+    )
+}
+
+/// Locks the warehouse with the identifier, which may be one of its former slugs.
+fn lock_identified_warehouse(
+    conn: RepoConnection,
+    warehouse_id: WarehouseIdentifier,
+) -> RepoConnectionFuture<Vec<DbWarehouse>> {
+    Box::new(
+        repos::warehouses::find_by_identifier(conn, warehouse_id).and_then(|(warehouse, conn)| {
+            match warehouse {
+                None => no_warehouses(conn),
+                Some(DbWarehouse(warehouse)) => lock_warehouses(conn, warehouse.warehouse.id),
+            }
+        }),
+    )
+}
+
+/// Archives the warehouse, or restores it when `archived_at` is `None`.
+/// Returns the warehouse as is if it already is in the requested state.
+fn set_archived_at(
+    repo_factory: &RepoFactory,
+    conn: RepoConnection,
+    warehouse_id: WarehouseIdentifier,
+    archived_at: Option<DateTime<Utc>>,
+) -> RepoConnectionFuture<Option<DbWarehouse>> {
+    let repo_factory = repo_factory.clone();
+    Box::new(
+        resolve_identifier(conn, warehouse_id)
+            .and_then({
+                let repo_factory = repo_factory.clone();
+                move |(warehouse_id, conn)| {
+                    let mut mask = WarehouseFilter::from(warehouse_id.clone());
+                    mask.archived = Some(archived_at.is_none().into());
+                    (repo_factory.warehouse_repo_factory)()
+                        .update(
+                            conn,
+                            WarehouseUpdater {
+                                mask,
+                                extra: WarehouseRecordUpdateData {
+                                    archived_at: Some(archived_at.into()),
+                                    ..Default::default()
+                                },
+                                ..Default::default()
+                            },
+                        )
+                        .map(move |(v, conn)| ((v, warehouse_id), conn))
+                }
+            })
+            .and_then(move |((mut v, warehouse_id), conn)| match v.pop() {
+                Some(updated) => Box::new(future::ok((Some(updated), conn)))
+                    as RepoConnectionFuture<Option<DbWarehouse>>,
+                None => Box::new(
+                    (repo_factory.warehouse_repo_factory)()
+                        .select(conn, warehouse_id.into())
+                        .map(|(mut v, conn)| (v.pop(), conn)),
+                ),
+            }),
+    )
+}
+
+/// Replaces a former slug with the id of the warehouse it used to point at.
+fn resolve_identifier(
+    conn: RepoConnection,
+    identifier: WarehouseIdentifier,
+) -> RepoConnectionFuture<WarehouseIdentifier> {
+    match identifier {
+        WarehouseIdentifier::Id(_) => Box::new(future::ok((identifier, conn))),
+        WarehouseIdentifier::Slug(_) => Box::new(
+            repos::warehouses::find_by_identifier(conn, identifier.clone()).map(
+                move |(warehouse, conn)| {
+                    (
+                        warehouse
+                            .map(|v| WarehouseIdentifier::Id(v.0.warehouse.id))
+                            .unwrap_or(identifier),
+                        conn,
+                    )
+                },
+            ),
+        ),
+    }
+}
+
+/// Fails with `SlugTaken` if the slug belongs, now or formerly, to a warehouse other than `owner`.
+/// Must run in a transaction, which holds the claim until it ends.
+fn claim_slug(
+    conn: RepoConnection,
+    slug: WarehouseSlug,
+    owner: Option<WarehouseId>,
+) -> RepoConnectionFuture<()> {
+    Box::new(
+        repos::warehouses::lock_slug(conn, &slug)
+            .and_then({
+                let slug = slug.clone();
+                move |((), conn)| {
+                    repos::warehouses::find_by_identifier(conn, WarehouseIdentifier::Slug(slug))
+                }
+            })
+            .and_then(move |(current, conn)| match current {
+                Some(ref current) if Some(current.0.warehouse.id) != owner => Err((
+                    format_err!("Slug {} is taken by another warehouse", slug.0)
+                        .context(Error::SlugTaken)
+                        .into(),
+                    conn,
+                )),
+                _ => Ok(((), conn)),
+            }),
+    )
+}
+
+/// Validates and claims the new slug of the warehouse, resolving to the slug it replaces if it changes.
+fn prepare_slug_change(
+    conn: RepoConnection,
+    warehouse_id: WarehouseIdentifier,
+    new_slug: Option<WarehouseSlug>,
+) -> RepoConnectionFuture<(WarehouseIdentifier, Option<WarehouseSlug>)> {
+    let (id, new_slug) = match (warehouse_id.clone(), new_slug) {
+        (WarehouseIdentifier::Id(id), Some(new_slug)) => (id, new_slug),
+        _ => return Box::new(future::ok(((warehouse_id, None), conn))),
+    };
+
+    Box::new(
+        repos::warehouses::lock_warehouse(conn, id).and_then(
+            move |(current, conn)| match current {
+                Some(DbWarehouse(current)) => {
+                    // Sending back the current slug is not a change, even if it is a generated one
+                    if current.warehouse.slug == new_slug {
+                        Box::new(future::ok(((warehouse_id, None), conn)))
+                            as RepoConnectionFuture<(WarehouseIdentifier, Option<WarehouseSlug>)>
+                    } else if let Err(e) = validate_slug(&new_slug) {
+                        Box::new(future::err((e, conn)))
+                    } else {
+                        let former_slug = current.warehouse.slug;
+                        Box::new(
+                            claim_slug(conn, new_slug, Some(id))
+                                .map(move |((), conn)| ((warehouse_id, Some(former_slug)), conn)),
+                        )
+                    }
+                }
+                None => Box::new(future::ok(((warehouse_id, None), conn))),
+            },
+        ),
+    )
+}
+
+/// Stocks of archived or not active warehouses are retained, but none of them can be promised.
+fn ensure_warehouse_available(
+    repo_factory: &RepoFactory,
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+) -> RepoConnectionFuture<()> {
+    Box::new(
+        (repo_factory.warehouse_repo_factory)()
+            .select(
+                conn,
+                WarehouseFilter {
+                    id: Some(warehouse_id.into()),
+                    ..Default::default()
+                },
+            )
+            .and_then(move |(mut v, conn)| match v.pop() {
+                Some(DbWarehouse(ref warehouse)) if warehouse.archived_at.is_some() => Err((
+                    format_err!("Warehouse {} is archived", warehouse_id)
+                        .context(Error::WarehouseUnavailable)
+                        .into(),
+                    conn,
+                )),
+                Some(DbWarehouse(ref warehouse)) if warehouse.status != WarehouseStatus::Active => {
+                    Err((
+                        format_err!("Warehouse {} is {}", warehouse_id, warehouse.status)
+                            .context(Error::WarehouseUnavailable)
+                            .into(),
+                        conn,
+                    ))
+                }
+                _ => Ok(((), conn)),
+            }),
+    )
+}
+
+/// Checks the written stock against the warehouse capacity if its quantity grew, passing it through.
+/// Every stock write that may increase a quantity must go through this.
+/// The warehouse is locked after the stock, so that concurrent writes of other products wait
+/// for each other and see each other's quantities.
+pub fn ensure_capacity(
+    conn: RepoConnection,
+    quantity_before: Quantity,
+    stock: DbStock,
+) -> RepoConnectionFuture<DbStock> {
+    let (warehouse_id, product_id, quantity) = (
+        stock.0.stock.warehouse_id,
+        stock.0.stock.product_id,
+        stock.0.stock.quantity,
+    );
+    if quantity.0 <= quantity_before.0 {
+        return Box::new(future::ok((stock, conn)));
+    }
+
+    Box::new(
+        repos::warehouses::lock_warehouse(conn, warehouse_id)
+            .and_then(move |(warehouse, conn)| {
+                repos::stocks::used_capacity(conn, warehouse_id, product_id)
+                    .map(move |(used, conn)| (warehouse, used, conn))
+            })
+            .and_then(move |(warehouse, used, conn)| {
+                let result = match warehouse {
+                    Some(DbWarehouse(warehouse)) => {
+                        warehouse.capacity.check(product_id, quantity, used)
+                    }
+                    None => Ok(()),
+                };
+                match result {
+                    Ok(()) => Ok((stock, conn)),
+                    Err(e) => Err((e, conn)),
+                }
+            }),
+    )
+}
+
+pub fn ensure_superadmin(login: &UserLogin) -> Result<(), failure::Error> {
+    match repos::warehouses::managed_store_ids(login) {
+        None => Ok(()),
+        Some(_) => Err(format_err!("Only superadmins are allowed to do this")
+            .context(Error::Forbidden)
+            .into()),
+    }
+}
+
+/// Fetches the warehouse and makes sure the caller may change its stocks.
+/// Needed for statements that bypass the ACL engine of the stocks repo.
+pub fn authorize_stock_write(
+    repo_factory: &RepoFactory,
+    login: &UserLogin,
+    conn: RepoConnection,
+    warehouse_id: WarehouseId,
+) -> RepoConnectionFuture<Warehouse> {
+    let login = login.clone();
+    Box::new(
+        (repo_factory.warehouse_repo_factory)()
+            .select(
+                conn,
+                WarehouseFilter {
+                    id: Some(warehouse_id.into()),
+                    ..Default::default()
+                },
+            )
+            .and_then(move |(mut v, conn)| match v.pop() {
+                None => Err((
+                    format_err!("Warehouse {} does not exist", warehouse_id)
+                        .context(Error::NotFound)
+                        .into(),
+                    conn,
+                )),
+                Some(DbWarehouse(WarehouseRecord { warehouse, .. })) => {
+                    if repos::stocks::can_access_warehouse_stocks(
+                        &login,
+                        &warehouse,
+                        &Action::Update,
+                    ) {
+                        Ok((warehouse, conn))
+                    } else {
+                        Err((
+                            format_err!(
+                                "Caller may not change stocks of warehouse {}",
+                                warehouse_id
+                            )
+                            .context(Error::Forbidden)
+                            .into(),
+                            conn,
+                        ))
+                    }
+                }
+            }),
+    )
+}
+
+/// Fails with `VersionConflict` unless the current version is the one the caller expects.
+/// A missing entity never matches an expected version.
+fn ensure_version(
+    current: Option<Version>,
+    expected: Option<Version>,
+) -> Result<(), failure::Error> {
+    match (current, expected) {
+        (_, None) => Ok(()),
+        (Some(current), Some(expected)) if current == expected => Ok(()),
+        (current, Some(expected)) => {
+            Err(
+                format_err!("Current version is {:?}, expected {}", current, expected)
+                    .context(Error::VersionConflict)
+                    .into(),
+            )
+        }
+    }
 }